@@ -0,0 +1,63 @@
+use crate::problem::Failure;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Body of `POST /api/analyses`.
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
+pub struct AnalysisRequest {
+    /// `github.com`, `gitlab.com`, `codeberg.org` or another forge.
+    pub host: String,
+    pub owner: String,
+    pub repository: String,
+    /// The default branch if missing.
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Directories or files to analyse, everything if empty.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub submodules: bool,
+    /// Url notified with a POST when the analysis finishes.
+    #[serde(default)]
+    pub callback: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalysisStatus {
+    Queued,
+    InProgress,
+    Done,
+    Failed,
+}
+
+/// Result of an earlier commit, returned while the current one is analysed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct PreviousResult {
+    pub date: DateTime<Utc>,
+    pub commit: String,
+    pub result: String,
+}
+
+/// Response of both analysis endpoints.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Analysis {
+    pub id: String,
+    pub status: AnalysisStatus,
+    pub host: String,
+    pub owner: String,
+    pub repository: String,
+    pub branch: String,
+    /// Output of the running clone.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<String>,
+    /// scc output once the analysis is done.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<PreviousResult>,
+    /// Why the analysis failed, with the `code` and `status` of the problem.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<Failure>,
+}
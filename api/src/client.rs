@@ -0,0 +1,379 @@
+use crate::{
+    analysis::{Analysis, AnalysisRequest, AnalysisStatus},
+    headers::{API_KEY_HEADER, TOKEN_HEADER, USERNAME_HEADER},
+    info::{Branches, Status},
+    problem::{Failure, ProblemDetails, PROBLEM_JSON},
+};
+use futures_util::{stream, SinkExt, Stream, StreamExt};
+use reqwest::{header::CONTENT_TYPE, RequestBuilder, Response};
+use serde::de::DeserializeOwned;
+use snafu::{ResultExt, Snafu};
+use std::time::Duration;
+use tokio::{
+    net::TcpStream,
+    time::{timeout_at, Instant},
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        http::{HeaderName, HeaderValue},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_PROGRESS_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Request to {url} failed: {source}"))]
+    Http { url: String, source: reqwest::Error },
+    #[snafu(display("{} {}: {}", problem.status, problem.code, problem.detail))]
+    Problem { problem: ProblemDetails },
+    #[snafu(display("Unexpected response {status} from {url}"))]
+    UnexpectedResponse { url: String, status: u16 },
+    #[snafu(display("Progress stream {url} failed: {source}"))]
+    WebSocket {
+        url: String,
+        source: tungstenite::Error,
+    },
+    #[snafu(display("Progress stream {url} didn't finish in time"))]
+    ProgressTimeout { url: String },
+    #[snafu(display("Can't parse progress message: {source}"))]
+    Message { source: serde_json::Error },
+    #[snafu(display("Analysis {id} failed: {}", failure.detail))]
+    AnalysisFailed { id: String, failure: Failure },
+}
+
+impl Error {
+    /// Stable code of the service's problem or of the failed analysis, such as
+    /// `repository_not_found`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Problem { problem } => Some(&problem.code),
+            Error::AnalysisFailed { failure, .. } => Some(&failure.code),
+            _ => None,
+        }
+    }
+}
+
+/// Async client of a running service.
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: String,
+    api_key: Option<String>,
+    credential: Option<(Option<String>, String)>,
+    interval: Duration,
+    progress_timeout: Duration,
+}
+
+impl Client {
+    /// `base_url` is where the service is served, such as `https://cloc.info`.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            api_key: None,
+            credential: None,
+            interval: DEFAULT_INTERVAL,
+            progress_timeout: DEFAULT_PROGRESS_TIMEOUT,
+        }
+    }
+
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Token for private repositories, sent with the forge's default username if `None`.
+    pub fn with_git_token(mut self, username: Option<String>, token: impl Into<String>) -> Self {
+        self.credential = Some((username, token.into()));
+        self
+    }
+
+    /// How often results are polled and progress is asked for, one second by default.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// How long [`Client::stream_progress`] waits for `Done` or `Error`, 30 minutes by default.
+    pub fn with_progress_timeout(mut self, timeout: Duration) -> Self {
+        self.progress_timeout = timeout;
+        self
+    }
+
+    /// API key and token headers, sent with every request and the progress handshake.
+    fn auth_headers(&self) -> Vec<(&'static str, &str)> {
+        let mut headers = Vec::with_capacity(3);
+        if let Some(api_key) = &self.api_key {
+            headers.push((API_KEY_HEADER, api_key.as_str()));
+        }
+        if let Some((username, token)) = &self.credential {
+            headers.push((TOKEN_HEADER, token.as_str()));
+            if let Some(username) = username {
+                headers.push((USERNAME_HEADER, username.as_str()));
+            }
+        }
+        headers
+    }
+
+    fn authorized(&self, mut request: RequestBuilder) -> RequestBuilder {
+        for (name, value) in self.auth_headers() {
+            request = request.header(name, value);
+        }
+        request
+    }
+
+    /// Starts an analysis; its `status` is already `done` when the result is known.
+    pub async fn request(&self, request: &AnalysisRequest) -> Result<Analysis, Error> {
+        let url = format!("{}/api/analyses", self.base_url);
+        let response = self
+            .authorized(self.http.post(&url).json(request))
+            .send()
+            .await
+            .context(HttpSnafu { url: &url })?;
+        json(url, response).await
+    }
+
+    /// Current state of an analysis started with [`Client::request`].
+    pub async fn poll(&self, id: &str) -> Result<Analysis, Error> {
+        let url = format!("{}/api/analyses/{id}", self.base_url);
+        let response = self
+            .authorized(self.http.get(&url))
+            .send()
+            .await
+            .context(HttpSnafu { url: &url })?;
+        json(url, response).await
+    }
+
+    /// Polls an analysis until it's done and returns the scc output.
+    pub async fn fetch_result(&self, id: &str) -> Result<String, Error> {
+        loop {
+            let analysis = self.poll(id).await?;
+            match analysis.status {
+                AnalysisStatus::Done => return Ok(analysis.result.unwrap_or_default()),
+                AnalysisStatus::Failed => {
+                    return Err(Error::AnalysisFailed {
+                        id: analysis.id,
+                        failure: analysis.error.unwrap_or_else(|| Failure {
+                            code: "internal_error".to_string(),
+                            status: 500,
+                            detail: String::new(),
+                        }),
+                    })
+                }
+                AnalysisStatus::Queued | AnalysisStatus::InProgress => {
+                    tokio::time::sleep(self.interval).await
+                }
+            }
+        }
+    }
+
+    /// Requests an analysis and waits for its scc output.
+    pub async fn analyze(&self, request: &AnalysisRequest) -> Result<String, Error> {
+        let analysis = self.request(request).await?;
+        match analysis.status {
+            AnalysisStatus::Done => Ok(analysis.result.unwrap_or_default()),
+            _ => self.fetch_result(&analysis.id).await,
+        }
+    }
+
+    /// Branches and tags of a repository, looked up without cloning it.
+    pub async fn branches(
+        &self,
+        host: &str,
+        owner: &str,
+        repository: &str,
+    ) -> Result<Branches, Error> {
+        let url = format!("{}/api/{host}/{owner}/{repository}/branches", self.base_url);
+        let response = self
+            .authorized(self.http.get(&url))
+            .send()
+            .await
+            .context(HttpSnafu { url: &url })?;
+        json(url, response).await
+    }
+
+    /// Progress of the analysis of `request`, which has to be requested first with the same
+    /// credentials, otherwise it stays `Ready`. The stream ends after `Done` or `Error`, or
+    /// with [`Error::ProgressTimeout`] after the progress timeout.
+    pub async fn stream_progress(
+        &self,
+        request: &AnalysisRequest,
+    ) -> Result<impl Stream<Item = Result<Status, Error>>, Error> {
+        let url = self.progress_url(request);
+        let mut handshake = url
+            .as_str()
+            .into_client_request()
+            .map_err(|source| websocket_error(&url, source))?;
+        for (name, value) in self.auth_headers() {
+            let name = HeaderName::try_from(name).map_err(tungstenite::Error::from);
+            let value = HeaderValue::from_str(value).map_err(tungstenite::Error::from);
+            match (name, value) {
+                (Ok(name), Ok(value)) => handshake.headers_mut().insert(name, value),
+                (Err(source), _) | (_, Err(source)) => return Err(websocket_error(&url, source)),
+            };
+        }
+        let (socket, _response) = connect_async(handshake)
+            .await
+            .map_err(|source| websocket_error(&url, source))?;
+
+        let interval = self.interval;
+        let deadline = Instant::now() + self.progress_timeout;
+        Ok(stream::unfold(
+            Some((socket, url, true)),
+            move |state| async move {
+                let (mut socket, url, first) = state?;
+                let next = async {
+                    if !first {
+                        tokio::time::sleep(interval).await;
+                    }
+                    next_status(&mut socket, &url).await
+                };
+                match timeout_at(deadline, next).await {
+                    Ok(Ok(Some(status))) => {
+                        let finished = matches!(status, Status::Done(_) | Status::Error(_));
+                        let state = (!finished).then_some((socket, url, false));
+                        Some((Ok(status), state))
+                    }
+                    Ok(Ok(None)) => None,
+                    Ok(Err(error)) => Some((Err(error), None)),
+                    Err(_) => Some((Err(Error::ProgressTimeout { url }), None)),
+                }
+            },
+        ))
+    }
+
+    fn progress_url(&self, request: &AnalysisRequest) -> String {
+        let base_url = match self.base_url.strip_prefix("http") {
+            Some(rest) => format!("ws{rest}"),
+            None => self.base_url.clone(),
+        };
+        let mut url = format!(
+            "{base_url}/ws/{}/{}/{}",
+            request.host, request.owner, request.repository
+        );
+        if let Some(branch) = &request.branch {
+            url = format!("{url}/tree/{branch}");
+        }
+
+        let mut query = Vec::with_capacity(2);
+        if !request.paths.is_empty() {
+            query.push(format!("paths={}", request.paths.join(",")));
+        }
+        if request.submodules {
+            query.push("submodules=true".to_string());
+        }
+        if !query.is_empty() {
+            url = format!("{url}?{}", query.join("&"));
+        }
+        url
+    }
+}
+
+/// Body of a successful response, otherwise the service's problem.
+async fn json<T: DeserializeOwned>(url: String, response: Response) -> Result<T, Error> {
+    let status = response.status();
+    if status.is_success() {
+        return response.json().await.context(HttpSnafu { url });
+    }
+
+    let is_problem = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(PROBLEM_JSON));
+    if is_problem {
+        if let Ok(problem) = response.json().await {
+            return Err(Error::Problem { problem });
+        }
+    }
+    Err(Error::UnexpectedResponse {
+        url,
+        status: status.as_u16(),
+    })
+}
+
+/// The service answers the handshake with a problem when the repository can't be looked up.
+fn websocket_error(url: &str, source: tungstenite::Error) -> Error {
+    if let tungstenite::Error::Http(response) = &source {
+        if let Some(problem) = response
+            .body()
+            .as_deref()
+            .and_then(|body| serde_json::from_slice(body).ok())
+        {
+            return Error::Problem { problem };
+        }
+    }
+    Error::WebSocket {
+        url: url.to_string(),
+        source,
+    }
+}
+
+/// Every message sent is answered with the current status.
+async fn next_status(socket: &mut Socket, url: &str) -> Result<Option<Status>, Error> {
+    socket
+        .send(Message::Text("status".to_string()))
+        .await
+        .context(WebSocketSnafu { url })?;
+    while let Some(message) = socket.next().await {
+        match message.context(WebSocketSnafu { url })? {
+            Message::Text(text) => {
+                return serde_json::from_str(&text).map(Some).context(MessageSnafu)
+            }
+            Message::Close(_) => return Ok(None),
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Client;
+    use crate::{
+        analysis::AnalysisRequest,
+        headers::{API_KEY_HEADER, TOKEN_HEADER, USERNAME_HEADER},
+    };
+
+    #[test]
+    fn progress_handshake_carries_the_credentials() {
+        let client = Client::new("https://cloc.info/")
+            .with_api_key("cloc_key")
+            .with_git_token(None, "secret");
+        let request = AnalysisRequest {
+            host: "github.com".to_string(),
+            owner: "org".to_string(),
+            repository: "repo".to_string(),
+            branch: Some("feature/x".to_string()),
+            paths: vec!["src".to_string(), "lib".to_string()],
+            submodules: true,
+            callback: None,
+        };
+
+        let url = client.progress_url(&request);
+        assert_eq!(
+            url,
+            "wss://cloc.info/ws/github.com/org/repo/tree/feature/x?paths=src,lib&submodules=true"
+        );
+        assert_eq!(
+            client.auth_headers(),
+            [(API_KEY_HEADER, "cloc_key"), (TOKEN_HEADER, "secret")]
+        );
+        let client = client.with_git_token(Some("bot".to_string()), "secret");
+        assert_eq!(client.auth_headers()[2], (USERNAME_HEADER, "bot"));
+    }
+}
@@ -0,0 +1,6 @@
+/// Header with the API key, `Authorization: Bearer <key>` is accepted as well.
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+/// Header with an access token for a private repository.
+pub const TOKEN_HEADER: &str = "X-Git-Token";
+/// Optional header with the user name the token belongs to.
+pub const USERNAME_HEADER: &str = "X-Git-Username";
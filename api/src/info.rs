@@ -0,0 +1,338 @@
+use crate::problem::Failure;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{cmp::Ordering, fmt::Display};
+#[cfg(feature = "postgres")]
+use tokio_postgres::Row;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+pub struct BranchValue {
+    pub name: String,
+    pub commit: String,
+}
+
+/// Tag with the commit it points at; for annotated tags `object` is the tag object itself.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema)]
+pub struct TagValue {
+    pub name: String,
+    pub object: String,
+    pub commit: String,
+}
+
+#[derive(
+    Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, ToSchema,
+)]
+pub struct Branches {
+    /// Empty for bundles without `HEAD` and empty repositories.
+    pub default_branch: String,
+    pub branches: Vec<BranchValue>,
+    #[serde(default)]
+    pub tags: Vec<TagValue>,
+}
+
+/// State of an analysis, also the messages of the progress streams. `Previous` and `Done`
+/// carry the scc output as bytes.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub enum Status {
+    InProgress(String),
+    Cloned,
+    Previous {
+        date: DateTime<Utc>,
+        commit: String,
+        data: Vec<u8>,
+    },
+    Done(Vec<u8>),
+    Ready,
+    Error(Failure),
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Done(_) => write!(f, "Done"),
+            Status::InProgress(text) => write!(f, "{}", text),
+            Status::Cloned => write!(f, "Cloned"),
+            Status::Ready => write!(f, "Ready"),
+            Status::Error(failure) => write!(f, "Error: {}", failure.detail),
+            Status::Previous { .. } => write!(f, "Previous"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct PopularBranch {
+    pub branch_name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct PopularRepository {
+    pub hostname: String,
+    pub owner: String,
+    pub repository_name: String,
+    pub total_count: i64,
+    pub branches: Vec<PopularBranch>,
+}
+
+impl PartialOrd for PopularRepository {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PopularRepository {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.total_count.cmp(&self.total_count)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PopularRepositories {
+    pub repositories: Vec<PopularRepository>,
+}
+
+impl PopularRepositories {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(
+        &mut self,
+        hostname: String,
+        owner: String,
+        repository_name: String,
+        branch: String,
+        count: i64,
+    ) {
+        let branch = PopularBranch {
+            branch_name: branch,
+            count,
+        };
+        if let Some(repository) = self
+            .repositories
+            .iter_mut()
+            .find(|r| r.repository_name == repository_name)
+        {
+            repository.total_count += count;
+
+            repository.branches.push(branch);
+        } else {
+            let total_count = branch.count;
+            let branches = vec![branch];
+            let repository = PopularRepository {
+                hostname,
+                owner,
+                repository_name,
+                total_count,
+                branches,
+            };
+            self.repositories.push(repository)
+        }
+        self.repositories.sort();
+    }
+
+    pub fn top(&self, limit: usize) -> Vec<PopularRepository> {
+        self.repositories.iter().take(limit).cloned().collect()
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<Vec<Row>> for PopularRepositories {
+    fn from(rows: Vec<Row>) -> Self {
+        let mut repositories = Self::default();
+
+        for row in rows {
+            let hostname: String = row.get("hostname");
+            let owner: String = row.get("owner");
+            let repository_name: String = row.get("repository_name");
+            let branch: String = row.get("name");
+            let count: i64 = row.get("count");
+            repositories.push(hostname, owner, repository_name, branch, count);
+        }
+        repositories
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct LargestBranch {
+    pub branch_name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct LargestRepository {
+    pub hostname: String,
+    pub owner: String,
+    pub repository_name: String,
+    pub size: u64,
+    pub branches: Vec<LargestBranch>,
+}
+
+impl PartialOrd for LargestRepository {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LargestRepository {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.size.cmp(&self.size)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct LargestRepositories {
+    repositories: Vec<LargestRepository>,
+}
+
+impl LargestRepositories {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn push(
+        &mut self,
+        hostname: String,
+        owner: String,
+        repository_name: String,
+        branch: String,
+        size: u64,
+    ) {
+        let branch = LargestBranch {
+            branch_name: branch,
+            size,
+        };
+        if let Some(repository) = self
+            .repositories
+            .iter_mut()
+            .find(|r| r.repository_name == repository_name)
+        {
+            repository.size = repository.size.max(size);
+            repository.branches.push(branch);
+        } else {
+            let branches = vec![branch];
+            let repository = LargestRepository {
+                hostname,
+                owner,
+                repository_name,
+                size,
+                branches,
+            };
+            self.repositories.push(repository);
+            self.repositories.sort();
+        }
+    }
+
+    pub fn top(&self, limit: usize) -> Vec<LargestRepository> {
+        self.repositories.iter().take(limit).cloned().collect()
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<Vec<Row>> for LargestRepositories {
+    fn from(rows: Vec<Row>) -> Self {
+        let mut repositories = Self::default();
+
+        for row in rows {
+            let hostname: String = row.get("hostname");
+            let owner: String = row.get("owner");
+            let repository_name: String = row.get("repository_name");
+            let branch: String = row.get("name");
+            let size = row.get::<&str, i64>("size") as u64;
+            repositories.push(hostname, owner, repository_name, branch, size);
+        }
+        repositories
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct RecentBranch {
+    pub branch_name: String,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct RecentRepository {
+    pub hostname: String,
+    pub owner: String,
+    pub repository_name: String,
+    pub time: DateTime<Utc>,
+    pub branches: Vec<RecentBranch>,
+}
+
+impl PartialOrd for RecentRepository {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RecentRepository {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.cmp(&self.time)
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct RecentRepositories {
+    repositories: Vec<RecentRepository>,
+}
+
+impl RecentRepositories {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn push(
+        &mut self,
+        hostname: String,
+        owner: String,
+        repository_name: String,
+        branch: String,
+        time: DateTime<Utc>,
+    ) {
+        let branch = RecentBranch {
+            branch_name: branch,
+            time,
+        };
+        if let Some(repository) = self
+            .repositories
+            .iter_mut()
+            .find(|r| r.repository_name == repository_name)
+        {
+            repository.time = repository.time.max(time);
+            repository.branches.push(branch);
+        } else {
+            let branches = vec![branch];
+            let repository = RecentRepository {
+                hostname,
+                owner,
+                repository_name,
+                time,
+                branches,
+            };
+            self.repositories.push(repository);
+            self.repositories.sort();
+        }
+    }
+
+    pub fn top(&self, limit: usize) -> Vec<RecentRepository> {
+        self.repositories.iter().take(limit).cloned().collect()
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl From<Vec<Row>> for RecentRepositories {
+    fn from(rows: Vec<Row>) -> Self {
+        let mut repositories: RecentRepositories = RecentRepositories::new();
+
+        for row in rows {
+            let hostname: String = row.get("hostname");
+            let owner: String = row.get("owner");
+            let repository_name: String = row.get("repository_name");
+            let branch: String = row.get("name");
+            let time: DateTime<Utc> = row.get("time");
+            repositories.push(hostname, owner, repository_name, branch, time);
+        }
+        repositories
+    }
+}
@@ -0,0 +1,9 @@
+//! Types and async client of the Count lines of Code service.
+
+pub mod analysis;
+pub mod client;
+pub mod headers;
+pub mod info;
+pub mod problem;
+
+pub use client::{Client, Error};
@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// Media type of RFC 7807 error bodies.
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Body of a problem response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ProblemDetails {
+    /// Always `about:blank`, `code` tells problems apart.
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable, machine readable code such as `repository_not_found`.
+    pub code: String,
+    pub request_id: Option<String>,
+}
+
+/// Why an analysis failed, reported as a problem with this `code` and `status`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Failure {
+    pub code: String,
+    pub status: u16,
+    pub detail: String,
+}
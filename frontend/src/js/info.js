@@ -95,7 +95,8 @@ function extractContent(response, error_msg) {
     let msg = error_msg ? error_msg + ":\n" : ""
     const contentType = response.headers.get("content-type");
     let result = new Reply(response.status)
-    if (contentType && contentType.indexOf("application/json") !== -1) {
+    // Errors come as application/problem+json
+    if (contentType && /application\/(problem\+)?json/.test(contentType)) {
         return response.json().then(data => {
             result.setJsonData(data)
             return result
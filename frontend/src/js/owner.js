@@ -0,0 +1,70 @@
+import { buildRepositoryPath, createTableHead, createTableRow, documentGetElementById } from "./common";
+
+const OWNER_DIV = documentGetElementById("owner")
+const WARNING_DIV = documentGetElementById("warning")
+const LANGUAGES_DIV = documentGetElementById("languages")
+const REPOSITORIES_DIV = documentGetElementById("repositories")
+
+// The page is served at /:host/:owner, the report at /api/:host/:owner
+const [hostname, owner] = window.location.pathname.split("/").filter(Boolean)
+
+function escapeHtml(str) {
+    const div = document.createElement("div")
+    div.textContent = str
+    return div.innerHTML
+}
+
+function showWarning(text) {
+    WARNING_DIV.textContent = text
+    WARNING_DIV.classList.remove("hidden")
+}
+
+function createLanguagesTable(report) {
+    let table = '<table class="table-auto w-full">'
+    table += createTableHead(["Language", "Files", "Lines", "Comments", "Code"])
+    table += "<tbody>"
+    for (const stat of [...report.languages, report.total]) {
+        table += createTableRow([escapeHtml(stat.language), stat.files, stat.lines, stat.comments, stat.code])
+    }
+    table += "</tbody></table>"
+    LANGUAGES_DIV.innerHTML = table
+    LANGUAGES_DIV.classList.remove("hidden")
+}
+
+function createRepositoriesTable(report) {
+    let table = '<table class="table-auto w-full">'
+    table += createTableHead(["Repository", "Size", "Status", "Code"])
+    table += "<tbody>"
+    for (const repository of report.repositories) {
+        const name = escapeHtml(repository.repository_name)
+        const path = buildRepositoryPath(report.hostname, report.owner, repository.repository_name)
+        const link = '<a class="underline" href="/' + encodeURI(path) + '">' + name + '</a>'
+        const code = repository.code === null ? "-" : repository.code
+        table += createTableRow([link, repository.size, repository.status.replace("_", " "), code])
+    }
+    table += "</tbody></table>"
+    REPOSITORIES_DIV.innerHTML = table
+    REPOSITORIES_DIV.classList.remove("hidden")
+}
+
+async function showReport() {
+    OWNER_DIV.textContent = hostname + "/" + owner
+    const response = await fetch("/api/" + hostname + "/" + owner)
+    const data = await response.json()
+    if (!response.ok) {
+        showWarning(data.detail || data.title || "Error at fetching the report")
+        return
+    }
+    if (data.repositories.length === 0) {
+        showWarning("No public repositories")
+        return
+    }
+    if (data.repositories.every((repository) => repository.code === null)) {
+        showWarning("None of the repositories were analysed yet, open one to analyse it")
+    } else {
+        createLanguagesTable(data)
+    }
+    createRepositoriesTable(data)
+}
+
+showReport()
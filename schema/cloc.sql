@@ -47,7 +47,9 @@ CREATE TABLE public.branches (
     name text NOT NULL,
     last_commit_sha text NOT NULL,
     scc_output bytea,
-    size bigint
+    size bigint,
+    clone_options text DEFAULT ''::text NOT NULL,
+    blob_limit bigint
 );
 
 
@@ -63,7 +65,8 @@ CREATE VIEW public.branches_view AS
     branches.name,
     branches.last_commit_sha,
     branches.size
-   FROM public.branches;
+   FROM public.branches
+  WHERE (branches.clone_options = ''::text);
 
 
 ALTER TABLE public.branches_view OWNER TO postgres;
@@ -82,6 +85,67 @@ CREATE TABLE public.statistic (
 
 ALTER TABLE public.statistic OWNER TO postgres;
 
+--
+-- Name: uploads; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.uploads (
+    id text NOT NULL,
+    content_hash text,
+    scc_output bytea NOT NULL,
+    branch text,
+    commit text,
+    created timestamp with time zone DEFAULT now() NOT NULL,
+    expires timestamp with time zone NOT NULL
+);
+
+
+ALTER TABLE public.uploads OWNER TO postgres;
+
+--
+-- Name: branch_cache; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.branch_cache (
+    url text NOT NULL,
+    branches text NOT NULL,
+    updated timestamp with time zone DEFAULT now() NOT NULL
+);
+
+
+ALTER TABLE public.branch_cache OWNER TO postgres;
+
+--
+-- Name: api_keys; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.api_keys (
+    id bigint NOT NULL,
+    name text NOT NULL,
+    key_hash text NOT NULL,
+    analyses_per_hour integer,
+    max_bytes bigint,
+    created timestamp with time zone DEFAULT now() NOT NULL,
+    revoked timestamp with time zone
+);
+
+
+ALTER TABLE public.api_keys OWNER TO postgres;
+
+--
+-- Name: api_key_usage; Type: TABLE; Schema: public; Owner: postgres
+--
+
+CREATE TABLE public.api_key_usage (
+    id bigint NOT NULL,
+    api_key_id bigint NOT NULL,
+    "time" timestamp with time zone DEFAULT now() NOT NULL,
+    bytes bigint DEFAULT 0 NOT NULL
+);
+
+
+ALTER TABLE public.api_key_usage OWNER TO postgres;
+
 --
 -- Name: recently_branches_view; Type: VIEW; Schema: public; Owner: postgres
 --
@@ -104,7 +168,9 @@ CREATE TABLE public.repositories (
     hostname text NOT NULL,
     owner text NOT NULL,
     repository_name text NOT NULL,
-    default_branch text NOT NULL
+    default_branch text NOT NULL,
+    watched boolean DEFAULT false NOT NULL,
+    private boolean DEFAULT false NOT NULL
 );
 
 
@@ -124,7 +190,8 @@ CREATE VIEW public.repositories_view AS
     branches_view.last_commit_sha,
     branches_view.size
    FROM (public.repositories
-     JOIN public.branches_view ON ((repositories.id = branches_view.repository_id)));
+     JOIN public.branches_view ON ((repositories.id = branches_view.repository_id)))
+  WHERE (NOT repositories.private);
 
 
 ALTER TABLE public.repositories_view OWNER TO postgres;
@@ -185,6 +252,7 @@ CREATE VIEW public.largest_repositories AS
     branches_view.size
    FROM (public.repositories
      JOIN public.branches_view ON ((repositories.id = branches_view.repository_id)))
+  WHERE (NOT repositories.private)
   ORDER BY branches_view.size DESC;
 
 
@@ -268,6 +336,48 @@ ALTER TABLE public.statistic_id_seq OWNER TO postgres;
 ALTER SEQUENCE public.statistic_id_seq OWNED BY public.statistic.id;
 
 
+--
+-- Name: api_keys_id_seq; Type: SEQUENCE; Schema: public; Owner: postgres
+--
+
+CREATE SEQUENCE public.api_keys_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+ALTER TABLE public.api_keys_id_seq OWNER TO postgres;
+
+--
+-- Name: api_keys_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: postgres
+--
+
+ALTER SEQUENCE public.api_keys_id_seq OWNED BY public.api_keys.id;
+
+
+--
+-- Name: api_key_usage_id_seq; Type: SEQUENCE; Schema: public; Owner: postgres
+--
+
+CREATE SEQUENCE public.api_key_usage_id_seq
+    START WITH 1
+    INCREMENT BY 1
+    NO MINVALUE
+    NO MAXVALUE
+    CACHE 1;
+
+
+ALTER TABLE public.api_key_usage_id_seq OWNER TO postgres;
+
+--
+-- Name: api_key_usage_id_seq; Type: SEQUENCE OWNED BY; Schema: public; Owner: postgres
+--
+
+ALTER SEQUENCE public.api_key_usage_id_seq OWNED BY public.api_key_usage.id;
+
+
 --
 -- Name: branches id; Type: DEFAULT; Schema: public; Owner: postgres
 --
@@ -289,6 +399,20 @@ ALTER TABLE ONLY public.repositories ALTER COLUMN id SET DEFAULT nextval('public
 ALTER TABLE ONLY public.statistic ALTER COLUMN id SET DEFAULT nextval('public.statistic_id_seq'::regclass);
 
 
+--
+-- Name: api_keys id; Type: DEFAULT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.api_keys ALTER COLUMN id SET DEFAULT nextval('public.api_keys_id_seq'::regclass);
+
+
+--
+-- Name: api_key_usage id; Type: DEFAULT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.api_key_usage ALTER COLUMN id SET DEFAULT nextval('public.api_key_usage_id_seq'::regclass);
+
+
 --
 -- Name: branches branches_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
 --
@@ -302,7 +426,7 @@ ALTER TABLE ONLY public.branches
 --
 
 ALTER TABLE ONLY public.branches
-    ADD CONSTRAINT branches_repo_id_name_key UNIQUE (repository_id, name);
+    ADD CONSTRAINT branches_repo_id_name_key UNIQUE (repository_id, name, clone_options);
 
 
 --
@@ -321,6 +445,60 @@ ALTER TABLE ONLY public.repositories
     ADD CONSTRAINT repositories_pkey PRIMARY KEY (id);
 
 
+--
+-- Name: uploads uploads_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.uploads
+    ADD CONSTRAINT uploads_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: branch_cache branch_cache_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.branch_cache
+    ADD CONSTRAINT branch_cache_pkey PRIMARY KEY (url);
+
+
+--
+-- Name: api_keys api_keys_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: api_keys api_keys_key_hash_key; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.api_keys
+    ADD CONSTRAINT api_keys_key_hash_key UNIQUE (key_hash);
+
+
+--
+-- Name: api_key_usage api_key_usage_pkey; Type: CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.api_key_usage
+    ADD CONSTRAINT api_key_usage_pkey PRIMARY KEY (id);
+
+
+--
+-- Name: api_key_usage_key_time_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX api_key_usage_key_time_idx ON public.api_key_usage USING btree (api_key_id, "time");
+
+
+--
+-- Name: uploads_content_hash_idx; Type: INDEX; Schema: public; Owner: postgres
+--
+
+CREATE INDEX uploads_content_hash_idx ON public.uploads USING btree (content_hash);
+
+
 --
 -- Name: branches branches_repo_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
 --
@@ -329,6 +507,14 @@ ALTER TABLE ONLY public.branches
     ADD CONSTRAINT branches_repo_id_fkey FOREIGN KEY (repository_id) REFERENCES public.repositories(id);
 
 
+--
+-- Name: api_key_usage api_key_usage_api_key_id_fkey; Type: FK CONSTRAINT; Schema: public; Owner: postgres
+--
+
+ALTER TABLE ONLY public.api_key_usage
+    ADD CONSTRAINT api_key_usage_api_key_id_fkey FOREIGN KEY (api_key_id) REFERENCES public.api_keys(id);
+
+
 --
 -- PostgreSQL database dump complete
 --
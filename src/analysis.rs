@@ -0,0 +1,303 @@
+use crate::{
+    handlers::{extract_user_agent, requester},
+    logic::{
+        callback::Notifier,
+        info::{CloneOptions, Status},
+        repository::RepositoryProvider,
+    },
+    openapi::{LookupProblems, Routes},
+    problem::{Problem, ProblemDetails},
+};
+use axum::{
+    extract::{FromRequest, Path, Request, State},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json,
+};
+use hmac::{Hmac, Mac};
+use hyper::{header::LOCATION, StatusCode};
+use rand::{thread_rng, Rng};
+use retainer::Cache;
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+
+pub use cloc_api::analysis::{Analysis, AnalysisRequest, AnalysisStatus, PreviousResult};
+
+const ID_LENGTH: usize = 32;
+/// Ids are forgotten this long after they were last requested or polled.
+const TARGET_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What an id stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    unique_name: String,
+    host: String,
+    owner: String,
+    repository: String,
+    branch: String,
+}
+
+impl Target {
+    fn analysis(&self, id: String, status: Status) -> Analysis {
+        let mut analysis = Analysis {
+            id,
+            status: AnalysisStatus::Queued,
+            host: self.host.clone(),
+            owner: self.owner.clone(),
+            repository: self.repository.clone(),
+            branch: self.branch.clone(),
+            progress: None,
+            result: None,
+            previous: None,
+            error: None,
+        };
+        match status {
+            Status::Ready => {}
+            Status::Cloned => analysis.status = AnalysisStatus::InProgress,
+            Status::InProgress(progress) => {
+                analysis.status = AnalysisStatus::InProgress;
+                analysis.progress = Some(progress);
+            }
+            Status::Previous { date, commit, data } => {
+                analysis.status = AnalysisStatus::InProgress;
+                analysis.previous = Some(PreviousResult {
+                    date,
+                    commit,
+                    result: String::from_utf8_lossy(&data).into_owned(),
+                });
+            }
+            Status::Done(data) => {
+                analysis.status = AnalysisStatus::Done;
+                analysis.result = Some(String::from_utf8_lossy(&data).into_owned());
+            }
+            Status::Error(error) => {
+                analysis.status = AnalysisStatus::Failed;
+                analysis.error = Some(error);
+            }
+        }
+        analysis
+    }
+}
+
+/// Keyed with the server `secret`, so an id can't be derived from the repository name and
+/// ids of private ones are only known to whoever requested them.
+fn analysis_id(secret: &[u8], unique_name: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(unique_name.as_bytes());
+    let mut id = hex::encode(mac.finalize().into_bytes());
+    id.truncate(ID_LENGTH);
+    id
+}
+
+/// Branch part of `[private/<fingerprint or shared>/]host/owner/repository/branch[?options]`.
+fn branch_of(unique_name: &str, repository_prefix: &str) -> String {
+    let name = unique_name.split('?').next().unwrap_or(unique_name);
+    name.find(repository_prefix)
+        .map(|start| name[start + repository_prefix.len()..].to_string())
+        .unwrap_or_default()
+}
+
+/// Router state of the analysis endpoints.
+#[derive(Clone)]
+pub struct Analyses {
+    provider: RepositoryProvider,
+    targets: Arc<Cache<String, Target>>,
+    /// Key of the ids, ids are only kept in memory so a new one on each start is enough.
+    secret: Arc<[u8; 32]>,
+}
+
+impl Analyses {
+    pub fn new(provider: RepositoryProvider) -> Self {
+        Self {
+            provider,
+            targets: Arc::new(Cache::new()),
+            secret: Arc::new(thread_rng().gen()),
+        }
+    }
+
+    /// Removes expired ids, runs until the task is aborted.
+    pub async fn monitor(&self) {
+        self.targets.monitor(4, 0.25, Duration::from_secs(60)).await;
+    }
+}
+
+pub fn routes() -> Routes<Analyses> {
+    Routes::new()
+        .route("/analyses", post(create_analysis))
+        .route("/analyses/:id", get(get_analysis))
+}
+
+fn analysis_response(status: StatusCode, analysis: &Analysis) -> Response {
+    let location = format!("/api/analyses/{}", analysis.id);
+    (status, [(LOCATION, location)], Json(analysis)).into_response()
+}
+
+/// `POST /api/analyses`: starts an analysis or returns the stored result. Answers 200 with
+/// the result, otherwise 202 with a `Location` to poll.
+#[utoipa::path(
+    post,
+    path = "/api/analyses",
+    tag = "analyses",
+    request_body = AnalysisRequest,
+    responses(
+        (status = 200, description = "The result", body = Analysis, headers(("Location" = String))),
+        (status = 202, description = "Queued or in progress, poll `Location`", body = Analysis, headers(("Location" = String))),
+        (status = 400, description = "Invalid body or callback", body = ProblemDetails, content_type = "application/problem+json"),
+        LookupProblems,
+    )
+)]
+pub async fn create_analysis(
+    State(analyses): State<Analyses>,
+    request: Request,
+) -> Result<Response, Problem> {
+    let requester = requester(extract_user_agent(&request), &request);
+    let Json(request) = Json::<AnalysisRequest>::from_request(request, &analyses)
+        .await
+        .map_err(|rejection| {
+            Problem::new(rejection.status(), "invalid_request", rejection.body_text())
+        })?;
+    if request
+        .callback
+        .as_deref()
+        .is_some_and(|url| !Notifier::is_valid_url(url))
+    {
+        return Err(Problem::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_callback",
+            "Callback must be an absolute http(s) url",
+        ));
+    }
+
+    let mut repository = request.repository.clone();
+    if request.host != "git.sr.ht" && !repository.ends_with(".git") {
+        repository = format!("{repository}.git");
+    }
+    let branch = request
+        .branch
+        .as_deref()
+        .map(|branch| branch.trim_matches('/'))
+        .filter(|branch| !branch.is_empty())
+        .map(str::to_string);
+    let (unique_name, status) = analyses
+        .provider
+        .request_info_for(
+            request.host.clone(),
+            request.owner.clone(),
+            repository.clone(),
+            branch,
+            CloneOptions::new(&request.paths, request.submodules),
+            requester,
+        )
+        .await
+        .map_err(|error| Problem::from(&error))?;
+    if let Some(callback) = request.callback {
+        analyses.provider.register_callback(&unique_name, callback);
+    }
+
+    let id = analysis_id(analyses.secret.as_slice(), &unique_name);
+    let target = Target {
+        branch: branch_of(
+            &unique_name,
+            &format!("{}/{}/{repository}/", request.host, request.owner),
+        ),
+        unique_name,
+        host: request.host,
+        owner: request.owner,
+        repository: request.repository,
+    };
+    let analysis = target.analysis(id.clone(), status);
+    analyses.targets.insert(id, target, TARGET_TTL).await;
+
+    let status = match analysis.status {
+        AnalysisStatus::Done => StatusCode::OK,
+        _ => StatusCode::ACCEPTED,
+    };
+    Ok(analysis_response(status, &analysis))
+}
+
+/// `GET /api/analyses/:id`: current state of an analysis requested during the last day.
+#[utoipa::path(
+    get,
+    path = "/api/analyses/{id}",
+    tag = "analyses",
+    params(("id" = String, Path, description = "Id returned when the analysis was requested")),
+    responses(
+        (status = 200, description = "Current state", body = Analysis),
+        (status = 404, description = "Unknown id", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn get_analysis(
+    State(analyses): State<Analyses>,
+    Path(id): Path<String>,
+) -> Result<Response, Problem> {
+    let target = analyses
+        .targets
+        .get(&id)
+        .await
+        .map(|target| target.value().clone())
+        .ok_or_else(|| {
+            Problem::new(
+                StatusCode::NOT_FOUND,
+                "analysis_not_found",
+                format!("Analysis {id} not found, request it again"),
+            )
+        })?;
+    // Polling keeps the id alive
+    analyses
+        .targets
+        .insert(id.clone(), target.clone(), TARGET_TTL)
+        .await;
+    let status = analyses
+        .provider
+        .current_status(&target.unique_name)
+        .unwrap_or(Status::Ready);
+
+    Ok(analysis_response(
+        StatusCode::OK,
+        &target.analysis(id, status),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analysis_id, branch_of, AnalysisStatus, Target};
+    use crate::logic::{info::Status, Error};
+
+    #[test]
+    fn status_is_mapped_to_analysis() {
+        let unique_name = "private/0123456789abcdef/github.com/org/repo.git/feature/x?paths=src";
+        let target = Target {
+            unique_name: unique_name.to_string(),
+            host: "github.com".to_string(),
+            owner: "org".to_string(),
+            repository: "repo".to_string(),
+            branch: branch_of(unique_name, "github.com/org/repo.git/"),
+        };
+        assert_eq!(target.branch, "feature/x");
+        let id = analysis_id(b"secret", unique_name);
+        assert_eq!(id.len(), 32);
+        assert_eq!(id, analysis_id(b"secret", unique_name));
+        assert_ne!(id, analysis_id(b"other secret", unique_name));
+
+        let queued = target.analysis(id.clone(), Status::Ready);
+        assert_eq!(queued.status, AnalysisStatus::Queued);
+        let running = target.analysis(id.clone(), Status::InProgress("Cloning".into()));
+        assert_eq!(running.status, AnalysisStatus::InProgress);
+        assert_eq!(running.progress.as_deref(), Some("Cloning"));
+        let done = target.analysis(id.clone(), Status::Done(b"Total 10".to_vec()));
+        assert_eq!(done.status, AnalysisStatus::Done);
+        assert_eq!(done.result.as_deref(), Some("Total 10"));
+        let timeout = Error::CloneTimeout {
+            repository: "repo".to_string(),
+            seconds: 600,
+        };
+        let failed = target.analysis(id, Status::Error(timeout.failure()));
+        assert_eq!(failed.status, AnalysisStatus::Failed);
+        let error = failed.error.unwrap();
+        assert_eq!((error.code.as_str(), error.status), ("clone_timeout", 504));
+
+        let json = serde_json::to_value(&done).unwrap();
+        assert_eq!(json["status"], "done");
+        assert!(json.get("error").is_none());
+    }
+}
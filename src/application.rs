@@ -1,33 +1,44 @@
 use crate::{
-    handlers::{self},
+    analysis::{self, Analyses},
+    badge, handlers,
     logic::{
-        git::Git,
-        repository::{count_line_of_code, RepositoryProvider},
+        api_key::{key_from_headers, ApiKey, ApiKeys},
+        archive::ArchiveLimits,
+        callback::Notifier,
+        credentials::{Credentials, TOKEN_HEADER, USERNAME_HEADER},
+        forge::Forge,
+        git::{BranchCacheConfig, Git},
+        rate_limit::{Budget, ClientIp, RateLimiter, RateLimits},
+        repository::RepositoryProvider,
+        scheduler::Scheduler,
+        upload::UploadStore,
     },
-    statistic::{largest, popular, recent},
-    websocket::{handler_ws, handler_ws_with_branch},
+    openapi::{
+        self, Routes, API_PREFIX, PAGES, PAGE_PREFIX, REPOSITORY_API_PREFIX, WEBSOCKET_PREFIX,
+    },
+    preview,
+    problem::{request_id_middleware, Problem},
+    statistic,
+    upload::{self, UploadLimits, UploadState},
+    webhook::{self, WebhookState},
+    websocket,
 };
 use axum::{
     body::Body,
-    error_handling::{HandleError, HandleErrorLayer},
-    extract,
+    error_handling::HandleErrorLayer,
+    extract::{ConnectInfo, State},
     handler::HandlerWithoutStateExt,
     middleware::Next,
     response::{IntoResponse, Response},
-    routing::{get, get_service, post},
+    routing::{get_service, MethodRouter},
     serve::serve,
-    Router,
 };
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
 use hyper::{Request, StatusCode};
 use retainer::Cache;
-use std::{future::IntoFuture, net::SocketAddr, path::Path, sync::Arc, time::Duration};
-use tempfile::tempdir_in;
-use tokio::{
-    fs,
-    signal::{self, ctrl_c},
-};
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc};
+use tokio::signal::{self, ctrl_c};
 use tokio_postgres::NoTls;
 use tokio_util::sync::CancellationToken;
 use tower::{BoxError, ServiceBuilder};
@@ -54,66 +65,95 @@ fn is_fingerprinted_asset(path: &str) -> bool {
     suffix.len() >= 6 && suffix.chars().all(|ch| ch.is_ascii_alphanumeric())
 }
 
+fn page_service(path: &str) -> MethodRouter {
+    get_service(ServeFile::new(path)).handle_error(|error| async move {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Unhandled internal error: {}", error),
+        )
+    })
+}
+
+/// Every route of the service, the frontend assets aside.
+pub fn routes(
+    provider: RepositoryProvider,
+    analyses: Analyses,
+    connection_pool: Pool<PostgresConnectionManager<NoTls>>,
+    upload_state: UploadState,
+    webhook_state: WebhookState,
+) -> Routes {
+    let api_routes = statistic::routes()
+        .with_state(connection_pool)
+        .merge(analysis::routes().with_state(analyses))
+        .merge(openapi::routes());
+
+    let pages = PAGES.iter().fold(Routes::new(), |routes, (path, file, _)| {
+        routes.route(path, page_service(file))
+    });
+    pages
+        .merge(upload::routes(upload_state))
+        .merge(webhook::routes().with_state(webhook_state))
+        .nest(
+            WEBSOCKET_PREFIX,
+            websocket::routes().with_state(provider.clone()),
+        )
+        .nest("/badge/:host", badge::routes().with_state(provider.clone()))
+        .nest(
+            "/preview/:host",
+            preview::routes().with_state(provider.clone()),
+        )
+        .nest(API_PREFIX, api_routes)
+        .nest(
+            REPOSITORY_API_PREFIX,
+            handlers::api_routes().with_state(provider.clone()),
+        )
+        .nest(PAGE_PREFIX, handlers::general_routes().with_state(provider))
+}
+
 pub async fn start_application(
     socket: SocketAddr,
     connection_pool: Pool<PostgresConnectionManager<NoTls>>,
 ) -> Result<(), String> {
-    let root_service =
-        get_service(ServeFile::new("dist/index.html")).handle_error(|error| async move {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Unhandled internal error: {}", error),
-            )
-        });
-
-    let upload_service = HandleError::new(
-        get_service(ServeFile::new("dist/upload.html")),
-        |error| async move {
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Unhandled internal error: {}", error),
-            )
-        },
-    );
-
-    let cache = Arc::new(Cache::new());
-    let cache_clone = cache.clone();
-    let git_provider = Git::new(cache);
+    let branch_cache = BranchCacheConfig::from_env();
+    let mut git_provider = Git::new(Arc::new(Cache::new())).with_config(branch_cache);
+    if branch_cache.persist {
+        git_provider = git_provider.with_store(connection_pool.clone());
+    }
 
     let cancel = Arc::new(CancellationToken::new());
+    let rate_limiter = RateLimiter::new(RateLimits::from_env());
     let repository_provider = RepositoryProvider::new(
         connection_pool.clone(),
         git_provider.clone(),
+        Forge::from_env(),
+        Notifier::from_env(),
+        Credentials::from_env(),
+        rate_limiter.clone(),
         cancel.clone(),
     );
 
-    let monitor =
-        tokio::spawn(async move { cache_clone.monitor(4, 0.25, Duration::from_secs(1)).await });
-
-    let websocket_service = Router::new()
-        .route("/:owner/:repo", get(handler_ws))
-        .route("/:owner/:repo/tree/*branch", get(handler_ws_with_branch))
-        .route("/:owner/:repo/-/tree/*branch", get(handler_ws_with_branch))
-        .route("/:owner/:repo/src/*branch", get(handler_ws_with_branch))
-        .route(
-            "/:owner/:repo/src/branch/*branch",
-            get(handler_ws_with_branch),
-        )
-        .route("/project/:owner/:repo", get(handler_ws))
-        .route(
-            "/project/:owner/:repo/tree/*branch",
-            get(handler_ws_with_branch),
-        )
-        .with_state(repository_provider.clone());
+    let git_monitor = git_provider.clone();
+    let monitor = tokio::spawn(async move { git_monitor.monitor().await });
+    let analyses = Analyses::new(repository_provider.clone());
+    let analyses_monitor = {
+        let analyses = analyses.clone();
+        tokio::spawn(async move { analyses.monitor().await })
+    };
 
-    let statistic_router = Router::new()
-        .route("/largest/:limit", get(largest))
-        .route("/recent/:limit", get(recent))
-        .route("/popular/:limit", get(popular))
-        .with_state(connection_pool);
+    let webhook_state = WebhookState {
+        provider: repository_provider.clone(),
+        secret: std::env::var("WEBHOOK_SECRET").ok().map(Arc::new),
+    };
+
+    let upload_state = UploadState::new(
+        "cloc_repo",
+        UploadLimits::from_env(),
+        ArchiveLimits::from_env(),
+        UploadStore::from_env(connection_pool.clone()),
+        cancel.clone(),
+    );
+    upload_state.remove_stale().await;
 
-    let api_router = handlers::create_api_router(repository_provider.clone());
-    let general_router = handlers::create_general_router(repository_provider.clone());
     let assets_service = get_service(ServeDir::new("dist/assets"))
         .handle_error(|error| async move {
             (
@@ -123,43 +163,64 @@ pub async fn start_application(
         })
         // .layer(CompressionLayer::new().gzip(true).deflate(true).br(true))
         ;
-    let app = Router::new()
-        .route_service("/", root_service)
-        .route_service("/upload", upload_service)
-        .route_service("/post", post(upload))
-        .nest("/ws/:host", websocket_service)
-        .nest("/api", statistic_router)
-        .nest("/api/:host", api_router)
-        .nest("/:host", general_router)
-        .nest_service("/assets", assets_service)
-        .fallback_service(not_found.into_service())
-        .layer(
-            ServiceBuilder::new()
-                .layer(HandleErrorLayer::new(handle_errors))
-                .timeout(std::time::Duration::from_secs(600)),
-        )
-        .layer(CorsLayer::new().allow_credentials(true))
-        .layer(axum::middleware::from_fn(set_static_cache_control))
-        .layer(CompressionLayer::new())
-        .layer(TraceLayer::new_for_http());
+    let app = routes(
+        repository_provider.clone(),
+        analyses,
+        connection_pool.clone(),
+        upload_state,
+        webhook_state,
+    )
+    .static_files("/assets", assets_service)
+    .into_router()
+    .fallback_service(not_found.into_service())
+    .layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_errors))
+            .timeout(std::time::Duration::from_secs(600)),
+    )
+    .layer(axum::middleware::from_fn_with_state(
+        rate_limiter,
+        rate_limit_middleware,
+    ))
+    .layer(axum::middleware::from_fn_with_state(
+        ApiKeys::new(connection_pool.clone()),
+        api_key_middleware,
+    ))
+    .layer(CorsLayer::new().allow_credentials(true))
+    .layer(axum::middleware::from_fn(set_static_cache_control))
+    .layer(CompressionLayer::new())
+    .layer(axum::middleware::from_fn(request_id_middleware))
+    .layer(TraceLayer::new_for_http());
 
     let tcp_listener = tokio::net::TcpListener::bind(&socket)
         .await
         .map_err(|error| format!("Failed to bind HTTP listener at {socket}: {error}"))?;
-    let server = serve(tcp_listener, app.into_make_service())
-        .with_graceful_shutdown(shutdown_signal(cancel.clone()))
-        .into_future();
+    let server = serve(
+        tcp_listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(cancel.clone()))
+    .into_future();
 
     let repository_service = {
         let repository_provider = repository_provider.clone();
         tokio::spawn(async move { repository_provider.run().await })
     };
 
+    let scheduler_service = {
+        let scheduler = Scheduler::from_env(repository_provider.clone(), cancel.clone());
+        tokio::spawn(async move { scheduler.run().await })
+    };
+
     let handle = server.await;
     cancel.cancel();
     monitor.abort();
+    analyses_monitor.abort();
 
     let repository_result = repository_service.await;
+    if let Err(error) = scheduler_service.await {
+        tracing::warn!("Scheduler task stopped unexpectedly: {error}");
+    }
     if let Err(error) = monitor.await {
         if !error.is_cancelled() {
             tracing::warn!("Cache monitor task stopped unexpectedly: {error}");
@@ -178,8 +239,11 @@ pub async fn start_application(
         Err(error) => Err(format!("Error while running HTTP server: {error}")),
     }
 }
-async fn set_static_cache_control(request: Request<Body>, next: Next) -> Response {
+pub(crate) async fn set_static_cache_control(request: Request<Body>, next: Next) -> Response {
     let path = request.uri().path().to_owned();
+    let has_credential = [TOKEN_HEADER, USERNAME_HEADER]
+        .iter()
+        .any(|name| request.headers().contains_key(*name));
     let mut response = next.run(request).await;
 
     if response
@@ -195,6 +259,14 @@ async fn set_static_cache_control(request: Request<Body>, next: Next) -> Respons
         } else {
             Some("no-cache,private,max-age=0")
         }
+    } else if (path.starts_with("/badge/") || path.starts_with("/preview/")) && has_credential {
+        // Counts of a private repository must never reach a shared cache
+        Some("private, no-store")
+    } else if (path.starts_with("/badge/") || path.starts_with("/preview/"))
+        && response.status().is_success()
+    {
+        // Badges and preview cards are proxied by image caches, keep them short-lived
+        Some("public, max-age=300, s-maxage=300")
     } else if response
         .headers()
         .get(hyper::header::CONTENT_TYPE)
@@ -216,6 +288,86 @@ async fn set_static_cache_control(request: Request<Body>, next: Next) -> Respons
     response
 }
 
+/// Rejects unknown or revoked API keys, passes valid ones to the handlers as an extension
+/// and reports the remaining quota. Requests without a key stay anonymous.
+async fn api_key_middleware(
+    State(api_keys): State<ApiKeys>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(key) = key_from_headers(request.headers()) else {
+        return next.run(request).await;
+    };
+
+    let api_key = match api_keys.find(&key).await {
+        Ok(Some(api_key)) => api_key,
+        Ok(None) => {
+            return Problem::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_api_key",
+                "Invalid or revoked API key",
+            )
+            .into_response();
+        }
+        Err(error) => {
+            tracing::error!("Can't look up API key: {error}");
+            return Problem::internal("Can't verify API key").into_response();
+        }
+    };
+
+    request.extensions_mut().insert(api_key.clone());
+    let mut response = next.run(request).await;
+
+    match api_keys.usage(&api_key).await {
+        Ok(usage) => {
+            for (name, value) in usage.headers(&api_key.quota, chrono::Utc::now()) {
+                if let Ok(value) = axum::http::HeaderValue::from_str(&value) {
+                    response.headers_mut().insert(name, value);
+                }
+            }
+        }
+        Err(error) => tracing::warn!("Can't get usage of API key {}: {error}", api_key.id),
+    }
+    response
+}
+
+/// Spends a read token of the client address on every request except static assets and
+/// passes the address to the handlers, which spend a clone token when they queue a clone.
+/// Clients with a valid API key are limited by its quota instead.
+async fn rate_limit_middleware(
+    State(rate_limiter): State<RateLimiter>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    if request.extensions().get::<ApiKey>().is_some()
+        || request.uri().path().starts_with("/assets/")
+    {
+        return next.run(request).await;
+    }
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip());
+    let Some(ip) = rate_limiter.limits().client_ip(request.headers(), peer) else {
+        return next.run(request).await;
+    };
+
+    if let Err(retry_after) = rate_limiter.take(ip, Budget::Read) {
+        let retry_after = retry_after.as_secs().max(1);
+        return Problem::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limited",
+            format!("Too many requests from this address, retry in {retry_after} seconds"),
+        )
+        .with_retry_after(retry_after)
+        .into_response();
+    }
+
+    request.extensions_mut().insert(ClientIp(ip));
+    next.run(request).await
+}
+
 pub async fn not_found(_uri: axum::http::Uri) -> Response<Body> {
     match std::fs::read("dist/404.html") {
         Ok(buffer) => (
@@ -294,118 +446,14 @@ async fn shutdown_signal(cancel: Arc<CancellationToken>) {
     cancel.cancel();
 }
 
-async fn handle_errors(err: BoxError) -> (StatusCode, String) {
+async fn handle_errors(err: BoxError) -> Problem {
     if err.is::<tower::timeout::error::Elapsed>() {
-        (
+        Problem::new(
             StatusCode::REQUEST_TIMEOUT,
-            "Request took too long".to_string(),
+            "request_timeout",
+            "Request took too long",
         )
     } else {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Unhandled internal error: {}", err),
-        )
-    }
-}
-
-async fn upload(mut multipart: extract::Multipart) -> Result<Response<Body>, (StatusCode, String)> {
-    fs::create_dir_all("cloc_repo")
-        .await
-        .map_err(internal_server_error)?;
-
-    let tempdir = tempdir_in("cloc_repo").map_err(internal_server_error)?;
-    let path = tempdir.path().to_path_buf();
-    let path_str = path
-        .to_str()
-        .ok_or_else(|| internal_server_error("temporary path is not valid UTF-8"))?;
-
-    let mut index = 0usize;
-    while let Some(field) = multipart.next_field().await.map_err(bad_request)? {
-        let source_name = field.file_name().or(field.name()).unwrap_or("upload");
-        let relative_path = sanitize_upload_path(source_name);
-        let data = field.bytes().await.map_err(bad_request)?;
-        let file_path = unique_upload_path(&path, &relative_path, index);
-
-        if let Some(parent) = file_path.parent() {
-            fs::create_dir_all(parent)
-                .await
-                .map_err(internal_server_error)?;
-        }
-
-        fs::write(&file_path, &data)
-            .await
-            .map_err(internal_server_error)?;
-
-        tracing::debug!("write file '{}'", file_path.display());
-        index += 1;
+        Problem::internal(format!("Unhandled internal error: {}", err))
     }
-
-    let scc_output = count_line_of_code(path_str, "")
-        .await
-        .map_err(|e| internal_server_error(e.to_string()))?;
-
-    Response::builder()
-        .header("Content-Type", "text/plain")
-        .body(Body::from(scc_output))
-        .map_err(internal_server_error)
-}
-
-fn sanitize_upload_path(name: &str) -> std::path::PathBuf {
-    let mut path = std::path::PathBuf::new();
-
-    for component in Path::new(name).components() {
-        if let std::path::Component::Normal(value) = component {
-            let Some(value) = value.to_str() else {
-                continue;
-            };
-
-            let sanitized = value
-                .chars()
-                .map(|ch| if ch.is_control() { '_' } else { ch })
-                .collect::<String>();
-
-            if !sanitized.is_empty() {
-                path.push(sanitized);
-            }
-        }
-    }
-
-    if path.as_os_str().is_empty() {
-        path.push("upload");
-    }
-
-    path
-}
-
-fn unique_upload_path(root: &Path, relative_path: &Path, index: usize) -> std::path::PathBuf {
-    let candidate = root.join(relative_path);
-
-    if !candidate.exists() {
-        return candidate;
-    }
-
-    let parent = candidate
-        .parent()
-        .map(Path::to_path_buf)
-        .unwrap_or_else(|| root.to_path_buf());
-    let stem = candidate
-        .file_stem()
-        .and_then(|value| value.to_str())
-        .filter(|value| !value.is_empty())
-        .unwrap_or("upload");
-    let extension = candidate.extension().and_then(|value| value.to_str());
-    let file_name = match extension {
-        Some(extension) if !extension.is_empty() => format!("{index:04}_{stem}.{extension}"),
-        _ => format!("{index:04}_{stem}"),
-    };
-
-    parent.join(file_name)
-}
-
-fn bad_request(error: impl std::fmt::Display) -> (StatusCode, String) {
-    (StatusCode::BAD_REQUEST, error.to_string())
-}
-
-fn internal_server_error(error: impl std::fmt::Display) -> (StatusCode, String) {
-    (StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
 }
@@ -0,0 +1,302 @@
+use crate::{
+    handlers::{extract_user_agent, requester, BRANCH_PATHS, REPOSITORY_PATHS},
+    logic::{
+        info::{Requester, Status},
+        repository::RepositoryProvider,
+        summary::Summary,
+    },
+    openapi::{BranchPath, RepositoryPath, Routes},
+};
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use hyper::{
+    header::{CACHE_CONTROL, CONTENT_TYPE},
+    Request,
+};
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+const SVG_CONTENT_TYPE: &str = "image/svg+xml; charset=utf-8";
+const COLOR_OK: &str = "#4c1";
+const COLOR_PENDING: &str = "#dfb317";
+const COLOR_ERROR: &str = "#9f9f9f";
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BadgeQuery {
+    /// Count only this language instead of the total.
+    language: Option<String>,
+    /// Overrides the left part of the badge.
+    label: Option<String>,
+}
+
+pub fn routes() -> Routes<RepositoryProvider> {
+    let routes = REPOSITORY_PATHS
+        .iter()
+        .fold(Routes::new(), |routes, path| routes.route(path, get(badge)));
+    BRANCH_PATHS.iter().fold(routes, |routes, path| {
+        routes.route(path, get(badge_with_branch))
+    })
+}
+
+/// Badge with the lines of code of a repository from its stored result, nothing is analysed.
+#[utoipa::path(
+    get,
+    path = "/badge/{host}/{owner}/{repo}",
+    tag = "images",
+    params(RepositoryPath, BadgeQuery),
+    responses((status = 200, description = "Line count, `analysing…`, `error`, `unknown` if never analysed or `not found`", content_type = "image/svg+xml"))
+)]
+async fn badge(
+    Path((host, owner, repository_name)): Path<(String, String, String)>,
+    Query(query): Query<BadgeQuery>,
+    State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let requester = requester(extract_user_agent(&request), &request);
+    render_badge(
+        host,
+        owner,
+        repository_name,
+        None,
+        query,
+        provider,
+        requester,
+    )
+    .await
+}
+
+/// Badge with the lines of code of a branch.
+#[utoipa::path(
+    get,
+    path = "/badge/{host}/{owner}/{repo}/tree/{branch}",
+    tag = "images",
+    params(BranchPath, BadgeQuery),
+    responses((status = 200, description = "Line count, `analysing…`, `error`, `unknown` if never analysed or `not found`", content_type = "image/svg+xml"))
+)]
+async fn badge_with_branch(
+    Path((host, owner, repository_name, branch)): Path<(String, String, String, String)>,
+    Query(query): Query<BadgeQuery>,
+    State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let branch = branch.trim_matches('/').to_string();
+    let requester = requester(extract_user_agent(&request), &request);
+    render_badge(
+        host,
+        owner,
+        repository_name,
+        Some(branch),
+        query,
+        provider,
+        requester,
+    )
+    .await
+}
+
+async fn render_badge(
+    host: String,
+    owner: String,
+    mut repository_name: String,
+    branch: Option<String>,
+    query: BadgeQuery,
+    provider: RepositoryProvider,
+    requester: Requester,
+) -> Response<Body> {
+    if host != "git.sr.ht" && !repository_name.ends_with(".git") {
+        repository_name = format!("{repository_name}.git");
+    }
+
+    let label = query
+        .label
+        .clone()
+        .unwrap_or_else(|| match &query.language {
+            Some(language) => format!("{language} code"),
+            None => "lines of code".to_string(),
+        });
+
+    // Image proxies fetch badges of any README, they never start an analysis
+    let status = provider
+        .known_status_for(&host, &owner, &repository_name, branch, &requester)
+        .await;
+
+    let (value, color) = match status {
+        Ok(Some(Status::Done(data) | Status::Previous { data, .. })) => {
+            let summary = Summary::parse(&data);
+            let code = match &query.language {
+                Some(language) => summary.language(language).map(|stat| stat.code),
+                None => Some(summary.total.code),
+            };
+            let svg = match code {
+                Some(code) => svg(&label, &humanize(code), COLOR_OK),
+                None => svg(&label, "none", COLOR_ERROR),
+            };
+            // Cache-Control for finished badges is set by `set_static_cache_control`
+            return ([(CONTENT_TYPE, SVG_CONTENT_TYPE)], svg).into_response();
+        }
+        Ok(Some(Status::InProgress(_) | Status::Cloned | Status::Ready)) => {
+            ("analysing…".to_string(), COLOR_PENDING)
+        }
+        Ok(Some(Status::Error(_))) => ("error".to_string(), COLOR_ERROR),
+        Ok(None) => ("unknown".to_string(), COLOR_ERROR),
+        Err(error) => {
+            tracing::warn!("Badge error: {error}");
+            ("not found".to_string(), COLOR_ERROR)
+        }
+    };
+
+    (
+        [
+            (CONTENT_TYPE, SVG_CONTENT_TYPE),
+            (CACHE_CONTROL, "no-cache,private,max-age=0"),
+        ],
+        svg(&label, &value, color),
+    )
+        .into_response()
+}
+
+pub(crate) fn humanize(value: u64) -> String {
+    match value {
+        0..=999 => value.to_string(),
+        1_000..=9_999 => format!("{:.1}k", value as f64 / 1_000.0),
+        10_000..=999_999 => format!("{}k", value / 1_000),
+        1_000_000..=9_999_999 => format!("{:.1}M", value as f64 / 1_000_000.0),
+        _ => format!("{}M", value / 1_000_000),
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.chars()
+        .map(|ch| match ch {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&apos;".to_string(),
+            ch => ch.to_string(),
+        })
+        .collect()
+}
+
+// Approximation of Verdana 11px used by shields.io flat badges
+fn text_width(text: &str) -> usize {
+    text.chars().count() * 7 + 10
+}
+
+fn svg(label: &str, value: &str, color: &str) -> String {
+    let label_width = text_width(label);
+    let value_width = text_width(value);
+    let width = label_width + value_width;
+    let label_x = label_width / 2;
+    let value_x = label_width + value_width / 2;
+    let label = escape(label);
+    let value = escape(value);
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {value}"><title>{label}: {value}</title><linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient><clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath><g clip-path="url(#r)"><rect width="{label_width}" height="20" fill="#555"/><rect x="{label_width}" width="{value_width}" height="20" fill="{color}"/><rect width="{width}" height="20" fill="url(#s)"/></g><g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="11"><text x="{label_x}" y="15" fill="#010101" fill-opacity=".3">{label}</text><text x="{label_x}" y="14">{label}</text><text x="{value_x}" y="15" fill="#010101" fill-opacity=".3">{value}</text><text x="{value_x}" y="14">{value}</text></g></svg>"##
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{humanize, routes, svg};
+    use crate::{
+        application::set_static_cache_control,
+        logic::{
+            info::{BranchValue, Branches, Status},
+            repository::{
+                tests::{provider_with_branches, set_status},
+                RepositoryProvider,
+            },
+        },
+        openapi::Routes,
+    };
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header::CACHE_CONTROL, Request},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    async fn app() -> (Router, RepositoryProvider) {
+        let branches = Branches {
+            default_branch: "main".to_string(),
+            branches: vec![BranchValue {
+                name: "main".to_string(),
+                commit: "abc".to_string(),
+            }],
+            tags: Vec::new(),
+        };
+        let provider = provider_with_branches("https://github.com/acme/tool.git", branches).await;
+        let router = Routes::new()
+            .nest("/badge/:host", routes().with_state(provider.clone()))
+            .into_router()
+            .layer(axum::middleware::from_fn(set_static_cache_control));
+        (router, provider)
+    }
+
+    async fn get(app: &Router, uri: &str) -> (Option<String>, String) {
+        let request = Request::get(uri).body(Body::empty()).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let cache_control = response
+            .headers()
+            .get(CACHE_CONTROL)
+            .map(|value| value.to_str().unwrap().to_string());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (cache_control, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn badge_shows_progress_then_the_stored_counts() {
+        let (app, provider) = app().await;
+        set_status(
+            &provider,
+            "github.com/acme/tool.git/main",
+            Status::InProgress("Cloning".to_string()),
+        );
+
+        let (cache_control, badge) = get(&app, "/badge/github.com/acme/tool").await;
+        assert!(badge.contains("lines of code: analysing…"));
+        assert_eq!(cache_control.as_deref(), Some("no-cache,private,max-age=0"));
+
+        set_status(
+            &provider,
+            "github.com/acme/tool.git/main",
+            Status::Done(
+                b"Language Files Lines Blanks Comments Code Complexity\n\
+                  Rust 2 1500 100 100 1300 0\n\
+                  Python 1 300 50 50 200 0\n\
+                  Total 3 1800 150 150 1500 0\n"
+                    .to_vec(),
+            ),
+        );
+        let (cache_control, badge) = get(&app, "/badge/github.com/acme/tool/tree/main").await;
+        assert!(badge.contains("lines of code: 1.5k"));
+        assert!(cache_control.unwrap().starts_with("public"));
+
+        let (_, badge) = get(&app, "/badge/github.com/acme/tool?language=python").await;
+        assert!(badge.contains("python code: 200"));
+        let (_, badge) = get(&app, "/badge/github.com/acme/tool?language=Go").await;
+        assert!(badge.contains("Go code: none"));
+    }
+
+    #[test]
+    fn humanizes_line_counts() {
+        assert_eq!(humanize(999), "999");
+        assert_eq!(humanize(1_234), "1.2k");
+        assert_eq!(humanize(123_456), "123k");
+        assert_eq!(humanize(4_500_000), "4.5M");
+    }
+
+    #[test]
+    fn svg_escapes_text() {
+        let badge = svg("C++ & <C>", "1k", "#4c1");
+
+        assert!(badge.contains("C++ &amp; &lt;C&gt;: 1k"));
+        assert!(!badge.contains("<C>"));
+    }
+}
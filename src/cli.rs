@@ -0,0 +1,167 @@
+use crate::logic::{
+    cloner::{CloneLimits, Cloner},
+    credentials::Credentials,
+    git::Git,
+    info::{to_url, CloneOptions, Task},
+    repository::count_line_of_code,
+    Error,
+};
+use cloc_api::analysis::AnalysisRequest;
+use dashmap::DashMap;
+use retainer::Cache;
+use std::sync::Arc;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// Repository given on the command line as `[https://]host/owner/repository[/tree/branch]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepositoryUrl {
+    pub host: String,
+    pub owner: String,
+    /// Without `.git`.
+    pub repository: String,
+    pub branch: Option<String>,
+}
+
+impl RepositoryUrl {
+    /// Accepts the urls the forges show, including branch pages and GitFlic's `project`.
+    pub fn parse(url: &str) -> Option<Self> {
+        let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let url = url
+            .split(['?', '#'])
+            .next()
+            .unwrap_or(url)
+            .trim_matches('/');
+        let (host, path) = url.split_once('/')?;
+        let path = path.strip_prefix("project/").unwrap_or(path);
+
+        let mut parts = path.splitn(3, '/');
+        let owner = parts.next().filter(|owner| !owner.is_empty())?;
+        let repository = parts
+            .next()
+            .map(|repository| repository.trim_end_matches(".git"))
+            .filter(|repository| !repository.is_empty())?;
+        let branch = parts.next().and_then(|rest| {
+            let rest = rest.strip_prefix("-/").unwrap_or(rest);
+            ["tree/", "src/branch/", "src/"]
+                .into_iter()
+                .find_map(|prefix| rest.strip_prefix(prefix))
+                .filter(|branch| !branch.is_empty())
+                .map(str::to_string)
+        });
+
+        Some(Self {
+            host: host.to_ascii_lowercase(),
+            owner: owner.to_string(),
+            repository: repository.to_string(),
+            branch,
+        })
+    }
+
+    /// Name git clones, with `.git` except on sourcehut.
+    pub fn repository_name(&self) -> String {
+        if self.host == "git.sr.ht" {
+            self.repository.clone()
+        } else {
+            format!("{}.git", self.repository)
+        }
+    }
+
+    pub fn analysis_request(&self, branch: Option<String>) -> AnalysisRequest {
+        AnalysisRequest {
+            host: self.host.clone(),
+            owner: self.owner.clone(),
+            repository: self.repository.clone(),
+            branch: branch.or_else(|| self.branch.clone()),
+            paths: Vec::new(),
+            submodules: false,
+            callback: None,
+        }
+    }
+}
+
+/// Clones `url` into a temporary directory and counts it with scc, without the database.
+/// Credentials come from `GIT_CREDENTIALS` and limits from the `CLONE_*` variables as for
+/// the service.
+pub async fn analyze(
+    url: &RepositoryUrl,
+    branch: Option<String>,
+    format: &str,
+) -> Result<Vec<u8>, Error> {
+    let repository_name = url.repository_name();
+    let remote = to_url(&url.host, &url.owner, &repository_name);
+    let credential = Credentials::from_env().for_host(&url.host);
+    let branch = match branch.or_else(|| url.branch.clone()) {
+        Some(branch) => branch,
+        None => {
+            Git::new(Arc::new(Cache::new()))
+                .default_branch(&remote, credential.as_ref())
+                .await?
+        }
+    };
+
+    let task = Task {
+        host: url.host.clone(),
+        owner: url.owner.clone(),
+        repository_name,
+        branch: branch.clone(),
+        default_branch: branch,
+        user_agent: "cloc-cli".to_string(),
+        credential,
+        usage: None,
+        options: CloneOptions::default(),
+    };
+    let directory = tempfile::tempdir().map_err(|source| Error::CreateTempDirError { source })?;
+    let path = directory.path().join("repository");
+    let path = path.to_string_lossy();
+
+    let cloner = Cloner::new(
+        Arc::new(DashMap::new()),
+        CloneLimits::from_env(),
+        Arc::new(CancellationToken::new()),
+    );
+    let deadline = Instant::now() + cloner.limits().timeout;
+    cloner
+        .clone_repository(&task, &task.to_unique_name(), &path, None, deadline)
+        .await?;
+    count_line_of_code(&path, format).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RepositoryUrl;
+
+    #[test]
+    fn repository_url_is_parsed() {
+        let url = RepositoryUrl::parse("https://github.com/boyter/scc.git").unwrap();
+        assert_eq!(
+            url,
+            RepositoryUrl {
+                host: "github.com".to_string(),
+                owner: "boyter".to_string(),
+                repository: "scc".to_string(),
+                branch: None,
+            }
+        );
+        assert_eq!(url.repository_name(), "scc.git");
+
+        let url = RepositoryUrl::parse("gitlab.com/group/project/-/tree/feature/x").unwrap();
+        assert_eq!(url.repository, "project");
+        assert_eq!(url.branch.as_deref(), Some("feature/x"));
+        let url = RepositoryUrl::parse("https://codeberg.org/org/repo/src/branch/dev").unwrap();
+        assert_eq!(url.branch.as_deref(), Some("dev"));
+        let url = RepositoryUrl::parse("https://gitflic.ru/project/red-soft/fbx").unwrap();
+        assert_eq!(
+            (url.owner.as_str(), url.repository.as_str()),
+            ("red-soft", "fbx")
+        );
+        assert_eq!(
+            RepositoryUrl::parse("git.sr.ht/~sircmpwn/hare")
+                .unwrap()
+                .repository_name(),
+            "hare"
+        );
+
+        assert!(RepositoryUrl::parse("https://github.com/boyter").is_none());
+    }
+}
@@ -1,14 +1,22 @@
-use crate::logic::{
-    self,
-    info::{to_url, Status},
-    repository::RepositoryProvider,
+use crate::{
+    logic::{
+        self,
+        api_key::ApiKey,
+        callback::Notifier,
+        credentials::Credential,
+        info::{to_url, Branches, CloneOptions, OwnerReport, Requester, Status},
+        rate_limit::ClientIp,
+        repository::RepositoryProvider,
+    },
+    openapi::{BranchPath, CloneQuery, LookupProblems, OwnerPath, RepositoryPath, Routes},
+    preview::{inject_head, open_graph_tags},
+    problem::{Problem, ProblemDetails},
 };
 use axum::{
     body::Body,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::{IntoResponse, Response},
     routing::get,
-    Json, Router,
 };
 use chrono::{DateTime, Utc};
 use hyper::{
@@ -18,42 +26,44 @@ use hyper::{
 use mime_guess::mime::{APPLICATION_JSON, TEXT_PLAIN};
 use serde_json::json;
 use snafu::{ResultExt, Snafu};
-use std::time::Duration;
-
-pub fn create_api_router(provider: RepositoryProvider) -> Router {
-    Router::new()
-        .route("/:owner/:repo", get(default_branch_info))
-        .route("/:owner/:repo/src/branch/*branch", get(branch_commit_info))
-        .route("/:owner/:repo/tree/*branch", get(branch_commit_info))
-        .route("/:owner/:repo/-/tree/*branch", get(branch_commit_info))
-        .route("/:owner/:repo/src/*branch", get(branch_commit_info))
-        .route("/:owner/:repo/branches", get(all_branches_lookup))
-        .route("/project/:owner/:repo", get(default_branch_info))
-        .route(
-            "/project/:owner/:repo/tree/*branch",
-            get(branch_commit_info),
-        )
-        .route("/project/:owner/:repo/branches", get(all_branches_lookup))
-        .with_state(provider)
+use std::{collections::HashMap, time::Duration};
+
+/// Paths of a repository, GitLab subgroups are reached through `project`.
+pub const REPOSITORY_PATHS: [&str; 2] = ["/:owner/:repo", "/project/:owner/:repo"];
+/// Paths of a branch as the forges link them, the first one is the documented form.
+pub const BRANCH_PATHS: [&str; 5] = [
+    "/:owner/:repo/tree/*branch",
+    "/:owner/:repo/-/tree/*branch",
+    "/:owner/:repo/src/*branch",
+    "/:owner/:repo/src/branch/*branch",
+    "/project/:owner/:repo/tree/*branch",
+];
+
+pub fn api_routes() -> Routes<RepositoryProvider> {
+    let routes = Routes::new().route("/:owner", get(owner_report).post(queue_owner));
+    let routes = REPOSITORY_PATHS.iter().fold(routes, |routes, path| {
+        routes
+            .route(path, get(default_branch_info))
+            .route(&format!("{path}/branches"), get(all_branches_lookup))
+    });
+    BRANCH_PATHS.iter().fold(routes, |routes, path| {
+        routes.route(path, get(branch_commit_info))
+    })
 }
 
-pub fn create_general_router(provider: RepositoryProvider) -> Router {
-    Router::new()
-        .route("/:owner/:repo", get(default_handler))
-        .route("/:owner/:repo/src/branch/*branch", get(handler_with_branch))
-        .route("/:owner/:repo/tree/*branch", get(handler_with_branch))
-        .route("/:owner/:repo/-/tree/*branch", get(handler_with_branch))
-        .route("/:owner/:repo/src/*branch", get(handler_with_branch))
-        .route("/project/:owner/:repo", get(default_handler))
-        .route(
-            "/project/:owner/:repo/tree/*branch",
-            get(handler_with_branch),
-        )
-        .with_state(provider)
+pub fn general_routes() -> Routes<RepositoryProvider> {
+    let routes = Routes::new().route("/:owner", get(owner_handler));
+    let routes = REPOSITORY_PATHS.iter().fold(routes, |routes, path| {
+        routes.route(path, get(default_handler))
+    });
+    BRANCH_PATHS.iter().fold(routes, |routes, path| {
+        routes.route(path, get(handler_with_branch))
+    })
 }
 
-fn static_page() -> Result<Response<Body>, Error> {
-    let buffer = std::fs::read("dist/info.html").context(TemplatePageIoSnafu)?;
+fn static_page(path: &str, head: &str) -> Result<Response<Body>, Error> {
+    let buffer = std::fs::read(path).context(TemplatePageIoSnafu)?;
+    let buffer = inject_head(buffer, head);
     Ok((
         [
             ("Cache-Control", "no-cache,private,max-age=0"),
@@ -67,6 +77,21 @@ fn static_page() -> Result<Response<Body>, Error> {
 // Если нет в БД актуальной информации:
 // Начать клонировать параллельно репозиторий.
 // Ответить клиенту через 99 секунд 102, а затем через 99 секунд ответ 202
+/// Page of a repository or, with `If-Match: cloc`, its line counts.
+#[utoipa::path(
+    get,
+    path = "/{host}/{owner}/{repo}",
+    tag = "analyses",
+    params(RepositoryPath, CloneQuery, ("If-Match" = Option<String>, Header, description = "`cloc` to get the line counts instead of the page")),
+    responses(
+        (status = 200, description = "The page without `If-Match`, the scc output with it", content((String = "text/html"), (String = "text/plain"))),
+        (status = 202, description = "Analysis started, follow it on the progress stream"),
+        (status = 206, description = "Result of an earlier commit while the current one is analysed", body = Status),
+        (status = 400, description = "`If-Match` without `cloc` or an invalid callback", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "The analysis failed, with the status it failed with", body = ProblemDetails, content_type = "application/problem+json"),
+        LookupProblems,
+    )
+)]
 async fn default_handler(
     Path((host, owner, mut repository_name)): Path<(String, String, String)>,
     state: State<RepositoryProvider>,
@@ -79,6 +104,21 @@ async fn default_handler(
     handle_repository(host, owner, repository_name, None, state, request).await
 }
 
+/// Page of a branch or, with `If-Match: cloc`, its line counts.
+#[utoipa::path(
+    get,
+    path = "/{host}/{owner}/{repo}/tree/{branch}",
+    tag = "analyses",
+    params(BranchPath, CloneQuery, ("If-Match" = Option<String>, Header, description = "`cloc` to get the line counts instead of the page")),
+    responses(
+        (status = 200, description = "The page without `If-Match`, the scc output with it", content((String = "text/html"), (String = "text/plain"))),
+        (status = 202, description = "Analysis started, follow it on the progress stream"),
+        (status = 206, description = "Result of an earlier commit while the current one is analysed", body = Status),
+        (status = 400, description = "`If-Match` without `cloc` or an invalid callback", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 500, description = "The analysis failed, with the status it failed with", body = ProblemDetails, content_type = "application/problem+json"),
+        LookupProblems,
+    )
+)]
 async fn handler_with_branch(
     Path((host, owner, mut repository_name, branch_name)): Path<(String, String, String, String)>,
     state: State<RepositoryProvider>,
@@ -114,9 +154,17 @@ async fn handle_repository(
     request: Request<Body>,
 ) -> Result<Response<Body>, Error> {
     let user_agent = extract_user_agent(&request);
+    let callback = extract_callback(&request);
+
+    if callback
+        .as_deref()
+        .is_some_and(|url| !Notifier::is_valid_url(url))
+    {
+        return Err(Error::InvalidCallback);
+    }
 
     if is_terminal_browser(&user_agent) {
-        terminal_browser(host, owner, name, branch, user_agent, provider).await
+        terminal_browser(host, owner, name, branch, user_agent, provider, request).await
     } else {
         regular(host, owner, name, branch, user_agent, provider, request).await
     }
@@ -133,22 +181,19 @@ async fn regular(
 ) -> Result<Response<Body>, Error> {
     match request.headers().get(header::IF_MATCH) {
         Some(value) => {
-            let value = match value.to_str() {
-                Ok(v) => v,
-                Err(e) => {
-                    return Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .body(Body::from(e.to_string()))
-                        .context(ResponseSnafu)
-                }
-            };
+            let value = value.to_str().map_err(|_| Error::IfMatchError)?;
 
             if value.contains("cloc") {
+                let requester = requester(user_agent, &request);
+                let options = extract_clone_options(&request);
                 let (unique_name, status) = state
-                    .request_info(host, owner, name, branch, user_agent)
+                    .request_info_for(host, owner, name, branch, options, requester)
                     .await
                     .context(GithubProviderSnafu)?;
                 tracing::warn!("After request_info {unique_name}, {}", status);
+                if let Some(callback) = extract_callback(&request) {
+                    state.register_callback(&unique_name, callback);
+                }
 
                 let response = match status {
                     Status::Done(scc_output) => Response::builder()
@@ -174,10 +219,7 @@ async fn regular(
                         .header("Connection", "Upgrade")
                         .body(Body::empty())
                         .context(ResponseSnafu)?,
-                    Status::Error(e) => Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from(e))
-                        .context(ResponseSnafu)?,
+                    Status::Error(failure) => Problem::from(&failure).into_response(),
                     Status::Previous { date, commit, data } => {
                         let json = serde_json::to_string(&Status::Previous { date, commit, data })
                             .context(SerializeStatusSnafu)?;
@@ -191,14 +233,13 @@ async fn regular(
                 };
                 Ok(response)
             } else {
-                Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .header(CONTENT_TYPE, TEXT_PLAIN.essence_str())
-                    .body(Body::from("Unsupported If-Match header"))
-                    .context(ResponseSnafu)
+                Err(Error::IfMatchError)
             }
         }
-        None => static_page(),
+        None => static_page(
+            "dist/info.html",
+            &open_graph_tags(request.uri().path(), &owner, &name),
+        ),
     }
 }
 
@@ -209,12 +250,18 @@ async fn terminal_browser(
     branch: Option<String>,
     user_agent: String,
     repository_provider: RepositoryProvider,
+    request: Request<Body>,
 ) -> Result<Response<Body>, Error> {
     tracing::info!("Terminal browser: {:?}", user_agent);
+    let requester = requester(user_agent, &request);
+    let options = extract_clone_options(&request);
     let (unique_name, status) = repository_provider
-        .request_info(host, owner, name, branch, user_agent)
+        .request_info_for(host, owner, name, branch, options, requester)
         .await
         .context(GithubProviderSnafu)?;
+    if let Some(callback) = extract_callback(&request) {
+        repository_provider.register_callback(&unique_name, callback);
+    }
 
     tracing::debug!("After request_info for {unique_name}: {}", status);
 
@@ -237,12 +284,7 @@ async fn terminal_browser(
                 Status::InProgress(_) => {}
                 Status::Cloned => {}
                 Status::Ready => {}
-                Status::Error(e) => {
-                    break Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from(e))
-                        .context(ResponseSnafu)?;
-                }
+                Status::Error(failure) => break Problem::from(&failure).into_response(),
                 Status::Previous {date, commit, data} => break Response::builder()
                 .status(StatusCode::PARTIAL_CONTENT)
                 .header(CONTENT_TYPE, TEXT_PLAIN.essence_str())
@@ -280,7 +322,36 @@ fn is_terminal_browser(user_agent: &str) -> bool {
         || user_agent.contains("Wget")
 }
 
-fn extract_user_agent(request: &Request<Body>) -> String {
+/// Git credential from the headers, API key accepted by `api_key_middleware`
+/// and client address from `rate_limit_middleware`.
+pub(crate) fn requester(user_agent: String, request: &Request<Body>) -> Requester {
+    Requester {
+        user_agent,
+        credential: Credential::from_headers(request.headers()),
+        api_key: request.extensions().get::<ApiKey>().cloned(),
+        client_ip: request
+            .extensions()
+            .get::<ClientIp>()
+            .map(|ClientIp(ip)| *ip),
+        service: false,
+    }
+}
+
+/// Url from the `callback` query parameter, notified when the analysis finishes.
+fn extract_callback(request: &Request<Body>) -> Option<String> {
+    Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        .ok()
+        .and_then(|Query(mut query)| query.remove("callback"))
+}
+
+/// `paths` and `submodules` query parameters, invalid ones are ignored.
+fn extract_clone_options(request: &Request<Body>) -> CloneOptions {
+    Query::<CloneOptions>::try_from_uri(request.uri())
+        .map(|Query(options)| options)
+        .unwrap_or_default()
+}
+
+pub(crate) fn extract_user_agent(request: &Request<Body>) -> String {
     match request.headers().get(USER_AGENT) {
         Some(value) => match value.to_str() {
             Ok(value) => value,
@@ -293,10 +364,114 @@ fn extract_user_agent(request: &Request<Body>) -> String {
     }
     .to_string()
 }
+/// Page of an owner or, for terminals, its report as text.
+#[utoipa::path(
+    get,
+    path = "/{host}/{owner}",
+    tag = "analyses",
+    params(OwnerPath),
+    responses(
+        (status = 200, description = "The page, the report as text for terminals", content((String = "text/html"), (String = "text/plain"))),
+        LookupProblems,
+    )
+)]
+async fn owner_handler(
+    Path((host, owner)): Path<(String, String)>,
+    State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    if !is_terminal_browser(&extract_user_agent(&request)) {
+        return static_page("dist/owner.html", "");
+    }
+
+    let report = provider
+        .owner_report(&host, &owner)
+        .await
+        .context(GithubProviderSnafu)?;
+    Response::builder()
+        .header(CONTENT_TYPE, TEXT_PLAIN.essence_str())
+        .body(Body::from(report.to_string()))
+        .context(ResponseSnafu)
+}
+
+/// Stored results of the public repositories of an owner, nothing is analysed.
+#[utoipa::path(
+    get,
+    path = "/api/{host}/{owner}",
+    tag = "repositories",
+    params(OwnerPath),
+    responses(
+        (status = 200, description = "Line counts over the analysed repositories of the owner", body = OwnerReport),
+        (status = 400, description = "Owner name outside `[A-Za-z0-9._-]`", body = ProblemDetails, content_type = "application/problem+json"),
+        LookupProblems,
+    )
+)]
+async fn owner_report(
+    Path((host, owner)): Path<(String, String)>,
+    State(provider): State<RepositoryProvider>,
+) -> Result<Response<Body>, Error> {
+    tracing::debug!("owner_report() host: {host}, owner: {owner}");
+    let report = provider
+        .owner_report(&host, &owner)
+        .await
+        .context(GithubProviderSnafu)?;
+
+    owner_report_response(&report)
+}
+
+/// Queues the default branches of all repositories of an owner, requires an API key.
+#[utoipa::path(
+    post,
+    path = "/api/{host}/{owner}",
+    tag = "repositories",
+    params(OwnerPath, ("X-Api-Key" = String, Header, description = "API key of the client")),
+    responses(
+        (status = 200, description = "The report right after queueing", body = OwnerReport),
+        (status = 400, description = "Owner name outside `[A-Za-z0-9._-]`", body = ProblemDetails, content_type = "application/problem+json"),
+        LookupProblems,
+        (status = 401, description = "No API key", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn queue_owner(
+    Path((host, owner)): Path<(String, String)>,
+    State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Error> {
+    let requester = requester(extract_user_agent(&request), &request);
+    if requester.api_key.is_none() {
+        return Err(Error::ApiKeyRequired);
+    }
+    tracing::debug!("queue_owner() host: {host}, owner: {owner}");
+    let report = provider
+        .queue_owner(&host, &owner, &requester)
+        .await
+        .context(GithubProviderSnafu)?;
+
+    owner_report_response(&report)
+}
+
+fn owner_report_response(report: &OwnerReport) -> Result<Response<Body>, Error> {
+    let json = serde_json::to_string(report).context(SerializeStatusSnafu)?;
+    Response::builder()
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .body(Body::from(json))
+        .context(ResponseSnafu)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/{host}/{owner}/{repo}/branches",
+    tag = "repositories",
+    params(RepositoryPath),
+    responses(
+        (status = 200, description = "Branches and tags with their commits", body = Branches),
+        LookupProblems,
+    )
+)]
 async fn all_branches_lookup(
     Path((host, owner, mut repository_name)): Path<(String, String, String)>,
     State(provider): State<RepositoryProvider>,
-    _request: Request<Body>,
+    request: Request<Body>,
 ) -> Result<Response<Body>, Error> {
     tracing::warn!("all_branches_lookup() host: {host}, owner: {owner}, repo: {repository_name}");
     if host != "git.sr.ht" && !repository_name.ends_with(".git") {
@@ -304,55 +479,71 @@ async fn all_branches_lookup(
     }
     let url = to_url(&host, &owner, &repository_name);
     let branches_info = provider
-        .remote_branches(&url)
+        .remote_branches(
+            &host,
+            &url,
+            &requester(extract_user_agent(&request), &request),
+        )
         .await
         .with_context(|_e| GithubProviderSnafu)?;
 
-    match serde_json::to_string(&branches_info) {
-        Ok(branches) => Response::builder()
-            .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
-            .body(Body::from(branches))
-            .context(ResponseSnafu),
-        Err(e) => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .header(CONTENT_TYPE, TEXT_PLAIN.essence_str())
-            .body(Body::from(e.to_string()))
-            .context(ResponseSnafu),
-    }
+    let branches = serde_json::to_string(&branches_info).context(SerializeStatusSnafu)?;
+    Response::builder()
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .body(Body::from(branches))
+        .context(ResponseSnafu)
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/{host}/{owner}/{repo}",
+    tag = "repositories",
+    params(RepositoryPath),
+    responses(
+        (status = 200, description = "Default branch", body = inline(Object), example = json!({"default_branch": "main"})),
+        LookupProblems,
+    )
+)]
 async fn default_branch_info(
     Path((host, owner, mut repository_name)): Path<(String, String, String)>,
     State(provider): State<RepositoryProvider>,
-    _request: Request<Body>,
+    request: Request<Body>,
 ) -> Result<Response<Body>, Error> {
     tracing::debug!("default_branch_info() host: {host}, owner: {owner}, repo: {repository_name}");
     if host != "git.sr.ht" && !repository_name.ends_with(".git") {
         repository_name = format!("{repository_name}.git");
     }
     let default_branch = provider
-        .default_branch_remote(&host, &owner, &repository_name)
-        .await;
-
-    match default_branch {
-        Ok(default_branch) => {
-            let json = json!({ "default_branch": default_branch });
-            Response::builder()
-                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
-                .body(Body::from(json.to_string()))
-                .context(ResponseSnafu)
-        }
-        Err(e) => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .header(CONTENT_TYPE, TEXT_PLAIN.essence_str())
-            .body(Body::from(e.to_string()))
-            .context(ResponseSnafu),
-    }
+        .default_branch_remote(
+            &host,
+            &owner,
+            &repository_name,
+            &requester(extract_user_agent(&request), &request),
+        )
+        .await
+        .context(GithubProviderSnafu)?;
+
+    let json = json!({ "default_branch": default_branch });
+    Response::builder()
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .body(Body::from(json.to_string()))
+        .context(ResponseSnafu)
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/{host}/{owner}/{repo}/tree/{branch}",
+    tag = "repositories",
+    params(BranchPath),
+    responses(
+        (status = 200, description = "Last commit of the branch", body = inline(Object), example = json!({"commit": "0123456789abcdef0123456789abcdef01234567"})),
+        LookupProblems,
+    )
+)]
 async fn branch_commit_info(
     Path((host, owner, mut repository_name, branch)): Path<(String, String, String, String)>,
     State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
 ) -> Result<Response<Body>, Error> {
     tracing::info!("branch_commit_info() host: {host}, owner: {owner}, repo: {repository_name}, branch: {branch}");
     if host != "git.sr.ht" && !repository_name.ends_with(".git") {
@@ -364,22 +555,21 @@ async fn branch_commit_info(
         &branch
     };
     let commit = provider
-        .last_commit_remote(&host, &owner, &repository_name, branch)
-        .await;
-    match commit {
-        Ok(commit) => {
-            let json = json!({ "commit": commit });
-            Response::builder()
-                .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
-                .body(Body::from(json.to_string()))
-                .context(ResponseSnafu)
-        }
-        Err(e) => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
-            .body(Body::from(e.to_string()))
-            .context(ResponseSnafu),
-    }
+        .last_commit_remote(
+            &host,
+            &owner,
+            &repository_name,
+            branch,
+            &requester(extract_user_agent(&request), &request),
+        )
+        .await
+        .context(GithubProviderSnafu)?;
+
+    let json = json!({ "commit": commit });
+    Response::builder()
+        .header(CONTENT_TYPE, APPLICATION_JSON.essence_str())
+        .body(Body::from(json.to_string()))
+        .context(ResponseSnafu)
 }
 
 #[derive(Debug, Snafu)]
@@ -405,6 +595,12 @@ pub enum Error {
     #[snafu(display("Unrecognized If-Match header"))]
     IfMatchError,
 
+    #[snafu(display("Callback must be an absolute http(s) url"))]
+    InvalidCallback,
+
+    #[snafu(display("An API key is required"))]
+    ApiKeyRequired,
+
     #[snafu(display("Error at cloning repository or scc: {source}"))]
     DownloaderError { source: logic::Error },
 
@@ -412,18 +608,60 @@ pub enum Error {
     GithubProviderError { source: logic::Error },
 }
 
-impl IntoResponse for Error {
-    fn into_response(self) -> Response {
-        let msg = self.to_string();
-        let status = StatusCode::INTERNAL_SERVER_ERROR;
+impl Error {
+    fn source_error(&self) -> Option<&logic::Error> {
+        match self {
+            Error::DownloaderError { source } | Error::GithubProviderError { source } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
 
-        tracing::error!("{msg}");
+    /// Stable machine-readable identifier returned as `code` in problem bodies.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::WrongBranch { .. } => "branch_not_found",
+            Error::IfMatchError => "invalid_if_match",
+            Error::InvalidCallback => "invalid_callback",
+            Error::ApiKeyRequired => "api_key_required",
+            Error::TemplatePage => "page_not_found",
+            Error::DownloaderError { source } | Error::GithubProviderError { source } => {
+                source.code()
+            }
+            _ => "internal_error",
+        }
+    }
 
-        let body = Json(json!({
-            "error": msg,
-        }));
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::WrongBranch { .. } | Error::TemplatePage => StatusCode::NOT_FOUND,
+            Error::IfMatchError | Error::InvalidCallback => StatusCode::BAD_REQUEST,
+            Error::ApiKeyRequired => StatusCode::UNAUTHORIZED,
+            Error::DownloaderError { source } | Error::GithubProviderError { source } => {
+                source.status()
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
-        (status, body).into_response()
+impl From<&Error> for Problem {
+    fn from(error: &Error) -> Self {
+        match error.source_error() {
+            // The source carries `Retry-After`
+            Some(source) => Problem {
+                detail: error.to_string(),
+                ..Problem::from(source)
+            },
+            None => Problem::new(error.status(), error.code(), error.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        Problem::from(&self).into_response()
     }
 }
 
@@ -441,13 +679,62 @@ fn message_for_previous_result(date: DateTime<Utc>, commit: String, data: Vec<u8
 
 #[cfg(test)]
 mod tests {
-    use super::extract_user_agent;
-    use axum::body::Body;
+    use super::{extract_callback, extract_clone_options, extract_user_agent, Error};
+    use crate::{logic, problem::Problem};
+    use axum::{body::Body, response::IntoResponse};
     use hyper::{
         header::{HeaderValue, USER_AGENT},
-        Request,
+        Request, StatusCode,
     };
 
+    #[test]
+    fn git_failures_map_to_status_and_code() {
+        let url = "https://example.com/org/repo.git".to_string();
+        let cases = [
+            (
+                logic::Error::RepositoryNotFound { url: url.clone() },
+                StatusCode::NOT_FOUND,
+                "repository_not_found",
+            ),
+            (
+                logic::Error::AuthenticationRequired { url: url.clone() },
+                StatusCode::UNAUTHORIZED,
+                "authentication_required",
+            ),
+            (
+                logic::Error::RemoteUnreachable {
+                    url: url.clone(),
+                    message: "Could not resolve host".to_string(),
+                },
+                StatusCode::BAD_GATEWAY,
+                "remote_unreachable",
+            ),
+            (
+                logic::Error::RemoteTimeout { url },
+                StatusCode::GATEWAY_TIMEOUT,
+                "remote_timeout",
+            ),
+            (
+                logic::Error::CloneTooLarge {
+                    repository: "repo".to_string(),
+                    limit: 1,
+                },
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "repository_too_large",
+            ),
+        ];
+
+        for (source, status, code) in cases {
+            // Failures of queued tasks are reported the same way
+            let failure = Problem::from(&source.failure());
+            assert_eq!((failure.status, failure.code.as_ref()), (status, code));
+
+            let error = Error::GithubProviderError { source };
+            assert_eq!(error.code(), code);
+            assert_eq!(error.into_response().status(), status);
+        }
+    }
+
     #[test]
     fn invalid_user_agent_falls_back_to_unknown() {
         let request = Request::builder()
@@ -457,4 +744,39 @@ mod tests {
 
         assert_eq!(extract_user_agent(&request), "unknown");
     }
+
+    #[test]
+    fn callback_is_read_from_query() {
+        let request = Request::builder()
+            .uri("/github.com/owner/repo?callback=https%3A%2F%2Fci.example.com%2Fhook")
+            .body(Body::empty())
+            .unwrap();
+
+        assert_eq!(
+            extract_callback(&request).as_deref(),
+            Some("https://ci.example.com/hook")
+        );
+    }
+
+    #[test]
+    fn clone_options_are_read_from_query() {
+        let request = Request::builder()
+            .uri("/github.com/owner/repo?paths=src/,docs,../etc,src&submodules=true&callback=x")
+            .body(Body::empty())
+            .unwrap();
+        let options = extract_clone_options(&request);
+
+        assert_eq!(options.paths, ["docs", "src"]);
+        assert!(options.submodules);
+        assert_eq!(
+            options.to_unique_name("github.com/owner/repo/main"),
+            "github.com/owner/repo/main?paths=docs,src&submodules=true"
+        );
+
+        let request = Request::builder()
+            .uri("/github.com/owner/repo")
+            .body(Body::empty())
+            .unwrap();
+        assert!(extract_clone_options(&request).is_default());
+    }
 }
@@ -1,5 +1,13 @@
+pub mod analysis;
 pub mod application;
+pub mod badge;
+pub mod cli;
 pub mod handlers;
 pub mod logic;
+pub mod openapi;
+pub mod preview;
+pub mod problem;
 pub mod statistic;
+pub mod upload;
+pub mod webhook;
 pub mod websocket;
@@ -0,0 +1,335 @@
+use super::{Error, Id, QuerySnafu};
+use bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
+use chrono::{DateTime, Duration, Utc};
+use hyper::{header::AUTHORIZATION, HeaderMap};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+use tokio_postgres::{GenericClient, NoTls};
+
+pub use cloc_api::headers::API_KEY_HEADER;
+
+const KEY_PREFIX: &str = "cloc_";
+const KEY_LENGTH: usize = 40;
+
+/// Limits of a key, `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub analyses_per_hour: Option<u32>,
+    /// Total size of all repositories cloned with the key.
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKey {
+    pub id: Id,
+    pub name: String,
+    pub quota: Quota,
+}
+
+/// Consumption of a key: analyses started during the last hour and bytes cloned overall.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub analyses: u32,
+    /// Start of the oldest analysis within the last hour, its slot is freed an hour later.
+    pub oldest: Option<DateTime<Utc>>,
+    pub bytes: u64,
+}
+
+/// An analysis counted against a key: the usage row for `record_bytes` and the bytes
+/// the key may still clone, `None` is unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Charge {
+    pub usage_id: Id,
+    pub max_bytes: Option<u64>,
+}
+
+impl Usage {
+    pub fn check(&self, quota: &Quota) -> Result<(), Error> {
+        if quota
+            .analyses_per_hour
+            .is_some_and(|limit| self.analyses >= limit)
+        {
+            return Err(Error::QuotaExceeded {
+                desc: format!(
+                    "API key quota of {} analyses per hour is exhausted",
+                    quota.analyses_per_hour.unwrap_or_default()
+                ),
+            });
+        }
+        if quota.max_bytes.is_some_and(|limit| self.bytes >= limit) {
+            return Err(Error::QuotaExceeded {
+                desc: format!(
+                    "API key quota of {} cloned bytes is exhausted",
+                    quota.max_bytes.unwrap_or_default()
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Bytes left of the `quota`, `None` is unlimited.
+    pub fn remaining_bytes(&self, quota: &Quota) -> Option<u64> {
+        quota
+            .max_bytes
+            .map(|limit| limit.saturating_sub(self.bytes))
+    }
+
+    /// `X-RateLimit-*` headers describing the limits of `quota` that are set.
+    pub fn headers(&self, quota: &Quota, now: DateTime<Utc>) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::with_capacity(5);
+        if let Some(limit) = quota.analyses_per_hour {
+            let reset = self
+                .oldest
+                .map(|oldest| (oldest + Duration::hours(1) - now).num_seconds().max(0))
+                .unwrap_or_default();
+            headers.push(("X-RateLimit-Limit", limit.to_string()));
+            headers.push((
+                "X-RateLimit-Remaining",
+                limit.saturating_sub(self.analyses).to_string(),
+            ));
+            headers.push(("X-RateLimit-Reset", reset.to_string()));
+        }
+        if let (Some(limit), Some(remaining)) = (quota.max_bytes, self.remaining_bytes(quota)) {
+            headers.push(("X-RateLimit-Bytes-Limit", limit.to_string()));
+            headers.push(("X-RateLimit-Bytes-Remaining", remaining.to_string()));
+        }
+        headers
+    }
+}
+
+/// New random key, only its hash is stored.
+pub fn generate_key() -> String {
+    let random = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(KEY_LENGTH)
+        .map(char::from)
+        .collect::<String>();
+    format!("{KEY_PREFIX}{random}")
+}
+
+pub fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+/// Reads the key from `X-Api-Key` or `Authorization: Bearer`.
+pub fn key_from_headers(headers: &HeaderMap) -> Option<String> {
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    header(API_KEY_HEADER)
+        .or_else(|| header(AUTHORIZATION.as_str()).and_then(|value| value.strip_prefix("Bearer ")))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+}
+
+/// API keys in the `api_keys` table, their usage in `api_key_usage`.
+#[derive(Clone)]
+pub struct ApiKeys {
+    connection_pool: Pool<PostgresConnectionManager<NoTls>>,
+}
+
+impl ApiKeys {
+    pub fn new(connection_pool: Pool<PostgresConnectionManager<NoTls>>) -> Self {
+        Self { connection_pool }
+    }
+
+    /// Stores a new key and returns it, the plain key can't be recovered later.
+    pub async fn create(&self, name: &str, quota: Quota) -> Result<(ApiKey, String), Error> {
+        let key = generate_key();
+        let connection = self.connection().await?;
+        let query = "insert into api_keys(name, key_hash, analyses_per_hour, max_bytes) \
+                     values($1, $2, $3, $4) returning id";
+        let analyses_per_hour = quota.analyses_per_hour.map(|limit| limit as i32);
+        let max_bytes = quota.max_bytes.map(|limit| limit as i64);
+        let row = connection
+            .query_one(
+                query,
+                &[&name, &hash_key(&key), &analyses_per_hour, &max_bytes],
+            )
+            .await
+            .context(QuerySnafu { query })?;
+
+        let api_key = ApiKey {
+            id: row.get("id"),
+            name: name.to_string(),
+            quota,
+        };
+        Ok((api_key, key))
+    }
+
+    /// Returns `false` if there is no active key with `id`.
+    pub async fn revoke(&self, id: Id) -> Result<bool, Error> {
+        let connection = self.connection().await?;
+        let query = "update api_keys set revoked=now() where id=$1 and revoked is null";
+        let modified = connection
+            .execute(query, &[&id])
+            .await
+            .context(QuerySnafu { query })?;
+        Ok(modified > 0)
+    }
+
+    /// Active key matching `key`.
+    pub async fn find(&self, key: &str) -> Result<Option<ApiKey>, Error> {
+        let connection = self.connection().await?;
+        let query = "select id, name, analyses_per_hour, max_bytes from api_keys \
+                     where key_hash=$1 and revoked is null";
+        let row = connection
+            .query_opt(query, &[&hash_key(key)])
+            .await
+            .context(QuerySnafu { query })?;
+
+        Ok(row.map(|row| {
+            let analyses_per_hour: Option<i32> = row.get("analyses_per_hour");
+            let max_bytes: Option<i64> = row.get("max_bytes");
+            ApiKey {
+                id: row.get("id"),
+                name: row.get("name"),
+                quota: Quota {
+                    analyses_per_hour: analyses_per_hour.map(|limit| limit.max(0) as u32),
+                    max_bytes: max_bytes.map(|limit| limit.max(0) as u64),
+                },
+            }
+        }))
+    }
+
+    pub async fn usage(&self, api_key: &ApiKey) -> Result<Usage, Error> {
+        let connection = self.connection().await?;
+        query_usage(&*connection, api_key.id).await
+    }
+
+    /// Counts a new analysis if the quota allows it.
+    pub async fn start_analysis(&self, api_key: &ApiKey) -> Result<Charge, Error> {
+        let mut connection = self.connection().await?;
+        let query = "select pg_advisory_xact_lock($1)";
+        let transaction = connection
+            .transaction()
+            .await
+            .context(QuerySnafu { query })?;
+        // Concurrent requests with the same key must not both take the last slot
+        transaction
+            .execute(query, &[&api_key.id])
+            .await
+            .context(QuerySnafu { query })?;
+
+        let usage = query_usage(&transaction, api_key.id).await?;
+        usage.check(&api_key.quota)?;
+
+        let query = "insert into api_key_usage(api_key_id) values($1) returning id";
+        let row = transaction
+            .query_one(query, &[&api_key.id])
+            .await
+            .context(QuerySnafu { query })?;
+        transaction.commit().await.context(QuerySnafu { query })?;
+
+        Ok(Charge {
+            usage_id: row.get("id"),
+            max_bytes: usage.remaining_bytes(&api_key.quota),
+        })
+    }
+
+    /// Records the size of the repository cloned for an analysis.
+    pub async fn record_bytes(&self, usage_id: Id, bytes: u64) -> Result<(), Error> {
+        let connection = self.connection().await?;
+        let query = "update api_key_usage set bytes=$2 where id=$1";
+        connection
+            .execute(query, &[&usage_id, &(bytes as i64)])
+            .await
+            .context(QuerySnafu { query })?;
+        Ok(())
+    }
+
+    async fn connection(
+        &self,
+    ) -> Result<bb8::PooledConnection<'_, PostgresConnectionManager<NoTls>>, Error> {
+        self.connection_pool
+            .get()
+            .await
+            .map_err(|error| Error::ConnectionPool {
+                error: error.to_string(),
+            })
+    }
+}
+
+async fn query_usage(client: &impl GenericClient, api_key_id: Id) -> Result<Usage, Error> {
+    let query = "select \
+                 count(*) filter (where time > now() - interval '1 hour') as analyses, \
+                 min(time) filter (where time > now() - interval '1 hour') as oldest, \
+                 coalesce(sum(bytes), 0)::bigint as bytes \
+                 from api_key_usage where api_key_id=$1";
+    let row = client
+        .query_one(query, &[&api_key_id])
+        .await
+        .context(QuerySnafu { query })?;
+
+    let analyses: i64 = row.get("analyses");
+    let bytes: i64 = row.get("bytes");
+    Ok(Usage {
+        analyses: analyses.clamp(0, u32::MAX as i64) as u32,
+        oldest: row.get("oldest"),
+        bytes: bytes.max(0) as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_key, hash_key, key_from_headers, Quota, Usage};
+    use chrono::{Duration, Utc};
+    use hyper::{header::AUTHORIZATION, HeaderMap};
+
+    #[test]
+    fn quota_is_checked_and_reported() {
+        let now = Utc::now();
+        let quota = Quota {
+            analyses_per_hour: Some(2),
+            max_bytes: Some(1000),
+        };
+        let usage = Usage {
+            analyses: 1,
+            oldest: Some(now - Duration::minutes(45)),
+            bytes: 400,
+        };
+        assert!(usage.check(&quota).is_ok());
+        assert_eq!(usage.remaining_bytes(&quota), Some(600));
+        assert_eq!(
+            usage.headers(&quota, now),
+            vec![
+                ("X-RateLimit-Limit", "2".to_string()),
+                ("X-RateLimit-Remaining", "1".to_string()),
+                ("X-RateLimit-Reset", "900".to_string()),
+                ("X-RateLimit-Bytes-Limit", "1000".to_string()),
+                ("X-RateLimit-Bytes-Remaining", "600".to_string()),
+            ]
+        );
+
+        let exhausted = Usage {
+            analyses: 2,
+            ..usage
+        };
+        assert!(exhausted.check(&quota).is_err());
+        let too_large = Usage {
+            bytes: 1000,
+            ..usage
+        };
+        assert!(too_large.check(&quota).is_err());
+        assert_eq!(too_large.remaining_bytes(&quota), Some(0));
+        assert!(too_large.check(&Quota::default()).is_ok());
+        assert_eq!(too_large.remaining_bytes(&Quota::default()), None);
+        assert!(too_large.headers(&Quota::default(), now).is_empty());
+    }
+
+    #[test]
+    fn key_is_read_from_headers() {
+        let key = generate_key();
+        assert!(key.starts_with("cloc_"));
+        assert_ne!(hash_key(&key), key);
+        assert_ne!(key, generate_key());
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(key_from_headers(&headers), None);
+        headers.insert(AUTHORIZATION, format!("Bearer {key}").parse().unwrap());
+        assert_eq!(key_from_headers(&headers), Some(key.clone()));
+        headers.insert("X-Api-Key", "cloc_other".parse().unwrap());
+        assert_eq!(key_from_headers(&headers).as_deref(), Some("cloc_other"));
+    }
+}
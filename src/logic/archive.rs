@@ -0,0 +1,487 @@
+use super::Error;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Seek},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveKind {
+    pub fn detect(file_name: &str) -> Option<Self> {
+        let name = file_name.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else {
+            None
+        }
+    }
+}
+
+/// Limits of everything extracted for one upload, whatever the number of archives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveLimits {
+    /// Maximum total size of extracted files.
+    pub max_bytes: u64,
+    /// Maximum number of entries (files and directories).
+    pub max_entries: usize,
+}
+
+/// Bytes and entries extracted so far for one upload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Extracted {
+    pub bytes: u64,
+    pub entries: usize,
+}
+
+impl ArchiveLimits {
+    /// Reads `ARCHIVE_MAX_BYTES` (default 1 GiB) and `ARCHIVE_MAX_ENTRIES` (default 100 000).
+    pub fn from_env() -> Self {
+        let max_bytes = std::env::var("ARCHIVE_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1024 * 1024 * 1024);
+        let max_entries = std::env::var("ARCHIVE_MAX_ENTRIES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(100_000);
+
+        Self {
+            max_bytes,
+            max_entries,
+        }
+    }
+
+    pub fn check(&self, extracted: Extracted) -> Result<(), Error> {
+        if extracted.entries > self.max_entries {
+            return Err(Error::TooManyArchiveEntries {
+                limit: self.max_entries,
+            });
+        }
+        if extracted.bytes > self.max_bytes {
+            return Err(Error::ArchiveTooLarge {
+                limit: self.max_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reader failing once `cancelled` is set, so that a large entry stops being written too.
+struct Cancellable<'a, R> {
+    reader: R,
+    cancelled: &'a AtomicBool,
+}
+
+impl<R: Read> Read for Cancellable<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(io::Error::other("extraction cancelled"));
+        }
+        self.reader.read(buf)
+    }
+}
+
+/// Sets the flag when dropped, i.e. when the request waiting for the extraction goes away.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Extractor<'a> {
+    destination: &'a Path,
+    limits: ArchiveLimits,
+    extracted: Extracted,
+    cancelled: &'a AtomicBool,
+}
+
+impl Extractor<'_> {
+    /// Validates the entry name and returns its location inside the destination.
+    fn target(&mut self, name: &Path) -> Result<PathBuf, Error> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Err(archive_error("extraction cancelled"));
+        }
+        self.extracted.entries += 1;
+        self.limits.check(self.extracted)?;
+
+        let mut relative = PathBuf::new();
+        for component in name.components() {
+            match component {
+                Component::Normal(value) => relative.push(value),
+                Component::CurDir => {}
+                Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                    return Err(Error::UnsafeArchiveEntry {
+                        path: name.display().to_string(),
+                    })
+                }
+            }
+        }
+
+        if let Some(path) = conflicting_path(self.destination, &relative) {
+            return Err(Error::ConflictingUploadPath { path });
+        }
+        Ok(self.destination.join(relative))
+    }
+
+    /// Directories may be repeated or implied by earlier files, unlike files.
+    fn directory(&mut self, name: &Path) -> Result<(), Error> {
+        let target = self.target(name)?;
+        if target.exists() && !target.is_dir() {
+            return Err(Error::ConflictingUploadPath {
+                path: name.display().to_string(),
+            });
+        }
+        fs::create_dir_all(&target).map_err(archive_error)
+    }
+
+    fn file(&mut self, name: &Path, reader: &mut impl Read) -> Result<(), Error> {
+        let target = self.target(name)?;
+        if target.exists() {
+            return Err(Error::ConflictingUploadPath {
+                path: name.display().to_string(),
+            });
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(archive_error)?;
+        }
+
+        // One byte over the remaining budget is enough to detect an overflow
+        let remaining = self.limits.max_bytes.saturating_sub(self.extracted.bytes);
+        let mut file = File::create(&target).map_err(archive_error)?;
+        let mut reader = Cancellable {
+            reader: reader.take(remaining + 1),
+            cancelled: self.cancelled,
+        };
+        let written = io::copy(&mut reader, &mut file).map_err(archive_error)?;
+
+        self.extracted.bytes += written;
+        self.limits.check(self.extracted)
+    }
+
+    fn zip(&mut self, reader: impl Read + Seek) -> Result<(), Error> {
+        let mut archive = zip::ZipArchive::new(reader).map_err(archive_error)?;
+        self.limits.check(Extracted {
+            entries: self.extracted.entries + archive.len(),
+            ..self.extracted
+        })?;
+
+        for index in 0..archive.len() {
+            let mut file = archive.by_index(index).map_err(archive_error)?;
+            let name = PathBuf::from(file.name());
+            if file.is_symlink() {
+                return Err(Error::UnsafeArchiveEntry {
+                    path: file.name().to_string(),
+                });
+            }
+
+            if file.is_dir() {
+                self.directory(&name)?;
+            } else {
+                self.file(&name, &mut file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn tar(&mut self, reader: impl Read) -> Result<(), Error> {
+        let mut archive = tar::Archive::new(reader);
+
+        for entry in archive.entries().map_err(archive_error)? {
+            let mut entry = entry.map_err(archive_error)?;
+            let name = entry.path().map_err(archive_error)?.into_owned();
+            let kind = entry.header().entry_type();
+
+            if kind.is_dir() {
+                self.directory(&name)?;
+            } else if kind.is_file() || kind == tar::EntryType::Continuous {
+                self.file(&name, &mut entry)?;
+            } else if kind.is_pax_global_extensions()
+                || kind.is_pax_local_extensions()
+                || kind.is_gnu_longname()
+                || kind.is_gnu_longlink()
+            {
+                continue;
+            } else {
+                // Symlinks, hard links, devices and fifos are never extracted
+                return Err(Error::UnsafeArchiveEntry {
+                    path: name.display().to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn archive_error(error: impl std::fmt::Display) -> Error {
+    Error::Archive {
+        error: error.to_string(),
+    }
+}
+
+/// First ancestor of `relative` inside `root`, or `relative` itself, that exists but is not
+/// a directory, i.e. that a file or directory below it would conflict with.
+pub fn conflicting_path(root: &Path, relative: &Path) -> Option<String> {
+    let mut path = root.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        if components.peek().is_none() {
+            break;
+        }
+        path.push(component);
+        if path.exists() && !path.is_dir() {
+            return Some(
+                path.strip_prefix(root)
+                    .unwrap_or(&path)
+                    .display()
+                    .to_string(),
+            );
+        }
+    }
+    None
+}
+
+/// Safely unpacks `archive` into `destination`. `extracted` is what the upload already
+/// extracted, the returned total is checked against `limits`. Stops as soon as
+/// `cancelled` is set.
+pub fn extract(
+    kind: ArchiveKind,
+    archive: &Path,
+    destination: &Path,
+    limits: ArchiveLimits,
+    extracted: Extracted,
+    cancelled: &AtomicBool,
+) -> Result<Extracted, Error> {
+    let file = File::open(archive).map_err(archive_error)?;
+    let mut extractor = Extractor {
+        destination,
+        limits,
+        extracted,
+        cancelled,
+    };
+
+    match kind {
+        ArchiveKind::Zip => extractor.zip(file)?,
+        ArchiveKind::Tar => extractor.tar(file)?,
+        ArchiveKind::TarGz => extractor.tar(flate2::read::GzDecoder::new(file))?,
+        ArchiveKind::TarZst => {
+            extractor.tar(zstd::stream::read::Decoder::new(file).map_err(archive_error)?)?
+        }
+    }
+
+    Ok(extractor.extracted)
+}
+
+/// Runs `extract` off the async runtime. Dropping the future, e.g. when the client
+/// disconnects, stops the extraction.
+pub async fn extract_archive(
+    kind: ArchiveKind,
+    archive: PathBuf,
+    destination: PathBuf,
+    limits: ArchiveLimits,
+    extracted: Extracted,
+) -> Result<Extracted, Error> {
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let _cancel = CancelOnDrop(cancelled.clone());
+    tokio::task::spawn_blocking(move || {
+        extract(kind, &archive, &destination, limits, extracted, &cancelled)
+    })
+    .await
+    .map_err(archive_error)?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, ArchiveKind, ArchiveLimits, Extracted};
+    use crate::logic::Error;
+    use std::{fs::File, io::Write, path::Path, sync::atomic::AtomicBool};
+
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+
+    const LIMITS: ArchiveLimits = ArchiveLimits {
+        max_bytes: 1024,
+        max_entries: 10,
+    };
+
+    fn tar_gz(path: &Path, entries: &[(&str, &[u8])], symlink: Option<&str>) {
+        let file = File::create(path).unwrap();
+        let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            // `set_path` refuses `..`, write the raw name like a malicious archiver would
+            header.as_gnu_mut().unwrap().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_cksum();
+            builder.append(&header, *data).unwrap();
+        }
+        if let Some(name) = symlink {
+            let mut header = tar::Header::new_gnu();
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder
+                .append_link(&mut header, name, "/etc/passwd")
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn extracts_zip_and_tar_gz() {
+        let directory = tempfile::tempdir().unwrap();
+        let zip_path = directory.path().join("code.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        writer
+            .start_file("src/main.rs", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        writer.write_all(b"fn main() {}\n").unwrap();
+        writer.finish().unwrap();
+        let tar_path = directory.path().join("code.tar.gz");
+        tar_gz(&tar_path, &[("lib/a.py", b"print(1)\n")], None);
+
+        let out = directory.path().join("out");
+        assert_eq!(ArchiveKind::detect("Code.ZIP"), Some(ArchiveKind::Zip));
+        let extracted = extract(
+            ArchiveKind::Zip,
+            &zip_path,
+            &out,
+            LIMITS,
+            Extracted::default(),
+            &RUNNING,
+        );
+        assert_eq!(
+            extracted.unwrap(),
+            Extracted {
+                bytes: 13,
+                entries: 1
+            }
+        );
+        // The budget is shared by the archives of an upload
+        let extracted = Extracted {
+            bytes: 1020,
+            entries: 1,
+        };
+        let full = directory.path().join("full");
+        let error = extract(
+            ArchiveKind::TarGz,
+            &tar_path,
+            &full,
+            LIMITS,
+            extracted,
+            &RUNNING,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::ArchiveTooLarge { limit: 1024 }));
+        let extracted = Extracted {
+            bytes: 13,
+            entries: 1,
+        };
+        assert_eq!(
+            extract(
+                ArchiveKind::TarGz,
+                &tar_path,
+                &out,
+                LIMITS,
+                extracted,
+                &RUNNING
+            )
+            .unwrap()
+            .bytes,
+            22
+        );
+        assert!(out.join("src/main.rs").is_file());
+        assert!(out.join("lib/a.py").is_file());
+    }
+
+    #[test]
+    fn rejects_traversal_symlinks_and_bombs() {
+        let directory = tempfile::tempdir().unwrap();
+        let out = directory.path().join("out");
+        let archive = directory.path().join("bad.tar.gz");
+
+        tar_gz(&archive, &[("../evil.sh", b"rm -rf /")], None);
+        let error = extract(
+            ArchiveKind::TarGz,
+            &archive,
+            &out,
+            LIMITS,
+            Extracted::default(),
+            &RUNNING,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::UnsafeArchiveEntry { .. }));
+        assert!(!directory.path().join("evil.sh").exists());
+
+        tar_gz(&archive, &[], Some("link"));
+        let error = extract(
+            ArchiveKind::TarGz,
+            &archive,
+            &out,
+            LIMITS,
+            Extracted::default(),
+            &RUNNING,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::UnsafeArchiveEntry { .. }));
+
+        tar_gz(&archive, &[("big.txt", &[b'a'; 2048])], None);
+        let error = extract(
+            ArchiveKind::TarGz,
+            &archive,
+            &out,
+            LIMITS,
+            Extracted::default(),
+            &RUNNING,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::ArchiveTooLarge { limit: 1024 }));
+    }
+
+    #[test]
+    fn rejects_conflicting_entries_and_stops_when_cancelled() {
+        let directory = tempfile::tempdir().unwrap();
+        let archive = directory.path().join("conflict.tar.gz");
+        let extract_into = |out: &str, cancelled: &AtomicBool| {
+            extract(
+                ArchiveKind::TarGz,
+                &archive,
+                &directory.path().join(out),
+                LIMITS,
+                Extracted::default(),
+                cancelled,
+            )
+        };
+
+        tar_gz(&archive, &[("a.py", b"1"), ("a.py", b"2")], None);
+        let error = extract_into("twice", &RUNNING).unwrap_err();
+        assert!(matches!(error, Error::ConflictingUploadPath { path } if path == "a.py"));
+
+        tar_gz(&archive, &[("src", b"1"), ("src/a.py", b"2")], None);
+        let error = extract_into("file_then_directory", &RUNNING).unwrap_err();
+        assert!(matches!(error, Error::ConflictingUploadPath { path } if path == "src"));
+
+        tar_gz(&archive, &[("a.py", b"1")], None);
+        let error = extract_into("cancelled", &AtomicBool::new(true)).unwrap_err();
+        assert!(matches!(error, Error::Archive { .. }));
+        assert!(!directory.path().join("cancelled/a.py").exists());
+    }
+}
@@ -0,0 +1,245 @@
+use super::{
+    cloner::Cloner,
+    credentials::git_command,
+    git::parse_ls_remote,
+    info::{BranchValue, Branches},
+    Error,
+};
+use std::path::{Path, PathBuf};
+use tokio::time::Instant;
+
+/// Finds a bare repository at `root` or in its only subdirectory (`repo.git/`).
+pub fn find_bare_repository(root: &Path) -> Option<PathBuf> {
+    let is_bare = |path: &Path| {
+        path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+    };
+    if is_bare(root) {
+        return Some(root.to_path_buf());
+    }
+
+    let mut directories = std::fs::read_dir(root)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()));
+    let directory = directories.next()?.path();
+    (directories.next().is_none() && is_bare(&directory)).then_some(directory)
+}
+
+/// Makes an uploaded bare repository safe to read: alternates would let git read objects
+/// from any path or url, and the uploaded `config` is replaced so none of its settings apply.
+pub fn sanitize_bare_repository(repository: &Path) -> Result<(), Error> {
+    let info = repository.join("objects").join("info");
+    if let Ok(entries) = std::fs::read_dir(&info) {
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name().to_string_lossy().ends_with("alternates") {
+                return Err(Error::UnsafeArchiveEntry {
+                    path: format!("objects/info/{}", entry.file_name().to_string_lossy()),
+                });
+            }
+        }
+    }
+    std::fs::write(repository.join("config"), "[core]\n\tbare = true\n").map_err(|source| {
+        Error::Io {
+            url: repository.display().to_string(),
+            source,
+        }
+    })
+}
+
+/// Lists branches of a bundle file or a bare repository, the same way as for remotes.
+/// The upload is untrusted, so git runs under the clone limits of `cloner` until `deadline`.
+pub async fn source_branches(
+    cloner: &Cloner,
+    source: &Path,
+    deadline: Instant,
+) -> Result<Branches, Error> {
+    let source = source.to_str().ok_or_else(|| Error::BranchNotFound {
+        desc: "Uploaded repository path is not valid UTF-8".to_string(),
+    })?;
+    let mut command = git_command(None);
+    command.args(["ls-remote", "--symref", source]);
+    let output = cloner
+        .run_git(command, source, None, None, deadline)
+        .await?;
+    let output = String::from_utf8_lossy(&output);
+    Ok(parse_ls_remote(source, &output)?.branches())
+}
+
+/// Picks the requested branch, the default one otherwise.
+pub fn resolve_branch(branches: &Branches, requested: Option<&str>) -> Result<BranchValue, Error> {
+    let name = requested
+        .filter(|name| !name.is_empty())
+        .unwrap_or(&branches.default_branch);
+    branches
+        .branches
+        .iter()
+        .find(|branch| branch.name == name)
+        // Bundles created without HEAD have no default branch
+        .or_else(|| match requested {
+            None => branches.branches.first(),
+            Some(_) => None,
+        })
+        .cloned()
+        .ok_or_else(|| Error::WrongBranch {
+            wrong_branch: name.to_string(),
+        })
+}
+
+/// Checks out `branch` of a bundle or bare repository into `destination`, stopped like a
+/// clone when `deadline` passes or `destination` grows over the size limit of `cloner`.
+pub async fn clone_local(
+    cloner: &Cloner,
+    source: &Path,
+    branch: &str,
+    destination: &Path,
+    deadline: Instant,
+) -> Result<(), Error> {
+    let repository = source.display().to_string();
+    // `--no-local` goes through upload-pack instead of copying the uploaded object files as is
+    let mut command = git_command(None);
+    command
+        .args([
+            "clone",
+            "--quiet",
+            "--no-local",
+            "--single-branch",
+            "--branch",
+        ])
+        .arg(branch)
+        .arg(source)
+        .arg(destination);
+    cloner
+        .run_git(command, &repository, Some(destination), None, deadline)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        clone_local, find_bare_repository, resolve_branch, sanitize_bare_repository,
+        source_branches,
+    };
+    use crate::logic::{
+        cloner::{CloneLimits, Cloner},
+        Error,
+    };
+    use std::{path::Path, process::Command, time::Duration};
+    use tokio::time::Instant;
+
+    fn git(directory: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=cloc", "-c", "user.email=cloc@localhost"])
+            .args(args)
+            .current_dir(directory)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?}");
+    }
+
+    #[tokio::test]
+    async fn clones_branch_from_bundle_and_bare_repository() {
+        let directory = tempfile::tempdir().unwrap();
+        let work = directory.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        git(&work, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(work.join("main.rs"), "fn main() {}\n").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "--quiet", "-m", "init"]);
+        git(&work, &["checkout", "--quiet", "-b", "feature"]);
+        std::fs::write(work.join("lib.rs"), "pub fn f() {}\n").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "--quiet", "-m", "feature"]);
+        git(&work, &["checkout", "--quiet", "main"]);
+        git(&work, &["bundle", "create", "../repo.bundle", "--all"]);
+        git(
+            directory.path(),
+            &["clone", "--quiet", "--bare", "work", "repo.git"],
+        );
+
+        let cloner = Cloner::default();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let bundle = directory.path().join("repo.bundle");
+        let branches = source_branches(&cloner, &bundle, deadline).await.unwrap();
+        assert_eq!(branches.default_branch, "main");
+        assert_eq!(
+            resolve_branch(&branches, Some("feature")).unwrap().name,
+            "feature"
+        );
+        assert!(resolve_branch(&branches, Some("missing")).is_err());
+
+        let checkout = directory.path().join("checkout");
+        clone_local(&cloner, &bundle, "feature", &checkout, deadline)
+            .await
+            .unwrap();
+        assert!(checkout.join("lib.rs").is_file());
+
+        let limits = CloneLimits {
+            timeout: Duration::ZERO,
+            ..Default::default()
+        };
+        let stopped = Cloner::new(Default::default(), limits, Default::default());
+        let error = clone_local(
+            &stopped,
+            &bundle,
+            "main",
+            &checkout.with_extension("x"),
+            Instant::now(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(error, Error::CloneTimeout { .. }));
+
+        assert_eq!(
+            find_bare_repository(directory.path()),
+            None,
+            "several directories are ambiguous"
+        );
+        let bare = find_bare_repository(&directory.path().join("repo.git")).unwrap();
+        let bare_checkout = directory.path().join("bare");
+        clone_local(&cloner, &bare, "main", &bare_checkout, deadline)
+            .await
+            .unwrap();
+        assert!(bare_checkout.join("main.rs").is_file());
+        assert!(!bare_checkout.join("lib.rs").exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_alternates_and_ignores_uploaded_config() {
+        let directory = tempfile::tempdir().unwrap();
+        let work = directory.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        git(&work, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(work.join("main.rs"), "fn main() {}\n").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "--quiet", "-m", "init"]);
+        git(
+            directory.path(),
+            &["clone", "--quiet", "--bare", "work", "repo.git"],
+        );
+        let bare = directory.path().join("repo.git");
+
+        // git refuses repositories of an unknown format, so the clone fails if this is kept
+        std::fs::write(
+            bare.join("config"),
+            "[core]\n\tbare = true\n\trepositoryformatversion = 99\n",
+        )
+        .unwrap();
+        sanitize_bare_repository(&bare).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let checkout = directory.path().join("checkout");
+        clone_local(&Cloner::default(), &bare, "main", &checkout, deadline)
+            .await
+            .unwrap();
+        assert!(checkout.join("main.rs").is_file());
+
+        for name in ["alternates", "http-alternates"] {
+            let info = bare.join("objects").join("info");
+            std::fs::create_dir_all(&info).unwrap();
+            std::fs::write(info.join(name), "/etc\n").unwrap();
+            let error = sanitize_bare_repository(&bare).unwrap_err();
+            assert!(matches!(error, Error::UnsafeArchiveEntry { .. }), "{name}");
+            std::fs::remove_file(info.join(name)).unwrap();
+        }
+    }
+}
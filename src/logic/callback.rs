@@ -0,0 +1,335 @@
+use super::{info::Status, summary::Summary};
+use hmac::{Hmac, Mac};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+
+pub const SIGNATURE_HEADER: &str = "X-Cloc-Signature-256";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CallbackPayload {
+    pub unique_name: String,
+    pub status: String,
+    pub error: Option<String>,
+    /// Stable code of the error, as in problem responses.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub summary: Option<Summary>,
+}
+
+impl CallbackPayload {
+    /// Payload for a finished task, `None` while the task is still running.
+    pub fn new(unique_name: &str, status: &Status) -> Option<Self> {
+        let (status, failure, summary) = match status {
+            Status::Done(data) => ("done", None, Some(Summary::parse(data))),
+            Status::Error(failure) => ("error", Some(failure), None),
+            Status::InProgress(_) | Status::Cloned | Status::Ready | Status::Previous { .. } => {
+                return None
+            }
+        };
+
+        Some(Self {
+            unique_name: unique_name.to_string(),
+            status: status.to_string(),
+            error: failure.map(|failure| failure.detail.clone()),
+            code: failure.map(|failure| failure.code.clone()),
+            summary,
+        })
+    }
+}
+
+/// Delivers signed `CallbackPayload`s to client supplied urls.
+#[derive(Clone)]
+pub struct Notifier {
+    client: reqwest::Client,
+    secret: Option<Arc<String>>,
+    attempts: u32,
+    base_delay: Duration,
+    private_addresses: bool,
+}
+
+impl Notifier {
+    pub fn new(secret: Option<String>, attempts: u32, base_delay: Duration) -> Self {
+        Self {
+            client: Self::client(false),
+            secret: secret.map(Arc::new),
+            attempts,
+            base_delay,
+            private_addresses: false,
+        }
+    }
+
+    /// Callback urls are untrusted, so redirects aren't followed and hosts have to resolve to
+    /// global addresses, checked when connecting so a rebinding DNS server can't get around it.
+    fn client(private_addresses: bool) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder()
+            .user_agent(concat!("cloc.info/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(10))
+            .redirect(reqwest::redirect::Policy::none());
+        if !private_addresses {
+            builder = builder.dns_resolver(Arc::new(GlobalResolver));
+        }
+        builder.build().unwrap_or_default()
+    }
+
+    /// Also delivers to loopback and private networks, for tests and trusted deployments.
+    pub fn with_private_addresses(mut self) -> Self {
+        self.client = Self::client(true);
+        self.private_addresses = true;
+        self
+    }
+
+    /// Signs payloads with `CALLBACK_SECRET` if it is set. Callbacks to private addresses
+    /// are only delivered with `CALLBACK_ALLOW_PRIVATE=true`.
+    pub fn from_env() -> Self {
+        let notifier = Self::new(
+            std::env::var("CALLBACK_SECRET").ok(),
+            5,
+            Duration::from_secs(2),
+        );
+        if std::env::var("CALLBACK_ALLOW_PRIVATE").is_ok_and(|value| value == "true") {
+            notifier.with_private_addresses()
+        } else {
+            notifier
+        }
+    }
+
+    /// An http(s) url whose host, when it is an IP address, is a global one. Host names are
+    /// checked when they are resolved.
+    pub fn is_valid_url(url: &str) -> bool {
+        reqwest::Url::parse(url).is_ok_and(|url| {
+            let Some(host) = url.host_str() else {
+                return false;
+            };
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            matches!(url.scheme(), "http" | "https") && host.parse().map_or(true, is_global)
+        })
+    }
+
+    pub fn sign(&self, body: &[u8]) -> Option<String> {
+        let secret = self.secret.as_ref()?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+        mac.update(body);
+        Some(format!(
+            "sha256={}",
+            hex::encode(mac.finalize().into_bytes())
+        ))
+    }
+
+    /// Posts `payload` to `url`, retrying with exponential backoff. Returns `true` on success.
+    pub async fn deliver(&self, url: &str, payload: &CallbackPayload) -> bool {
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(error) => {
+                tracing::error!("Can't serialize callback payload for {url}: {error}");
+                return false;
+            }
+        };
+        if !self.private_addresses && !Self::is_valid_url(url) {
+            tracing::warn!("Callback {url} for {} refused", payload.unique_name);
+            return false;
+        }
+        let signature = self.sign(&body);
+
+        let mut delay = self.base_delay;
+        for attempt in 1..=self.attempts {
+            let mut request = self
+                .client
+                .post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(body.clone());
+            if let Some(signature) = &signature {
+                request = request.header(SIGNATURE_HEADER, signature);
+            }
+
+            match request.send().await {
+                Ok(response) if response.status().is_success() => {
+                    tracing::info!("Callback {url} for {} delivered", payload.unique_name);
+                    return true;
+                }
+                Ok(response) => tracing::warn!(
+                    "Callback {url} attempt {attempt} failed: {}",
+                    response.status()
+                ),
+                Err(error) => tracing::warn!("Callback {url} attempt {attempt} failed: {error}"),
+            }
+
+            if attempt < self.attempts {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+        }
+
+        tracing::error!("Callback {url} for {} abandoned", payload.unique_name);
+        false
+    }
+
+    pub fn spawn_deliver(&self, url: String, payload: CallbackPayload) {
+        let notifier = self.clone();
+        tokio::spawn(async move { notifier.deliver(&url, &payload).await });
+    }
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// Resolves with the system resolver and keeps only global addresses.
+struct GlobalResolver;
+
+impl Resolve for GlobalResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<_> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_global(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(format!("{} has no global address", name.as_str()).into());
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Whether `ip` is reachable on the internet, unlike loopback, private, link-local (such as
+/// cloud metadata at 169.254.169.254), shared, documentation and reserved addresses.
+pub fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_global_v4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_global_v4(ip);
+            }
+            let segments = ip.segments();
+            // NAT64 embeds an IPv4 address in the last 32 bits
+            if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+                let [.., high, low] = segments;
+                return is_global_v4(Ipv4Addr::from((u32::from(high) << 16) | u32::from(low)));
+            }
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                // IPv4-compatible, deprecated
+                || segments[..6] == [0; 6]
+                // Unique local fc00::/7
+                || segments[0] & 0xfe00 == 0xfc00
+                // Link-local fe80::/10
+                || segments[0] & 0xffc0 == 0xfe80
+                // Documentation 2001:db8::/32
+                || segments[..2] == [0x2001, 0xdb8])
+        }
+    }
+}
+
+fn is_global_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        || a == 0
+        // Shared address space 100.64.0.0/10
+        || (a == 100 && b & 0xc0 == 64)
+        // IETF protocol assignments 192.0.0.0/24
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking 198.18.0.0/15
+        || (a == 198 && b & 0xfe == 18)
+        // Reserved 240.0.0.0/4
+        || a >= 240)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallbackPayload, Notifier, SIGNATURE_HEADER};
+    use crate::logic::{forge::tests::spawn_api, info::Status};
+    use axum::{extract::State, http::HeaderMap, routing::post, Router};
+    use hyper::StatusCode;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn retries_until_callback_is_accepted() {
+        #[derive(Clone, Default)]
+        struct Received {
+            attempts: Arc<AtomicUsize>,
+            signature: Arc<Mutex<Option<String>>>,
+        }
+
+        let received = Received::default();
+        let router = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(received): State<Received>, headers: HeaderMap| async move {
+                        let signature = headers
+                            .get(SIGNATURE_HEADER)
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string);
+                        *received.signature.lock().unwrap() = signature;
+                        if received.attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        } else {
+                            StatusCode::NO_CONTENT
+                        }
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let base_url = spawn_api(router).await;
+
+        let notifier = Notifier::new(Some("secret".to_string()), 3, Duration::from_millis(1))
+            .with_private_addresses();
+        let payload =
+            CallbackPayload::new("github.com/o/r.git/main", &Status::Done(vec![])).unwrap();
+
+        assert!(
+            notifier
+                .deliver(&format!("{base_url}/hook"), &payload)
+                .await
+        );
+        assert_eq!(received.attempts.load(Ordering::SeqCst), 2);
+        let body = serde_json::to_vec(&payload).unwrap();
+        assert_eq!(*received.signature.lock().unwrap(), notifier.sign(&body));
+    }
+
+    #[test]
+    fn running_tasks_have_no_payload() {
+        assert!(CallbackPayload::new("name", &Status::Ready).is_none());
+        assert!(!Notifier::is_valid_url("ftp://example.com"));
+        assert!(Notifier::is_valid_url("https://example.com/hook"));
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.1/",
+            "http://[::1]/",
+            "http://[::ffff:192.168.1.1]/",
+            "http://[fd00::1]/",
+        ] {
+            assert!(!Notifier::is_valid_url(url), "{url}");
+        }
+        assert!(Notifier::is_valid_url("http://93.184.216.34/hook"));
+    }
+
+    #[tokio::test]
+    async fn private_hosts_are_refused() {
+        let notifier = Notifier::new(None, 1, Duration::from_millis(1));
+        let payload = CallbackPayload::new("name", &Status::Done(vec![])).unwrap();
+        assert!(!notifier.deliver("http://localhost:9/hook", &payload).await);
+    }
+}
@@ -1,15 +1,70 @@
 use super::Error;
-use crate::logic::info::{to_url, Status, Task};
+use crate::logic::{
+    credentials::{git_command, Credential},
+    info::{to_url, Status, Task},
+    preflight::format_bytes,
+    repository::dir_size,
+};
 use dashmap::DashMap;
-use std::{fmt::Display, process::Stdio, sync::Arc};
+use std::{fmt::Display, future::Future, path::Path, process::Stdio, sync::Arc, time::Duration};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
-    process::Command,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, Command},
+    time::Instant,
 };
+use tokio_util::sync::CancellationToken;
+
+/// Default `--large-byte-count` of scc, larger files are not counted.
+const SCC_LARGE_BYTE_COUNT: u64 = 1_000_000;
+
+/// How often the size of the clone directory is checked.
+const SIZE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CloneLimits {
+    /// Wall-clock limit of a single `git clone`.
+    pub timeout: Duration,
+    /// Maximum size of the clone directory.
+    pub max_bytes: u64,
+    /// Larger files are neither downloaded nor checked out.
+    pub blob_limit: Option<u64>,
+}
+
+impl CloneLimits {
+    /// Reads `CLONE_TIMEOUT_SECS` (default 600), `CLONE_MAX_BYTES` (default 2 GiB) and
+    /// `CLONE_BLOB_LIMIT` (default 1 000 000 bytes, the size above which scc skips a file
+    /// anyway; 0 downloads every file).
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: u64) -> u64 {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            timeout: Duration::from_secs(var("CLONE_TIMEOUT_SECS", 600)),
+            max_bytes: var("CLONE_MAX_BYTES", 2 * 1024 * 1024 * 1024),
+            blob_limit: Some(var("CLONE_BLOB_LIMIT", SCC_LARGE_BYTE_COUNT))
+                .filter(|limit| *limit > 0),
+        }
+    }
+}
+
+impl Default for CloneLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(600),
+            max_bytes: 2 * 1024 * 1024 * 1024,
+            blob_limit: Some(SCC_LARGE_BYTE_COUNT),
+        }
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct Stages {
     command: String,
+    expected: String,
     cloning: String,
     enumerating: String,
     counting: String,
@@ -24,6 +79,7 @@ impl Stages {
     pub fn new() -> Self {
         Self {
             command: String::new(),
+            expected: String::new(),
             cloning: String::new(),
             enumerating: String::new(),
             counting: String::new(),
@@ -40,8 +96,9 @@ impl Display for Stages {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{}{}{}{}{}{}{}{}{}",
+            "{}{}{}{}{}{}{}{}{}{}",
             self.command,
+            self.expected,
             self.cloning,
             self.enumerating,
             self.counting,
@@ -80,11 +137,28 @@ impl AsRef<Vec<String>> for Args {
 #[derive(Clone, Default)]
 pub struct Cloner {
     statuses: Arc<DashMap<String, Status>>,
+    limits: CloneLimits,
+    cancel: Arc<CancellationToken>,
 }
 
 impl Cloner {
-    pub fn new(statuses: Arc<DashMap<String, Status>>) -> Self {
-        Self { statuses }
+    pub fn new(
+        statuses: Arc<DashMap<String, Status>>,
+        limits: CloneLimits,
+        cancel: Arc<CancellationToken>,
+    ) -> Self {
+        Self {
+            statuses,
+            limits,
+            cancel,
+        }
+    }
+
+    /// Same cloner with the size limit lowered to `max_bytes`, e.g. the remaining quota of a key.
+    pub fn with_max_bytes(&self, max_bytes: u64) -> Self {
+        let mut cloner = self.clone();
+        cloner.limits.max_bytes = cloner.limits.max_bytes.min(max_bytes);
+        cloner
     }
 
     async fn execute_new(
@@ -92,12 +166,20 @@ impl Cloner {
         args: Args,
         unique_name: &str,
         path: &str,
+        credential: Option<&Credential>,
+        expected_size: Option<u64>,
+        deadline: Instant,
     ) -> Result<Status, Error> {
-        let mut command = Command::new("git");
+        let mut command = git_command(credential);
         command.args(args.as_ref());
         command.stdout(Stdio::piped());
         command.stderr(Stdio::piped());
-        tracing::debug!("{command:?} to {path}");
+        command.kill_on_drop(true);
+        // Own process group, so helpers spawned by git are killed together with it
+        #[cfg(unix)]
+        command.process_group(0);
+        // `Command`'s Debug output includes the environment, which holds the token
+        tracing::debug!("git {args} to {path}");
 
         let repository = path.to_string();
 
@@ -110,12 +192,155 @@ impl Cloner {
             error: "stderr pipe is unavailable".to_string(),
         })?;
 
-        let mut reader = BufReader::new(stderr);
-        let mut buffer = Vec::with_capacity(1000);
-        let mut stages = Stages {
+        let stages = Stages {
             command: format!("git {}\n", args),
+            expected: expected_size
+                .map(|size| format!("Expected size: {}\n", format_bytes(size)))
+                .unwrap_or_default(),
             ..Default::default()
         };
+        let progress = self.read_progress(stderr, stages, unique_name, path, credential);
+        self.watch(&mut child, progress, path, Some(Path::new(path)), deadline)
+            .await?;
+
+        match child.wait().await {
+            Ok(exit) => {
+                tracing::debug!("git clone exit code: {}", exit);
+                if exit.success() {
+                    Ok(Status::Cloned)
+                } else {
+                    Err(Error::CloneError {
+                        repository: repository.clone(),
+                        error: exit.to_string(),
+                    })
+                }
+            }
+            Err(e) => {
+                tracing::error!("git clone exit error: {}", e);
+                Err(Error::CloneError {
+                    repository,
+                    error: e.to_string(),
+                })
+            }
+        }
+    }
+
+    /// Waits for `work` of `child`, killing its process group when the deadline passes, the
+    /// service shuts down or `watched` grows over the size limit.
+    async fn watch<T>(
+        &self,
+        child: &mut Child,
+        work: impl Future<Output = Result<T, Error>>,
+        repository: &str,
+        watched: Option<&Path>,
+        deadline: Instant,
+    ) -> Result<T, Error> {
+        tokio::pin!(work);
+        let started = Instant::now();
+        let deadline = tokio::time::sleep_until(deadline);
+        tokio::pin!(deadline);
+        let mut poll = tokio::time::interval(SIZE_POLL_INTERVAL);
+
+        let exceeded = loop {
+            tokio::select! {
+                result = &mut work => return result,
+                _ = &mut deadline => break Error::CloneTimeout {
+                    repository: repository.to_string(),
+                    seconds: started.elapsed().as_secs(),
+                },
+                _ = self.cancel.cancelled() => break Error::CloneCancelled {
+                    repository: repository.to_string(),
+                },
+                _ = poll.tick(), if watched.is_some() => {
+                    let Some(watched) = watched else { continue };
+                    // The directory doesn't exist until git has connected
+                    if let Ok(size) = dir_size(watched).await {
+                        if size > self.limits.max_bytes {
+                            break Error::CloneTooLarge {
+                                repository: repository.to_string(),
+                                limit: self.limits.max_bytes,
+                            };
+                        }
+                    }
+                }
+            }
+        };
+
+        tracing::warn!("Stopping git in {repository}: {exceeded}");
+        kill_process_group(child).await;
+        Err(exceeded)
+    }
+
+    /// Runs a git command that reports no progress, such as one on an uploaded repository,
+    /// under the same limits as a clone and returns its stdout.
+    pub async fn run_git(
+        &self,
+        mut command: Command,
+        repository: &str,
+        watched: Option<&Path>,
+        stdin: Option<&[u8]>,
+        deadline: Instant,
+    ) -> Result<Vec<u8>, Error> {
+        let error = |error: String| Error::CloneError {
+            repository: repository.to_string(),
+            error,
+        };
+        command
+            .stdin(match stdin {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut child = command.spawn().map_err(|e| error(e.to_string()))?;
+        let input = child.stdin.take();
+        let (Some(mut stdout), Some(mut stderr)) = (child.stdout.take(), child.stderr.take())
+        else {
+            return Err(error("output pipes are unavailable".to_string()));
+        };
+        let work = async {
+            let write = async {
+                if let (Some(mut input), Some(stdin)) = (input, stdin) {
+                    input.write_all(stdin).await?;
+                }
+                Ok(())
+            };
+            let (mut output, mut errors) = (Vec::new(), Vec::new());
+            tokio::try_join!(
+                write,
+                stdout.read_to_end(&mut output),
+                stderr.read_to_end(&mut errors)
+            )
+            .map_err(|e: std::io::Error| error(e.to_string()))?;
+            Ok((output, errors))
+        };
+        let (output, errors) = self
+            .watch(&mut child, work, repository, watched, deadline)
+            .await?;
+
+        let status = child.wait().await.map_err(|e| error(e.to_string()))?;
+        if status.success() {
+            Ok(output)
+        } else {
+            Err(error(String::from_utf8_lossy(&errors).trim().to_string()))
+        }
+    }
+
+    /// Publishes git progress from `stderr` as the status of `unique_name` until it closes.
+    async fn read_progress(
+        &self,
+        stderr: impl AsyncRead + Unpin,
+        mut stages: Stages,
+        unique_name: &str,
+        repository: &str,
+        credential: Option<&Credential>,
+    ) -> Result<(), Error> {
+        let mut reader = BufReader::new(stderr);
+        let mut buffer = Vec::with_capacity(1000);
 
         loop {
             let read =
@@ -123,15 +348,19 @@ impl Cloner {
                     .read_until(b'\r', &mut buffer)
                     .await
                     .map_err(|e| Error::CloneError {
-                        repository: repository.clone(),
+                        repository: repository.to_string(),
                         error: e.to_string(),
                     })?;
 
             if read == 0 {
-                break;
+                return Ok(());
             }
 
             if let Ok(line) = String::from_utf8(buffer.clone()) {
+                let line = match credential {
+                    Some(credential) => credential.redact(&line),
+                    None => line,
+                };
                 if line.contains("Cloning") {
                     stages.cloning = line;
                 } else if line.contains("remote: Enumerating") {
@@ -166,27 +395,6 @@ impl Cloner {
             self.statuses
                 .insert(unique_name.to_string(), Status::InProgress(stages_string));
         }
-
-        match child.wait().await {
-            Ok(exit) => {
-                tracing::debug!("git clone exit code: {}", exit);
-                if exit.success() {
-                    Ok(Status::Cloned)
-                } else {
-                    Err(Error::CloneError {
-                        repository: repository.clone(),
-                        error: exit.to_string(),
-                    })
-                }
-            }
-            Err(e) => {
-                tracing::error!("git clone exit error: {}", e);
-                Err(Error::CloneError {
-                    repository,
-                    error: e.to_string(),
-                })
-            }
-        }
     }
 
     // pub async fn pull_repository(&self, task: &Task, destination: &str) -> Result<Status, Error> {
@@ -204,26 +412,176 @@ impl Cloner {
     //     self.execute_new(args, destination).await
     // }
 
+    pub fn limits(&self) -> CloneLimits {
+        self.limits
+    }
+
+    /// Clones only what is counted: files above the blob limit and outside the requested
+    /// paths are neither downloaded nor checked out, submodules are added on request.
     pub async fn clone_repository(
         &self,
         task: &Task,
         unique_name: &str,
         path: &str,
+        expected_size: Option<u64>,
+        deadline: Instant,
     ) -> Result<Status, Error> {
+        let credential = task.credential.as_ref();
+        let filter = match self.limits.blob_limit {
+            Some(limit) => format!("--filter=blob:limit={limit}"),
+            None => "--filter=blob:none".to_string(),
+        };
+        // Checking out later lets the sparse patterns decide which blobs are fetched
+        let sparse = self.limits.blob_limit.is_some() || !task.options.paths.is_empty();
+
         let url = to_url(&task.host, &task.owner, &task.repository_name);
-        let args = Args(vec![
+        let mut args = vec![
             "clone".to_string(),
             "--progress".to_string(),
             "--no-tags".to_string(),
-            "--filter=blob:none".to_string(),
+            filter.clone(),
             "--single-branch".to_string(),
             "--depth=1".to_string(),
             "--branch".to_string(),
             task.branch.clone(),
-            url,
-            path.to_string(),
-        ]);
-        self.execute_new(args, unique_name, path).await
+        ];
+        if sparse {
+            args.push("--no-checkout".to_string());
+        }
+        args.extend([url, path.to_string()]);
+        let git = |args: &[&str]| {
+            let mut command = vec!["-C".to_string(), path.to_string()];
+            command.extend(args.iter().map(|arg| arg.to_string()));
+            Args(command)
+        };
+
+        let mut status = self
+            .execute_new(
+                Args(args),
+                unique_name,
+                path,
+                credential,
+                expected_size,
+                deadline,
+            )
+            .await?;
+
+        if sparse {
+            let excluded = match self.limits.blob_limit {
+                Some(_) => self.missing_blob_paths(path, deadline).await?,
+                None => Vec::new(),
+            };
+            let patterns = sparse_patterns(&task.options.paths, &excluded);
+            self.set_sparse_checkout(path, &patterns, deadline).await?;
+            status = self
+                .execute_new(
+                    git(&["checkout", "--progress", &task.branch]),
+                    unique_name,
+                    path,
+                    credential,
+                    expected_size,
+                    deadline,
+                )
+                .await?;
+        }
+
+        if task.options.submodules {
+            status = self
+                .execute_new(
+                    git(&[
+                        "submodule",
+                        "update",
+                        "--init",
+                        "--recursive",
+                        "--depth=1",
+                        "--progress",
+                        &filter,
+                    ]),
+                    unique_name,
+                    path,
+                    credential,
+                    expected_size,
+                    deadline,
+                )
+                .await?;
+        }
+
+        Ok(status)
+    }
+
+    /// Paths of files whose blobs were left out by the clone filter.
+    async fn missing_blob_paths(
+        &self,
+        path: &str,
+        deadline: Instant,
+    ) -> Result<Vec<String>, Error> {
+        let missing = self
+            .git_output(
+                path,
+                &["rev-list", "--objects", "--missing=print", "HEAD"],
+                deadline,
+            )
+            .await?;
+        let missing: std::collections::HashSet<&str> = missing
+            .lines()
+            .filter_map(|line| line.strip_prefix('?'))
+            .collect();
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let tree = self
+            .git_output(
+                path,
+                &["ls-tree", "-r", "-z", "--full-tree", "HEAD"],
+                deadline,
+            )
+            .await?;
+        Ok(tree
+            .split('\0')
+            .filter_map(|entry| {
+                let (info, file) = entry.split_once('\t')?;
+                let object = info.split_whitespace().nth(2)?;
+                missing.contains(object).then(|| file.to_string())
+            })
+            .collect())
+    }
+
+    async fn set_sparse_checkout(
+        &self,
+        path: &str,
+        patterns: &[String],
+        deadline: Instant,
+    ) -> Result<(), Error> {
+        let mut command = git_command(None);
+        command.args(["-C", path, "sparse-checkout", "set", "--no-cone", "--stdin"]);
+        self.run_git(
+            command,
+            path,
+            Some(Path::new(path)),
+            Some(patterns.join("\n").as_bytes()),
+            deadline,
+        )
+        .await
+        .map(drop)
+    }
+
+    /// Stdout of `git -C path args`, run under the limits of the clone.
+    async fn git_output(
+        &self,
+        path: &str,
+        args: &[&str],
+        deadline: Instant,
+    ) -> Result<String, Error> {
+        let mut command = git_command(None);
+        command.args(["-C", path]).args(args);
+        let output = self
+            .run_git(command, path, Some(Path::new(path)), None, deadline)
+            .await?;
+        String::from_utf8(output).map_err(|e| Error::CloneError {
+            repository: path.to_string(),
+            error: e.to_string(),
+        })
     }
 
     pub async fn set_done(&self, unique_name: &str) {
@@ -243,3 +601,154 @@ impl Cloner {
         }
     }
 }
+
+/// Non-cone sparse checkout patterns: the requested paths (or everything) without `excluded`.
+fn sparse_patterns(paths: &[String], excluded: &[String]) -> Vec<String> {
+    let escape = |path: &str| {
+        path.chars()
+            .fold(String::with_capacity(path.len()), |mut escaped, char| {
+                if matches!(char, '*' | '?' | '[' | '\\' | '!' | '#') {
+                    escaped.push('\\');
+                }
+                escaped.push(char);
+                escaped
+            })
+    };
+
+    let mut patterns: Vec<String> = if paths.is_empty() {
+        vec!["/*".to_string()]
+    } else {
+        paths
+            .iter()
+            .map(|path| format!("/{}", escape(path)))
+            .collect()
+    };
+    patterns.extend(excluded.iter().map(|path| format!("!/{}", escape(path))));
+    patterns
+}
+
+/// Kills git together with `git-remote-https` and other helpers it spawned.
+async fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    if let Some(id) = child.id() {
+        let killed = tokio::process::Command::new("kill")
+            .args(["-s", "KILL", "--", &format!("-{id}")])
+            .status()
+            .await;
+        if let Err(error) = killed {
+            tracing::warn!("Can't kill process group {id}: {error}");
+        }
+    }
+    if let Err(error) = child.kill().await {
+        tracing::debug!("Can't kill git process: {error}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sparse_patterns, Args, CloneLimits, Cloner};
+    use crate::logic::Error;
+    use std::{sync::Arc, time::Duration};
+    use tokio::time::Instant;
+    use tokio_util::sync::CancellationToken;
+
+    fn deadline() -> Instant {
+        Instant::now() + Duration::from_millis(200)
+    }
+
+    fn sleeping_git() -> Args {
+        // The shell and its `sleep` are children of git, killed with its process group
+        Args(
+            ["-c", "alias.wait=!sleep 30", "wait"]
+                .map(str::to_string)
+                .to_vec(),
+        )
+    }
+
+    #[tokio::test]
+    async fn clone_is_stopped_by_timeout_and_cancel() {
+        let limits = CloneLimits {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let cancel = Arc::new(CancellationToken::new());
+        let cloner = Cloner::new(Default::default(), limits, cancel.clone());
+
+        let started = Instant::now();
+        let result = cloner
+            .execute_new(
+                sleeping_git(),
+                "test",
+                "missing-clone-dir",
+                None,
+                None,
+                deadline(),
+            )
+            .await;
+        assert!(matches!(
+            result,
+            Err(Error::CloneTimeout { seconds: 0, .. })
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        cancel.cancel();
+        let result = cloner
+            .execute_new(
+                sleeping_git(),
+                "test",
+                "missing-clone-dir",
+                None,
+                None,
+                deadline(),
+            )
+            .await;
+        assert!(matches!(result, Err(Error::CloneCancelled { .. })));
+    }
+
+    #[tokio::test]
+    async fn large_blobs_are_left_out_of_checkout() {
+        let directory = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=cloc", "-c", "user.email=cloc@localhost"])
+                .args(args)
+                .current_dir(directory.path())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?}");
+        };
+        let source = directory.path().join("source");
+        std::fs::create_dir_all(source.join("src")).unwrap();
+        std::fs::write(source.join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(source.join("src/data[1].json"), "0".repeat(4096)).unwrap();
+        git(&["-C", "source", "init", "--quiet", "--initial-branch=main"]);
+        git(&["-C", "source", "add", "."]);
+        git(&["-C", "source", "commit", "--quiet", "-m", "init"]);
+        git(&["-C", "source", "config", "uploadpack.allowFilter", "true"]);
+        let url = format!("file://{}", source.display());
+        git(&[
+            "clone",
+            "--quiet",
+            "--filter=blob:limit=1000",
+            "--depth=1",
+            "--no-checkout",
+            &url,
+            "clone",
+        ]);
+
+        let clone = directory.path().join("clone");
+        let excluded = Cloner::default()
+            .missing_blob_paths(
+                clone.to_str().unwrap(),
+                Instant::now() + Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+        assert_eq!(excluded, ["src/data[1].json"]);
+        assert_eq!(
+            sparse_patterns(&[], &excluded),
+            ["/*", "!/src/data\\[1].json"]
+        );
+        assert_eq!(sparse_patterns(&["src".to_string()], &[]), ["/src"]);
+    }
+}
@@ -0,0 +1,228 @@
+use hyper::HeaderMap;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, sync::Arc};
+use tokio::process::Command;
+
+pub use cloc_api::headers::{TOKEN_HEADER, USERNAME_HEADER};
+
+// GitHub ignores the user name for tokens, GitLab and Gitea accept any non-empty one
+const DEFAULT_USERNAME: &str = "x-access-token";
+const USERNAME_VARIABLE: &str = "CLOC_GIT_USERNAME";
+const TOKEN_VARIABLE: &str = "CLOC_GIT_TOKEN";
+// Answers `get` from the environment of the git process, the token never appears in arguments
+const CREDENTIAL_HELPER: &str = "!f() { test \"$1\" = get && printf 'username=%s\\npassword=%s\\n' \"$CLOC_GIT_USERNAME\" \"$CLOC_GIT_TOKEN\"; }; f";
+
+/// Access token for private repositories, `Debug` never prints the token.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credential {
+    username: String,
+    token: String,
+    shared: bool,
+    /// The only host git sends the token to.
+    host: Option<String>,
+}
+
+impl Credential {
+    pub fn new(username: Option<String>, token: String) -> Self {
+        Self {
+            username: username
+                .filter(|username| !username.is_empty())
+                .unwrap_or_else(|| DEFAULT_USERNAME.to_string()),
+            token,
+            shared: false,
+            host: None,
+        }
+    }
+
+    /// Restricts the credential to `host`, git doesn't get it without one. Submodules and
+    /// redirects to other hosts are fetched anonymously.
+    pub fn with_host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_ascii_lowercase());
+        self
+    }
+
+    /// Configured by the operator for a whole host, so results are visible to everyone
+    /// who can reach the service. Per-request credentials keep results to their owner.
+    pub fn is_shared(&self) -> bool {
+        self.shared
+    }
+
+    /// Reads `X-Git-Token` and `X-Git-Username`.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = |name| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        header(TOKEN_HEADER).map(|token| Self::new(header(USERNAME_HEADER), token))
+    }
+
+    /// Stable identifier of the credential, safe to use in names and cache keys.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.username.as_bytes());
+        hasher.update([0]);
+        hasher.update(self.token.as_bytes());
+        let mut fingerprint = hex::encode(hasher.finalize());
+        fingerprint.truncate(16);
+        fingerprint
+    }
+
+    /// User name and token for HTTP basic authentication, never log them.
+    pub fn basic_auth(&self) -> (&str, &str) {
+        (&self.username, &self.token)
+    }
+
+    /// Replaces the token in git output before it is logged or shown to clients.
+    pub fn redact(&self, text: &str) -> String {
+        text.replace(&self.token, "***")
+    }
+}
+
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credential")
+            .field("username", &self.username)
+            .field("token", &"***")
+            .field("shared", &self.shared)
+            .field("host", &self.host)
+            .finish()
+    }
+}
+
+/// `git` command answering credential requests for the host of `credential`, if any.
+///
+/// Configured helpers are reset first so the token is never written by `store` or `cache`,
+/// and git never prompts on the terminal.
+pub fn git_command(credential: Option<&Credential>) -> Command {
+    let mut command = Command::new("git");
+    command
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .args(["-c", "credential.helper="]);
+    // A host with other characters could change the meaning of the config key
+    let scoped = credential.and_then(|credential| {
+        let host = credential.host.as_deref().filter(|host| {
+            host.bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b':'))
+        })?;
+        Some((credential, host))
+    });
+    if let Some((credential, host)) = scoped {
+        command
+            .arg("-c")
+            .arg(format!(
+                "credential.https://{host}.helper={CREDENTIAL_HELPER}"
+            ))
+            .env(USERNAME_VARIABLE, &credential.username)
+            .env(TOKEN_VARIABLE, &credential.token);
+    }
+    command
+}
+
+/// Per-host credentials configured by the operator.
+#[derive(Clone, Default)]
+pub struct Credentials {
+    hosts: Arc<HashMap<String, Credential>>,
+}
+
+impl Credentials {
+    /// Parses `host=token` or `host=username:token` pairs separated by `;`.
+    pub fn parse(value: &str) -> Self {
+        let hosts = value
+            .split(';')
+            .filter_map(|pair| {
+                let (host, secret) = pair.trim().split_once('=')?;
+                let mut credential = match secret.split_once(':') {
+                    Some((username, token)) => {
+                        Credential::new(Some(username.to_string()), token.to_string())
+                    }
+                    None => Credential::new(None, secret.to_string()),
+                };
+                credential.shared = true;
+                let host = host.trim().to_ascii_lowercase();
+                Some((host.clone(), credential.with_host(&host)))
+            })
+            .collect();
+
+        Self {
+            hosts: Arc::new(hosts),
+        }
+    }
+
+    /// Reads `GIT_CREDENTIALS`. Requests with an API key and the service's own refreshes
+    /// use the credential of a configured host, so their results are kept out of the public
+    /// lists. Anonymous clients can't use it to read private repositories.
+    pub fn from_env() -> Self {
+        std::env::var("GIT_CREDENTIALS")
+            .map(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    pub fn for_host(&self, host: &str) -> Option<Credential> {
+        self.hosts.get(&host.to_ascii_lowercase()).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{git_command, Credential, Credentials};
+    use std::process::Stdio;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn token_is_never_printed() {
+        let credentials = Credentials::parse("github.com=secret-token; git.example.com=ci:other");
+        let credential = credentials.for_host("GitHub.com").unwrap();
+
+        assert!(!format!("{credential:?}").contains("secret-token"));
+        assert_eq!(
+            credential.redact("fatal: secret-token rejected"),
+            "fatal: *** rejected"
+        );
+        assert_ne!(
+            credential.fingerprint(),
+            credentials
+                .for_host("git.example.com")
+                .unwrap()
+                .fingerprint()
+        );
+        assert!(credentials.for_host("gitlab.com").is_none());
+        assert!(credential.is_shared());
+    }
+
+    #[tokio::test]
+    async fn helper_answers_only_for_its_host() {
+        let fill = |host: &'static str| async move {
+            let credential =
+                Credential::new(None, "secret-token".to_string()).with_host("example.com");
+            let mut command = git_command(Some(&credential));
+            command
+                .args(["credential", "fill"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null());
+            assert!(
+                !format!("{:?}", command.as_std().get_args().collect::<Vec<_>>())
+                    .contains("secret-token")
+            );
+
+            let mut child = command.spawn().unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            stdin
+                .write_all(format!("protocol=https\nhost={host}\n\n").as_bytes())
+                .await
+                .unwrap();
+            drop(stdin);
+            let output = child.wait_with_output().await.unwrap();
+            String::from_utf8(output.stdout).unwrap()
+        };
+
+        let output = fill("example.com").await;
+        assert!(output.contains("username=x-access-token\n"));
+        assert!(output.contains("password=secret-token\n"));
+        assert!(!fill("evil.example.net").await.contains("secret-token"));
+    }
+}
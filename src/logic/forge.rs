@@ -0,0 +1,386 @@
+use super::{DeserializeSnafu, Error, HttpSnafu};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use snafu::ResultExt;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+const PER_PAGE: usize = 100;
+const MAX_PAGES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForgeKind {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForgeRepository {
+    pub name: String,
+    /// Repository size in bytes as reported by the forge, `0` if unknown.
+    pub size: u64,
+    pub default_branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubRepository {
+    name: String,
+    #[serde(default)]
+    size: u64,
+    #[serde(default)]
+    fork: bool,
+    default_branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitHubSize {
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct GitLabSize {
+    statistics: Option<GitLabStatistics>,
+}
+
+#[derive(Deserialize)]
+struct GitLabStatistics {
+    #[serde(default)]
+    repository_size: u64,
+}
+
+#[derive(Deserialize)]
+struct GitLabProject {
+    path: String,
+    default_branch: Option<String>,
+    forked_from_project: Option<serde_json::Value>,
+    statistics: Option<GitLabStatistics>,
+}
+
+/// Client of the forge REST APIs (GitHub, GitLab, Gitea/Forgejo).
+#[derive(Clone)]
+pub struct Forge {
+    client: reqwest::Client,
+    apis: Arc<HashMap<String, (ForgeKind, String)>>,
+    /// API tokens per host, sent as `Authorization: Bearer`.
+    tokens: Arc<HashMap<String, String>>,
+}
+
+impl Forge {
+    pub fn new() -> Self {
+        Self::with_timeout(Duration::from_secs(10))
+    }
+
+    fn with_timeout(timeout: Duration) -> Self {
+        let apis = HashMap::from([
+            (
+                "github.com".to_string(),
+                (ForgeKind::GitHub, "https://api.github.com".to_string()),
+            ),
+            (
+                "gitlab.com".to_string(),
+                (ForgeKind::GitLab, "https://gitlab.com/api/v4".to_string()),
+            ),
+            (
+                "codeberg.org".to_string(),
+                (ForgeKind::Gitea, "https://codeberg.org/api/v1".to_string()),
+            ),
+            (
+                "gitea.com".to_string(),
+                (ForgeKind::Gitea, "https://gitea.com/api/v1".to_string()),
+            ),
+        ]);
+
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("cloc.info/", env!("CARGO_PKG_VERSION")))
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default();
+
+        Self {
+            client,
+            apis: Arc::new(apis),
+            tokens: Arc::default(),
+        }
+    }
+
+    /// Reads `FORGE_TIMEOUT_SECS` (default 10) and `FORGE_TOKENS` with `host=token` pairs
+    /// separated by `;`. Anonymous GitHub clients get only 60 requests an hour.
+    pub fn from_env() -> Self {
+        let timeout = std::env::var("FORGE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .filter(|secs| *secs > 0)
+            .unwrap_or(10);
+        let tokens = std::env::var("FORGE_TOKENS").unwrap_or_default();
+        tokens
+            .split(';')
+            .filter_map(|pair| pair.trim().split_once('='))
+            .fold(
+                Self::with_timeout(Duration::from_secs(timeout)),
+                |forge, (host, token)| forge.with_token(host.trim(), token.trim()),
+            )
+    }
+
+    /// Registers (or replaces) the API base url used for `host`.
+    pub fn with_api(mut self, host: &str, kind: ForgeKind, base_url: &str) -> Self {
+        Arc::make_mut(&mut self.apis).insert(
+            host.to_string(),
+            (kind, base_url.trim_end_matches('/').to_string()),
+        );
+        self
+    }
+
+    /// Authenticates the requests to the API of `host` with `token`.
+    pub fn with_token(mut self, host: &str, token: &str) -> Self {
+        if !token.is_empty() {
+            Arc::make_mut(&mut self.tokens).insert(host.to_ascii_lowercase(), token.to_string());
+        }
+        self
+    }
+
+    pub fn api(&self, host: &str) -> Result<&(ForgeKind, String), Error> {
+        self.apis.get(host).ok_or_else(|| Error::UnsupportedForge {
+            host: host.to_string(),
+        })
+    }
+
+    /// Public, non-fork repositories of a user or organisation.
+    pub async fn owner_repositories(
+        &self,
+        host: &str,
+        owner: &str,
+    ) -> Result<Vec<ForgeRepository>, Error> {
+        let (kind, base_url) = self.api(host)?;
+        let owner = path_segment(owner)?;
+
+        match kind {
+            ForgeKind::GitHub | ForgeKind::Gitea => {
+                let path = if *kind == ForgeKind::GitHub {
+                    format!("{base_url}/users/{owner}/repos?type=owner")
+                } else {
+                    format!("{base_url}/users/{owner}/repos")
+                };
+                let repositories: Vec<GitHubRepository> = self.paginate(host, &path).await?;
+                let scale = 1024; // Both APIs report size in kilobytes
+                Ok(repositories
+                    .into_iter()
+                    .filter(|repository| !repository.fork)
+                    .map(|repository| ForgeRepository {
+                        name: repository.name,
+                        size: repository.size * scale,
+                        default_branch: repository.default_branch,
+                    })
+                    .collect())
+            }
+            ForgeKind::GitLab => {
+                let group = format!(
+                    "{base_url}/groups/{owner}/projects?statistics=true&include_subgroups=false"
+                );
+                let projects: Vec<GitLabProject> = match self.paginate(host, &group).await {
+                    Err(Error::NotFound { .. }) => {
+                        let user = format!("{base_url}/users/{owner}/projects?statistics=true");
+                        self.paginate(host, &user).await?
+                    }
+                    result => result?,
+                };
+                Ok(projects
+                    .into_iter()
+                    .filter(|project| project.forked_from_project.is_none())
+                    .map(|project| ForgeRepository {
+                        name: project.path,
+                        size: project
+                            .statistics
+                            .map(|statistics| statistics.repository_size)
+                            .unwrap_or_default(),
+                        default_branch: project.default_branch,
+                    })
+                    .collect())
+            }
+        }
+    }
+
+    /// Size of a repository in bytes as reported by the forge, `None` if the forge is not
+    /// supported or doesn't tell (GitLab shows statistics to members only).
+    pub async fn repository_size(
+        &self,
+        host: &str,
+        owner: &str,
+        repository_name: &str,
+    ) -> Result<Option<u64>, Error> {
+        let Ok((kind, base_url)) = self.api(host) else {
+            return Ok(None);
+        };
+        let owner = path_segment(owner)?;
+        let name = path_segment(repository_name.trim_end_matches(".git"))?;
+
+        match kind {
+            ForgeKind::GitHub | ForgeKind::Gitea => {
+                let url = format!("{base_url}/repos/{owner}/{name}");
+                let repository: GitHubSize = self.get_json(host, &url).await?;
+                // Both APIs report size in kilobytes
+                Ok(repository.size.map(|size| size * 1024))
+            }
+            ForgeKind::GitLab => {
+                let url = format!("{base_url}/projects/{owner}%2F{name}?statistics=true");
+                let project: GitLabSize = self.get_json(host, &url).await?;
+                Ok(project
+                    .statistics
+                    .map(|statistics| statistics.repository_size))
+            }
+        }
+    }
+
+    async fn paginate<T: DeserializeOwned>(&self, host: &str, url: &str) -> Result<Vec<T>, Error> {
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let mut result = Vec::new();
+
+        for page in 1..=MAX_PAGES {
+            let url = format!("{url}{separator}per_page={PER_PAGE}&page={page}");
+            let items: Vec<T> = self.get_json(host, &url).await?;
+            let count = items.len();
+            result.extend(items);
+
+            if count < PER_PAGE {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+
+    pub(crate) async fn get_json<T: DeserializeOwned>(
+        &self,
+        host: &str,
+        url: &str,
+    ) -> Result<T, Error> {
+        let mut request = self.client.get(url);
+        if let Some(token) = self.tokens.get(&host.to_ascii_lowercase()) {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await.context(HttpSnafu { url })?;
+        let status = response.status();
+        let bytes = response.bytes().await.context(HttpSnafu { url })?;
+
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Err(Error::NotFound { url: url.into() });
+        }
+        if !status.is_success() {
+            return Err(Error::RemoteError {
+                url: url.into(),
+                message: format!("{status}: {}", String::from_utf8_lossy(&bytes)),
+            });
+        }
+
+        serde_json::from_slice(&bytes).context(DeserializeSnafu {
+            bytes: String::from_utf8_lossy(&bytes),
+            url,
+        })
+    }
+}
+
+impl Default for Forge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Owners and repository names go into the API urls as they are, anything that could
+/// change the path (`/`, `?`, `%`, `..`) is rejected.
+fn path_segment(value: &str) -> Result<&str, Error> {
+    let valid = !value.is_empty()
+        && !value.chars().all(|c| c == '.')
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if valid {
+        Ok(value)
+    } else {
+        Err(Error::InvalidName {
+            name: value.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{Error, Forge, ForgeKind};
+    use axum::{extract::Query, http::HeaderMap, routing::get, Json, Router};
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    /// Serves `router` on a random local port and returns its base url.
+    pub(crate) async fn spawn_api(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{address}")
+    }
+
+    #[tokio::test]
+    async fn lists_owner_repositories_without_forks() {
+        let router = Router::new().route(
+            "/users/acme/repos",
+            get(|Query(query): Query<HashMap<String, String>>| async move {
+                let page = query.get("page").cloned().unwrap_or_default();
+                let repositories: Vec<Value> = if page == "1" {
+                    (0..100)
+                        .map(|i| json!({ "name": format!("repo{i}"), "size": 1, "fork": i == 0 }))
+                        .collect()
+                } else {
+                    vec![json!({ "name": "last", "size": 2, "fork": false, "default_branch": "main" })]
+                };
+                Json(repositories)
+            }),
+        );
+        let base_url = spawn_api(router).await;
+        let forge = Forge::new().with_api("localhost", ForgeKind::GitHub, &base_url);
+
+        let repositories = forge.owner_repositories("localhost", "acme").await.unwrap();
+
+        assert_eq!(repositories.len(), 100);
+        assert_eq!(repositories.last().unwrap().name, "last");
+        assert_eq!(repositories.last().unwrap().size, 2048);
+    }
+
+    #[tokio::test]
+    async fn reads_repository_size() {
+        let router = Router::new()
+            .route(
+                "/repos/acme/tool",
+                get(|headers: HeaderMap| async move {
+                    let authorized = headers
+                        .get("authorization")
+                        .is_some_and(|value| value == "Bearer secret");
+                    Json(json!({ "name": "tool", "size": authorized.then_some(3) }))
+                }),
+            )
+            .route(
+                "/projects/:project",
+                get(|| async { Json(json!({ "path": "tool" })) }),
+            );
+        let base_url = spawn_api(router).await;
+        let forge = Forge::new()
+            .with_api("github.localhost", ForgeKind::GitHub, &base_url)
+            .with_api("gitlab.localhost", ForgeKind::GitLab, &base_url)
+            .with_token("GitHub.localhost", "secret");
+
+        let size = forge.repository_size("github.localhost", "acme", "tool.git");
+        assert_eq!(size.await.unwrap(), Some(3072));
+        let size = forge.repository_size("gitlab.localhost", "acme", "tool.git");
+        assert_eq!(size.await.unwrap(), None);
+        let size = forge.repository_size("git.example.com", "acme", "tool.git");
+        assert_eq!(size.await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn rejects_names_that_change_the_api_path() {
+        let forge = Forge::new().with_api("localhost", ForgeKind::GitHub, "http://127.0.0.1:9");
+
+        for owner in ["..", "acme/../orgs", "acme?type=all", "acme%2F..", ""] {
+            let error = forge.owner_repositories("localhost", owner).await;
+            assert!(matches!(error, Err(Error::InvalidName { .. })), "{owner}");
+        }
+        let error = forge.repository_size("localhost", "acme", "../../user");
+        assert!(matches!(error.await, Err(Error::InvalidName { .. })));
+    }
+}
@@ -1,56 +1,399 @@
 use super::{
-    info::{BranchValue, Branches},
-    {Error, LineSnafu, Utf8Snafu},
+    credentials::{git_command, Credential},
+    info::Branches,
+    smart_http::{advertised_refs, Advertisement},
+    {DeserializeSnafu, Error, LineSnafu, QuerySnafu, Utf8Snafu},
 };
+use bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
 use retainer::Cache;
 use snafu::{OptionExt, ResultExt};
-use std::{sync::Arc, time::Duration};
-use tokio::process::Command;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, time::Instant};
+use tokio_postgres::NoTls;
+
+/// Limit for listing the refs of a remote, over HTTP or with `git ls-remote`.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Branches seen with a credential are cached apart from the anonymous ones.
+fn cache_key(url: &str, credential: Option<&Credential>) -> String {
+    match credential {
+        Some(credential) => format!("{url}#{}", credential.fingerprint()),
+        None => url.to_string(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BranchCacheConfig {
+    pub ttl: Duration,
+    /// How long a failed lookup is answered from the cache, zero disables it.
+    pub negative_ttl: Duration,
+    /// Anonymous lookups are kept in the `branch_cache` table across restarts.
+    pub persist: bool,
+}
+
+impl Default for BranchCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(10),
+            persist: false,
+        }
+    }
+}
+
+impl BranchCacheConfig {
+    /// Reads `BRANCH_CACHE_TTL_SECS` (default 60), `BRANCH_CACHE_NEGATIVE_TTL_SECS` (default 10)
+    /// and `BRANCH_CACHE_PERSIST` (`true` or `1`, default off).
+    pub fn from_env() -> Self {
+        let seconds = |name: &str, default: Duration| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .map(Duration::from_secs)
+                .unwrap_or(default)
+        };
+        let default = Self::default();
+        Self {
+            ttl: seconds("BRANCH_CACHE_TTL_SECS", default.ttl),
+            negative_ttl: seconds("BRANCH_CACHE_NEGATIVE_TTL_SECS", default.negative_ttl),
+            persist: std::env::var("BRANCH_CACHE_PERSIST")
+                .is_ok_and(|value| matches!(value.trim(), "1" | "true")),
+        }
+    }
+}
+
+/// Failure kept in the negative cache, turned back into an error on every hit.
+#[derive(Debug, Clone)]
+enum Failure {
+    NotFound,
+    Authentication,
+    Unreachable(String),
+    Timeout,
+    Remote(String),
+}
+
+impl Failure {
+    /// Only failures that will repeat for a while, not malformed responses.
+    fn from_error(error: &Error) -> Option<Self> {
+        match error {
+            Error::RepositoryNotFound { .. } => Some(Failure::NotFound),
+            Error::AuthenticationRequired { .. } => Some(Failure::Authentication),
+            Error::RemoteUnreachable { message, .. } => Some(Failure::Unreachable(message.clone())),
+            Error::RemoteTimeout { .. } => Some(Failure::Timeout),
+            Error::RemoteError { message, .. } => Some(Failure::Remote(message.clone())),
+            _ => None,
+        }
+    }
+
+    fn to_error(&self, url: &str) -> Error {
+        let url = url.to_string();
+        match self {
+            Failure::NotFound => Error::RepositoryNotFound { url },
+            Failure::Authentication => Error::AuthenticationRequired { url },
+            Failure::Unreachable(message) => Error::RemoteUnreachable {
+                url,
+                message: message.clone(),
+            },
+            Failure::Timeout => Error::RemoteTimeout { url },
+            Failure::Remote(message) => Error::RemoteError {
+                url,
+                message: message.clone(),
+            },
+        }
+    }
+}
+
+/// Branches of anonymous lookups in the `branch_cache` table.
+#[derive(Clone)]
+struct BranchStore {
+    connection_pool: Pool<PostgresConnectionManager<NoTls>>,
+}
+
+impl BranchStore {
+    /// Stored branches younger than `ttl` with the time they have left.
+    async fn get(&self, url: &str, ttl: Duration) -> Result<Option<(Branches, Duration)>, Error> {
+        let connection = self.connection().await?;
+        let query = "select branches, updated from branch_cache where url=$1";
+        let Some(row) = connection
+            .query_opt(query, &[&url])
+            .await
+            .context(QuerySnafu { query })?
+        else {
+            return Ok(None);
+        };
+
+        let updated: DateTime<Utc> = row.get("updated");
+        let age = (Utc::now() - updated).to_std().unwrap_or_default();
+        let Some(left) = ttl.checked_sub(age).filter(|left| !left.is_zero()) else {
+            return Ok(None);
+        };
+        let bytes: String = row.get("branches");
+        let branches = serde_json::from_str(&bytes).context(DeserializeSnafu { bytes, url })?;
+        Ok(Some((branches, left)))
+    }
+
+    async fn put(&self, url: &str, branches: &Branches) -> Result<(), Error> {
+        let connection = self.connection().await?;
+        let query = "insert into branch_cache(url, branches, updated) values($1, $2, now()) \
+                     on conflict (url) do update set branches=excluded.branches, updated=excluded.updated";
+        let branches = serde_json::to_string(branches).unwrap_or_default();
+        connection
+            .execute(query, &[&url, &branches])
+            .await
+            .context(QuerySnafu { query })?;
+        Ok(())
+    }
+
+    async fn remove(&self, url: &str) -> Result<(), Error> {
+        let connection = self.connection().await?;
+        let query = "delete from branch_cache where url=$1";
+        connection
+            .execute(query, &[&url])
+            .await
+            .context(QuerySnafu { query })?;
+        Ok(())
+    }
+
+    async fn connection(
+        &self,
+    ) -> Result<bb8::PooledConnection<'_, PostgresConnectionManager<NoTls>>, Error> {
+        self.connection_pool
+            .get()
+            .await
+            .map_err(|error| Error::ConnectionPool {
+                error: error.to_string(),
+            })
+    }
+}
 
 #[derive(Clone)]
 pub struct Git {
     pub cache: Arc<Cache<String, Branches>>,
+    failures: Arc<Cache<String, Failure>>,
+    /// Held while a missing entry is looked up, so concurrent misses share one request.
+    lookups: Arc<DashMap<String, Arc<Mutex<()>>>>,
+    /// Keys of the entries cached with a credential per url and when they expire, so
+    /// `invalidate` can remove them too.
+    credentialed: Arc<DashMap<String, HashMap<String, Instant>>>,
+    store: Option<BranchStore>,
+    config: BranchCacheConfig,
+    client: reqwest::Client,
 }
 
 impl Git {
     pub fn new(cache: Arc<Cache<String, Branches>>) -> Self {
-        Self { cache }
+        let client = reqwest::Client::builder()
+            // Some forges only answer `info/refs` to git user agents
+            .user_agent(concat!(
+                "git/2.0 (cloc.info/",
+                env!("CARGO_PKG_VERSION"),
+                ")"
+            ))
+            .timeout(LOOKUP_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            cache,
+            failures: Arc::new(Cache::new()),
+            lookups: Arc::new(DashMap::new()),
+            credentialed: Arc::new(DashMap::new()),
+            store: None,
+            config: BranchCacheConfig::default(),
+            client,
+        }
     }
 
-    pub async fn all_branches(&self, url: &str) -> Result<Branches, Error> {
-        let branches = if let Some(branches) = self.cache.get(&url.to_string()).await {
-            tracing::info!("Get branches from cache");
-            branches.clone()
-        } else {
-            let branches = self::all_heads_branches(url).await?;
-            self.cache
-                .insert(url.to_string(), branches.clone(), Duration::from_secs(60))
-                .await;
-            tracing::info!("all_branches() Inserted branches into git_provider cache for {url}");
-            branches
+    pub fn with_config(mut self, config: BranchCacheConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Persists anonymous lookups, used when `config.persist` is set.
+    pub fn with_store(mut self, connection_pool: Pool<PostgresConnectionManager<NoTls>>) -> Self {
+        self.store = Some(BranchStore { connection_pool });
+        self
+    }
+
+    /// Removes expired entries of both caches, runs until aborted.
+    pub async fn monitor(&self) {
+        let frequency = Duration::from_secs(1);
+        let forget_credentialed = async {
+            let mut interval = tokio::time::interval(frequency);
+            loop {
+                interval.tick().await;
+                self.forget_expired_credentialed(Instant::now());
+            }
         };
+        tokio::join!(
+            self.cache.monitor(4, 0.25, frequency),
+            self.failures.monitor(4, 0.25, frequency),
+            forget_credentialed
+        );
+    }
+
+    fn forget_expired_credentialed(&self, now: Instant) {
+        self.credentialed.retain(|_url, keys| {
+            keys.retain(|_key, expires| *expires > now);
+            !keys.is_empty()
+        });
+    }
 
-        Ok(branches)
+    fn remember_credentialed(&self, url: &str, key: &str, ttl: Duration) {
+        self.credentialed
+            .entry(url.to_string())
+            .or_default()
+            .insert(key.to_string(), Instant::now() + ttl);
     }
 
-    pub async fn default_branch(&self, url: &str) -> Result<String, Error> {
-        let branch = if let Some(branches) = self.cache.get(&url.to_string()).await {
-            tracing::info!("Get branch {} from cache", branches.default_branch);
-            branches.default_branch.clone()
+    /// Reads the refs of http(s) remotes in-process, other urls go through `git ls-remote`.
+    pub async fn remote_branches(
+        &self,
+        url: &str,
+        credential: Option<&Credential>,
+    ) -> Result<Branches, Error> {
+        if url.starts_with("https://") || url.starts_with("http://") {
+            let advertisement = advertised_refs(&self.client, url, credential).await?;
+            Ok(advertisement.branches())
         } else {
-            let branches = self.all_branches(url).await?;
-            branches.default_branch
+            all_heads_branches(url, credential).await
+        }
+    }
+
+    pub async fn all_branches(
+        &self,
+        url: &str,
+        credential: Option<&Credential>,
+    ) -> Result<Branches, Error> {
+        let key = cache_key(url, credential);
+        if let Some(cached) = self.cached(&key, url).await {
+            return cached;
+        }
+
+        let lookup = self.lookups.entry(key.clone()).or_default().clone();
+        let guard = lookup.lock().await;
+        // The lookup we waited for has filled the cache
+        let result = match self.cached(&key, url).await {
+            Some(cached) => cached,
+            None => self.lookup(&key, url, credential).await,
         };
+        drop(guard);
+        // The map and `lookup` are the only references once nobody waits anymore
+        self.lookups
+            .remove_if(&key, |_, lookup| Arc::strong_count(lookup) <= 2);
+
+        result
+    }
 
+    async fn cached(&self, key: &String, url: &str) -> Option<Result<Branches, Error>> {
+        if let Some(branches) = self.cache.get(key).await {
+            tracing::info!("Get branches from cache");
+            return Some(Ok(branches.clone()));
+        }
+        if let Some(failure) = self.failures.get(key).await {
+            tracing::info!("Get failure of {url} from cache");
+            return Some(Err(failure.to_error(url)));
+        }
+        None
+    }
+
+    async fn lookup(
+        &self,
+        key: &str,
+        url: &str,
+        credential: Option<&Credential>,
+    ) -> Result<Branches, Error> {
+        // Only anonymous results are shared, so only they survive restarts
+        let store = self.store.as_ref().filter(|_| credential.is_none());
+        if let Some(store) = store {
+            match store.get(url, self.config.ttl).await {
+                Ok(Some((branches, left))) => {
+                    self.cache
+                        .insert(key.to_string(), branches.clone(), left)
+                        .await;
+                    return Ok(branches);
+                }
+                Ok(None) => {}
+                Err(error) => tracing::warn!("Can't read stored branches of {url}: {error}"),
+            }
+        }
+
+        match self.remote_branches(url, credential).await {
+            Ok(branches) => {
+                if credential.is_some() {
+                    self.remember_credentialed(url, key, self.config.ttl);
+                }
+                self.cache
+                    .insert(key.to_string(), branches.clone(), self.config.ttl)
+                    .await;
+                tracing::info!(
+                    "all_branches() Inserted branches into git_provider cache for {url}"
+                );
+                if let Some(store) = store {
+                    if let Err(error) = store.put(url, &branches).await {
+                        tracing::warn!("Can't store branches of {url}: {error}");
+                    }
+                }
+                Ok(branches)
+            }
+            Err(error) => {
+                let failure = Failure::from_error(&error);
+                if let Some(failure) = failure.filter(|_| !self.config.negative_ttl.is_zero()) {
+                    if credential.is_some() {
+                        self.remember_credentialed(url, key, self.config.negative_ttl);
+                    }
+                    self.failures
+                        .insert(key.to_string(), failure, self.config.negative_ttl)
+                        .await;
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Forgets the branches of `url` seen anonymously and with every credential.
+    pub async fn invalidate(&self, url: &str) {
+        let credentialed = self
+            .credentialed
+            .remove(url)
+            .map(|(_url, keys)| keys)
+            .unwrap_or_default();
+        for key in credentialed.into_keys().chain([cache_key(url, None)]) {
+            self.failures.remove(&key).await;
+            if self.cache.remove(&key).await.is_some() {
+                tracing::info!("invalidate() Removed branches of {url} from git_provider cache");
+            }
+        }
+        if let Some(store) = &self.store {
+            if let Err(error) = store.remove(url).await {
+                tracing::warn!("Can't remove stored branches of {url}: {error}");
+            }
+        }
+    }
+
+    pub async fn default_branch(
+        &self,
+        url: &str,
+        credential: Option<&Credential>,
+    ) -> Result<String, Error> {
+        let branch = self.all_branches(url, credential).await?.default_branch;
+
+        // Empty repositories and servers without `HEAD` have no default branch
+        if branch.is_empty() {
+            return Err(Error::ExtractDefaultBranchError { repo: url.into() });
+        }
         Ok(branch)
     }
 
-    pub async fn last_commit(&self, url: &str, branch: &str) -> Result<String, Error> {
-        let branches = if let Some(branches) = self.cache.get(&url.to_string()).await {
-            branches.clone()
-        } else {
-            self.all_branches(url).await?
-        };
+    pub async fn last_commit(
+        &self,
+        url: &str,
+        branch: &str,
+        credential: Option<&Credential>,
+    ) -> Result<String, Error> {
+        let branches = self.all_branches(url, credential).await?;
 
         branches
             .branches
@@ -63,84 +406,117 @@ impl Git {
     }
 }
 
-pub async fn all_heads_branches(url: &str) -> Result<Branches, Error> {
-    let mut command = Command::new("git");
+/// Lists refs with `git ls-remote --symref`, used for bundles and non-http remotes.
+pub async fn all_heads_branches(
+    url: &str,
+    credential: Option<&Credential>,
+) -> Result<Branches, Error> {
+    let mut command = git_command(credential);
 
-    let result = command
-        .args(["ls-remote", url])
-        .output()
+    command
+        .args(["ls-remote", "--symref", url])
+        .kill_on_drop(true);
+    let result = tokio::time::timeout(LOOKUP_TIMEOUT, command.output())
         .await
+        .map_err(|_| Error::RemoteTimeout { url: url.into() })?
         .map_err(|e| Error::Io {
             url: url.into(),
             source: e,
         })?;
 
     if !result.status.success() {
-        return Err(Error::BranchNotFound {
-            desc: String::from_utf8(result.stderr).context(Utf8Snafu { url })?,
-        });
+        let stderr = String::from_utf8(result.stderr).context(Utf8Snafu { url })?;
+        let stderr = match credential {
+            Some(credential) => credential.redact(&stderr),
+            None => stderr,
+        };
+        return Err(classify_failure(url, &stderr));
     }
 
-    let string = String::from_utf8(result.stdout).context(Utf8Snafu { url })?;
-    let lines: Vec<&str> = string.lines().collect();
-
-    let mut default_branch = String::new();
-    let first_line = lines.first();
-    let default_branch_commit = first_line.context(LineSnafu {
-        url,
-        desc: "No lines",
-    })?;
-    let default_branch_commit =
-        default_branch_commit
-            .split_whitespace()
-            .next()
-            .context(LineSnafu {
-                url,
-                desc: "Can't extract commit in splitted first line (HEAD)",
-            })?;
-
-    let filtered = lines.iter().filter(|line| line.contains("refs/heads/"));
-    let mut branches = Vec::with_capacity(100);
-    for line in filtered {
-        let mut splitted = line.split_whitespace();
-        let commit = splitted.next().context(LineSnafu {
-            url,
-            desc: "Can't extract commit",
-        })?;
-        let name = splitted
-            .next()
-            .context(LineSnafu {
-                url,
-                desc: "Can't extract branch ('refs/')",
-            })?
-            .trim_start_matches("refs/heads/")
-            .to_string();
+    let output = String::from_utf8(result.stdout).context(Utf8Snafu { url })?;
+    Ok(parse_ls_remote(url, &output)?.branches())
+}
 
-        if commit == default_branch_commit {
-            default_branch = name.clone();
-        }
+/// Typed error for the stderr of a git command that failed to talk to `url`.
+pub fn classify_failure(url: &str, stderr: &str) -> Error {
+    let lowercase = stderr.to_ascii_lowercase();
+    let mentions = |patterns: &[&str]| patterns.iter().any(|pattern| lowercase.contains(pattern));
+    let url = url.to_string();
+    let message = stderr.trim().to_string();
 
-        branches.push(BranchValue {
-            name,
-            commit: commit.to_string(),
-        })
+    if mentions(&["timed out", "timeout"]) {
+        Error::RemoteTimeout { url }
+    // Forges answer anonymous requests for missing repositories with an authentication prompt
+    } else if mentions(&[
+        "authentication failed",
+        "could not read username",
+        "could not read password",
+        "terminal prompts disabled",
+        "permission denied",
+        "returned error: 403",
+    ]) {
+        Error::AuthenticationRequired { url }
+    } else if mentions(&[
+        "repository not found",
+        "does not appear to be a git repository",
+        "does not exist",
+        "returned error: 404",
+    ]) {
+        Error::RepositoryNotFound { url }
+    } else if mentions(&[
+        "could not resolve host",
+        "name or service not known",
+        "failed to connect",
+        "couldn't connect",
+        "connection refused",
+        "connection reset",
+        "network is unreachable",
+    ]) {
+        Error::RemoteUnreachable { url, message }
+    } else {
+        Error::RemoteError { url, message }
     }
+}
 
-    Ok(Branches {
-        default_branch,
-        branches,
-    })
+/// Parses `<commit>\t<ref>` lines, `ref: <target>\tHEAD` gives the default branch.
+pub fn parse_ls_remote(url: &str, output: &str) -> Result<Advertisement, Error> {
+    let mut advertisement = Advertisement::default();
+    for line in output.lines() {
+        let (value, name) = line.split_once('\t').context(LineSnafu {
+            url,
+            desc: "Can't split line into commit and ref",
+        })?;
+        match value.strip_prefix("ref: ") {
+            Some(target) if name == "HEAD" => advertisement.head_target = Some(target.to_string()),
+            Some(_) => {}
+            None => advertisement
+                .refs
+                .push((name.to_string(), value.to_string())),
+        }
+    }
+    Ok(advertisement)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Git;
+    use super::{classify_failure, parse_ls_remote, BranchCacheConfig, Git};
     use crate::logic::{
+        credentials::Credential,
+        forge::tests::spawn_api,
         info::{BranchValue, Branches},
         Error,
     };
+    use axum::{extract::State, routing::get, Router};
+    use hyper::StatusCode;
     use retainer::Cache;
-    use std::{sync::Arc, time::Duration};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+    use tokio::time::Instant;
 
     #[tokio::test]
     async fn missing_branch_returns_error() {
@@ -155,14 +531,133 @@ mod tests {
                         name: "main".to_string(),
                         commit: "abc123".to_string(),
                     }],
+                    tags: Vec::new(),
                 },
                 Duration::from_secs(60),
             )
             .await;
 
         let git = Git::new(cache);
-        let error = git.last_commit(url, "missing").await.unwrap_err();
+        let error = git.last_commit(url, "missing", None).await.unwrap_err();
 
         assert!(matches!(error, Error::BranchNotFound { .. }));
     }
+
+    #[tokio::test]
+    async fn default_branch_comes_from_symref_line() {
+        let output = "ref: refs/heads/trunk\tHEAD\n\
+                      abc123\tHEAD\n\
+                      abc123\trefs/heads/main\n\
+                      abc123\trefs/heads/trunk\n\
+                      def456\trefs/tags/v1\n";
+        let branches = parse_ls_remote("repo", output).unwrap().branches();
+        assert_eq!(branches.default_branch, "trunk");
+        assert_eq!(branches.tags[0].commit, "def456");
+        assert!(parse_ls_remote("repo", "abc123 HEAD").is_err());
+
+        // A repository without branches has nothing to analyse
+        let url = "https://example.com/org/empty.git";
+        let cache = Arc::new(Cache::new());
+        cache
+            .insert(
+                url.to_string(),
+                Branches::default(),
+                Duration::from_secs(60),
+            )
+            .await;
+        let error = Git::new(cache).default_branch(url, None).await.unwrap_err();
+        assert!(matches!(error, Error::ExtractDefaultBranchError { .. }));
+    }
+
+    #[tokio::test]
+    async fn concurrent_misses_and_failures_share_one_lookup() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let router = Router::new()
+            .route(
+                "/org/repo.git/info/refs",
+                get(|State(hits): State<Arc<AtomicUsize>>| async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let line = format!("{} HEAD\0symref=HEAD:refs/heads/main\n", "1".repeat(40));
+                    let body = format!("{:04x}{line}0000", line.len() + 4);
+                    (
+                        [(
+                            "content-type",
+                            "application/x-git-upload-pack-advertisement",
+                        )],
+                        body,
+                    )
+                }),
+            )
+            .route(
+                "/org/missing.git/info/refs",
+                get(|State(hits): State<Arc<AtomicUsize>>| async move {
+                    hits.fetch_add(1, Ordering::SeqCst);
+                    StatusCode::NOT_FOUND
+                }),
+            )
+            .with_state(hits.clone());
+        let base_url = spawn_api(router).await;
+        let git = Git::new(Arc::new(Cache::new()));
+
+        let url = format!("{base_url}/org/repo.git");
+        let lookups = (0..5).map(|_| git.default_branch(&url, None));
+        for branch in futures_util::future::join_all(lookups).await {
+            assert_eq!(branch.unwrap(), "main");
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(git.lookups.is_empty());
+
+        let missing = format!("{base_url}/org/missing.git");
+        for _ in 0..2 {
+            let error = git.all_branches(&missing, None).await.unwrap_err();
+            assert!(matches!(error, Error::RepositoryNotFound { .. }));
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // Lookups with a credential are forgotten as well after a push
+        let credential = Credential::new(None, "token".to_string());
+        for _ in 0..2 {
+            git.all_branches(&url, Some(&credential)).await.unwrap();
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        git.invalidate(&url).await;
+        assert!(git.credentialed.is_empty());
+        git.all_branches(&url, Some(&credential)).await.unwrap();
+        git.all_branches(&url, None).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 5);
+
+        // Expired entries of credentials are forgotten without an invalidation
+        git.forget_expired_credentialed(Instant::now());
+        assert_eq!(git.credentialed.len(), 1);
+        git.forget_expired_credentialed(Instant::now() + BranchCacheConfig::default().ttl);
+        assert!(git.credentialed.is_empty());
+    }
+
+    #[test]
+    fn git_failures_are_classified() {
+        let url = "https://example.com/org/repo.git";
+        let classify = |stderr| classify_failure(url, stderr).code();
+
+        assert_eq!(
+            classify("fatal: could not read Username for 'https://github.com': terminal prompts disabled\n"),
+            "authentication_required"
+        );
+        assert_eq!(
+            classify("remote: Repository not found.\nfatal: repository 'https://example.com/org/repo.git/' not found\n"),
+            "repository_not_found"
+        );
+        assert_eq!(
+            classify("fatal: unable to access 'https://example.invalid/': Could not resolve host: example.invalid\n"),
+            "remote_unreachable"
+        );
+        assert_eq!(
+            classify("fatal: unable to access 'https://example.com/': Failed to connect to example.com port 443 after 30001 ms: Timeout was reached\n"),
+            "remote_timeout"
+        );
+        assert_eq!(
+            classify("fatal: '/tmp/missing' does not appear to be a git repository\n"),
+            "repository_not_found"
+        );
+    }
 }
@@ -1,7 +1,19 @@
-use chrono::{DateTime, Utc};
+use super::{
+    api_key::{ApiKey, Charge},
+    credentials::Credential,
+    forge::ForgeRepository,
+    repository::SERVICE_USER_AGENT,
+    summary::{LanguageStat, Summary},
+};
 use serde::{Deserialize, Serialize};
-use std::{cmp::Ordering, fmt::Display, path::PathBuf};
-use tokio_postgres::Row;
+use std::{cmp::Reverse, fmt::Display, net::IpAddr, path::PathBuf};
+use utoipa::ToSchema;
+
+pub use cloc_api::info::{
+    BranchValue, Branches, LargestBranch, LargestRepositories, LargestRepository, PopularBranch,
+    PopularRepositories, PopularRepository, RecentBranch, RecentRepositories, RecentRepository,
+    Status, TagValue,
+};
 
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq)]
 pub struct StorageInfo {
@@ -16,18 +28,6 @@ pub struct RepositoryInfo {
     pub name: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub struct BranchValue {
-    pub name: String,
-    pub commit: String,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
-pub struct Branches {
-    pub default_branch: String,
-    pub branches: Vec<BranchValue>,
-}
-
 impl RepositoryInfo {
     pub fn new(hostname: &str, owner: &str, name: &str) -> Self {
         Self {
@@ -85,6 +85,21 @@ pub fn to_unique_name(host: &str, owner: &str, repository_name: &str, branch: &s
     format!("{host}/{owner}/{repository_name}/{branch}")
 }
 
+/// Statuses of private repositories are only reachable with the same credential.
+pub fn to_private_name(credential: &Credential, unique_name: &str) -> String {
+    format!("private/{}/{unique_name}", credential.fingerprint())
+}
+
+/// Name of the status of an analysis cloned with `credential`. Results of the operator's
+/// shared credentials have their own namespace, only computed for requesters that may use them.
+pub fn to_scoped_name(credential: Option<&Credential>, unique_name: String) -> String {
+    match credential {
+        Some(credential) if credential.is_shared() => format!("private/shared/{unique_name}"),
+        Some(credential) => to_private_name(credential, &unique_name),
+        None => unique_name,
+    }
+}
+
 #[derive(Debug, Clone, PartialOrd, PartialEq, Eq)]
 pub struct CocomoInfo {
     pub cost_develop: String,
@@ -106,327 +121,240 @@ pub struct Task {
     pub branch: String,
     pub default_branch: String,
     pub user_agent: String,
-}
-
-impl Task {
-    pub fn to_unique_name(&self) -> String {
-        to_unique_name(&self.host, &self.owner, &self.repository_name, &self.branch)
-    }
-
-    pub fn to_path(&self) -> String {
-        format!("{}/{}/{}", self.host, self.owner, self.repository_name)
-    }
-
-    pub fn to_url(&self) -> String {
-        to_url(&self.host, &self.owner, &self.repository_name)
-    }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Status {
-    InProgress(String),
-    Cloned,
-    Previous {
-        date: DateTime<Utc>,
-        commit: String,
-        data: Vec<u8>,
-    },
-    Done(Vec<u8>),
-    Ready,
-    Error(String),
-}
-
-impl Display for Status {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Status::Done(_) => write!(f, "Done"),
-            Status::InProgress(text) => write!(f, "{}", text),
-            Status::Cloned => write!(f, "Cloned"),
-            Status::Ready => write!(f, "Ready"),
-            Status::Error(e) => write!(f, "Error: {e}"),
-            Status::Previous { .. } => write!(f, "Previous"),
+    /// Access token of a private repository, never serialized or stored.
+    #[serde(skip)]
+    pub credential: Option<Credential>,
+    /// Quota charge of the API key that started the analysis, gets the cloned size.
+    #[serde(skip)]
+    pub usage: Option<Charge>,
+    #[serde(default)]
+    pub options: CloneOptions,
+}
+
+/// What of the repository is analysed, requested with `?paths=src,lib&submodules=true`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CloneOptions {
+    /// Directories or files to check out, everything if empty.
+    #[serde(default, deserialize_with = "comma_separated")]
+    pub paths: Vec<String>,
+    /// Check out submodules recursively.
+    #[serde(default)]
+    pub submodules: bool,
+}
+
+fn comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    Ok(normalize_paths(value.split(',')))
+}
+
+/// Trimmed, sorted and deduplicated paths without empty ones and ones leaving the repository.
+fn normalize_paths<'a>(paths: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut paths: Vec<String> = paths
+        .map(|path| path.trim().trim_matches('/'))
+        .filter(|path| !path.is_empty() && !path.split('/').any(|part| part == ".."))
+        .map(str::to_string)
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+impl CloneOptions {
+    pub fn new(paths: &[String], submodules: bool) -> Self {
+        Self {
+            paths: normalize_paths(paths.iter().map(String::as_str)),
+            submodules,
         }
     }
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct PopularBranch {
-    branch_name: String,
-    count: i64,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct PopularRepository {
-    hostname: String,
-    owner: String,
-    repository_name: String,
-    total_count: i64,
-    branches: Vec<PopularBranch>,
-}
-
-impl PartialOrd for PopularRepository {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
 
-impl Ord for PopularRepository {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.total_count.cmp(&self.total_count)
+    pub fn is_default(&self) -> bool {
+        self.paths.is_empty() && !self.submodules
     }
-}
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize)]
-pub struct PopularRepositories {
-    pub repositories: Vec<PopularRepository>,
-}
-
-impl PopularRepositories {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn push(
-        &mut self,
-        hostname: String,
-        owner: String,
-        repository_name: String,
-        branch: String,
-        count: i64,
-    ) {
-        let branch = PopularBranch {
-            branch_name: branch,
-            count,
-        };
-        if let Some(repository) = self
-            .repositories
-            .iter_mut()
-            .find(|r| r.repository_name == repository_name)
-        {
-            repository.total_count += count;
-
-            repository.branches.push(branch);
+    /// Results of different options are separate, the default keeps the plain name.
+    pub fn to_unique_name(&self, unique_name: &str) -> String {
+        if self.is_default() {
+            unique_name.to_string()
         } else {
-            let total_count = branch.count;
-            let branches = vec![branch];
-            let repository = PopularRepository {
-                hostname,
-                owner,
-                repository_name,
-                total_count,
-                branches,
-            };
-            self.repositories.push(repository)
+            format!("{unique_name}?{self}")
         }
-        self.repositories.sort();
-    }
-
-    pub fn top(&self, limit: usize) -> Vec<PopularRepository> {
-        self.repositories.iter().take(limit).cloned().collect()
     }
 }
 
-impl From<Vec<Row>> for PopularRepositories {
-    fn from(rows: Vec<Row>) -> Self {
-        let mut repositories = Self::default();
-
-        for row in rows {
-            let hostname: String = row.get("hostname");
-            let owner: String = row.get("owner");
-            let repository_name: String = row.get("repository_name");
-            let branch: String = row.get("name");
-            let count: i64 = row.get("count");
-            repositories.push(hostname, owner, repository_name, branch, count);
+/// Query string form, also stored with the result.
+impl Display for CloneOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::with_capacity(2);
+        if !self.paths.is_empty() {
+            parts.push(format!("paths={}", self.paths.join(",")));
         }
-        repositories
-    }
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct LargestBranch {
-    branch_name: String,
-    size: u64,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct LargestRepository {
-    hostname: String,
-    owner: String,
-    repository_name: String,
-    size: u64,
-    branches: Vec<LargestBranch>,
-}
-
-impl PartialOrd for LargestRepository {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
-}
-
-impl Ord for LargestRepository {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.size.cmp(&self.size)
+        if self.submodules {
+            parts.push("submodules=true".to_string());
+        }
+        write!(f, "{}", parts.join("&"))
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct LargestRepositories {
-    repositories: Vec<LargestRepository>,
+/// Who asks for an analysis.
+#[derive(Debug, Clone, Default)]
+pub struct Requester {
+    pub user_agent: String,
+    pub credential: Option<Credential>,
+    pub api_key: Option<ApiKey>,
+    /// Address of an anonymous client, new clones count against its rate limit.
+    pub client_ip: Option<IpAddr>,
+    /// Refreshes queued by the service itself, such as webhooks and the scheduler.
+    pub service: bool,
 }
 
-impl LargestRepositories {
-    pub fn new() -> Self {
-        Default::default()
+impl Requester {
+    pub fn anonymous(user_agent: String) -> Self {
+        Self {
+            user_agent,
+            ..Default::default()
+        }
     }
 
-    pub fn push(
-        &mut self,
-        hostname: String,
-        owner: String,
-        repository_name: String,
-        branch: String,
-        size: u64,
-    ) {
-        let branch = LargestBranch {
-            branch_name: branch,
-            size,
-        };
-        if let Some(repository) = self
-            .repositories
-            .iter_mut()
-            .find(|r| r.repository_name == repository_name)
-        {
-            repository.size = repository.size.max(size);
-            repository.branches.push(branch);
-        } else {
-            let branches = vec![branch];
-            let repository = LargestRepository {
-                hostname,
-                owner,
-                repository_name,
-                size,
-                branches,
-            };
-            self.repositories.push(repository);
-            self.repositories.sort();
+    pub fn service() -> Self {
+        Self {
+            user_agent: SERVICE_USER_AGENT.to_string(),
+            service: true,
+            ..Default::default()
         }
     }
 
-    pub fn top(&self, limit: usize) -> Vec<LargestRepository> {
-        self.repositories.iter().take(limit).cloned().collect()
+    /// Whether the operator's credential of a host may be used: anonymous clients could
+    /// otherwise read every private repository it has access to.
+    pub fn may_use_shared_credential(&self) -> bool {
+        self.service || self.api_key.is_some()
     }
 }
 
-impl From<Vec<Row>> for LargestRepositories {
-    fn from(rows: Vec<Row>) -> Self {
-        let mut repositories = Self::default();
-
-        for row in rows {
-            let hostname: String = row.get("hostname");
-            let owner: String = row.get("owner");
-            let repository_name: String = row.get("repository_name");
-            let branch: String = row.get("name");
-            let size = row.get::<&str, i64>("size") as u64;
-            repositories.push(hostname, owner, repository_name, branch, size);
-        }
-        repositories
+impl Task {
+    pub fn to_unique_name(&self) -> String {
+        let unique_name = self.options.to_unique_name(&to_unique_name(
+            &self.host,
+            &self.owner,
+            &self.repository_name,
+            &self.branch,
+        ));
+        to_scoped_name(self.credential.as_ref(), unique_name)
     }
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct RecentBranch {
-    branch_name: String,
-    time: DateTime<Utc>,
-}
-
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct RecentRepository {
-    hostname: String,
-    owner: String,
-
-    repository_name: String,
-    time: DateTime<Utc>,
-    branches: Vec<RecentBranch>,
-}
 
-impl PartialOrd for RecentRepository {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+    pub fn to_path(&self) -> String {
+        format!("{}/{}/{}", self.host, self.owner, self.repository_name)
     }
-}
 
-impl Ord for RecentRepository {
-    fn cmp(&self, other: &Self) -> Ordering {
-        other.time.cmp(&self.time)
+    pub fn to_url(&self) -> String {
+        to_url(&self.host, &self.owner, &self.repository_name)
     }
 }
 
-#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq)]
-pub struct RecentRepositories {
-    repositories: Vec<RecentRepository>,
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct OwnerRepository {
+    pub repository_name: String,
+    pub size: u64,
+    /// `done`, `previous`, `error`, `pending` or `not_analysed`.
+    pub status: String,
+    pub code: Option<u64>,
 }
 
-impl RecentRepositories {
-    pub fn new() -> Self {
-        Default::default()
-    }
-    pub fn push(
-        &mut self,
-        hostname: String,
-        owner: String,
-        repository_name: String,
-        branch: String,
-        time: DateTime<Utc>,
-    ) {
-        let branch = RecentBranch {
-            branch_name: branch,
-            time,
-        };
-        if let Some(repository) = self
-            .repositories
-            .iter_mut()
-            .find(|r| r.repository_name == repository_name)
-        {
-            repository.time = repository.time.max(time);
-            repository.branches.push(branch);
-        } else {
-            let branches = vec![branch];
-            let repository = RecentRepository {
-                hostname,
-                owner,
-                repository_name,
-                time,
-                branches,
+/// Aggregated report over all repositories of a user or organisation.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct OwnerReport {
+    pub hostname: String,
+    pub owner: String,
+    pub languages: Vec<LanguageStat>,
+    pub total: LanguageStat,
+    pub repositories: Vec<OwnerRepository>,
+}
+
+impl OwnerReport {
+    /// `None` for repositories that were never analysed.
+    pub fn new(
+        hostname: &str,
+        owner: &str,
+        results: Vec<(ForgeRepository, Option<Status>)>,
+    ) -> Self {
+        let mut summary = Summary::default();
+        let mut repositories = Vec::with_capacity(results.len());
+
+        for (repository, status) in results {
+            let (status, data) = match status {
+                Some(Status::Done(data)) => ("done", Some(data)),
+                Some(Status::Previous { data, .. }) => ("previous", Some(data)),
+                Some(Status::Error(_)) => ("error", None),
+                Some(Status::InProgress(_) | Status::Cloned | Status::Ready) => ("pending", None),
+                None => ("not_analysed", None),
             };
-            self.repositories.push(repository);
-            self.repositories.sort();
+            let code = data.map(|data| {
+                let repository_summary = Summary::parse(&data);
+                summary.merge(&repository_summary);
+                repository_summary.total.code
+            });
+
+            repositories.push(OwnerRepository {
+                repository_name: repository.name,
+                size: repository.size,
+                status: status.to_string(),
+                code,
+            });
         }
-    }
+        repositories.sort_by_key(|repository| Reverse(repository.size));
 
-    pub fn top(&self, limit: usize) -> Vec<RecentRepository> {
-        self.repositories.iter().take(limit).cloned().collect()
+        Self {
+            hostname: hostname.to_string(),
+            owner: owner.to_string(),
+            languages: summary.languages,
+            total: summary.total,
+            repositories,
+        }
     }
 }
 
-impl From<Vec<Row>> for RecentRepositories {
-    fn from(rows: Vec<Row>) -> Self {
-        let mut repositories: RecentRepositories = RecentRepositories::new();
-
-        for row in rows {
-            let hostname: String = row.get("hostname");
-            let owner: String = row.get("owner");
-            let repository_name: String = row.get("repository_name");
-            let branch: String = row.get("name");
-            let time: DateTime<Utc> = row.get("time");
-            repositories.push(hostname, owner, repository_name, branch, time);
+impl Display for OwnerReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}/{}", self.hostname, self.owner)?;
+        writeln!(
+            f,
+            "{:<24} {:>8} {:>10} {:>10} {:>10}",
+            "Language", "Files", "Lines", "Comments", "Code"
+        )?;
+        for stat in self.languages.iter().chain(std::iter::once(&self.total)) {
+            writeln!(
+                f,
+                "{:<24} {:>8} {:>10} {:>10} {:>10}",
+                stat.language, stat.files, stat.lines, stat.comments, stat.code
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<40} {:>12} {:>10} {:>10}",
+            "Repository", "Size", "Status", "Code"
+        )?;
+        for repository in &self.repositories {
+            let code = repository
+                .code
+                .map(|code| code.to_string())
+                .unwrap_or_else(|| "-".to_string());
+            writeln!(
+                f,
+                "{:<40} {:>12} {:>10} {:>10}",
+                repository.repository_name, repository.size, repository.status, code
+            )?;
         }
-        repositories
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{to_repository_path, to_url};
+    use super::{to_repository_path, to_url, OwnerReport, Status};
+    use crate::logic::forge::ForgeRepository;
 
     #[test]
     fn standard_hosts_keep_standard_repository_path() {
@@ -451,4 +379,38 @@ mod tests {
             "https://gitflic.ru/project/red-soft/fbx.git"
         );
     }
+
+    #[test]
+    fn owner_report_aggregates_finished_repositories() {
+        let output = b"Language Files Lines Blanks Comments Code Complexity\n\
+                       Rust 1 10 1 1 8 0\n\
+                       Total 1 10 1 1 8 0\n";
+        let repository = |name: &str, size| ForgeRepository {
+            name: name.to_string(),
+            size,
+            default_branch: None,
+        };
+
+        let report = OwnerReport::new(
+            "github.com",
+            "acme",
+            vec![
+                (repository("small", 10), Some(Status::Done(output.to_vec()))),
+                (repository("large", 99), Some(Status::Done(output.to_vec()))),
+                (repository("queued", 50), Some(Status::Ready)),
+                (repository("new", 1), None),
+            ],
+        );
+
+        assert_eq!(report.total.code, 16);
+        assert_eq!(report.languages[0].code, 16);
+        let names: Vec<_> = report
+            .repositories
+            .iter()
+            .map(|repository| repository.repository_name.as_str())
+            .collect();
+        assert_eq!(names, ["large", "queued", "small", "new"]);
+        assert_eq!(report.repositories[1].code, None);
+        assert_eq!(report.repositories[3].status, "not_analysed");
+    }
 }
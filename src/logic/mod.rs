@@ -1,10 +1,25 @@
+pub mod api_key;
+pub mod archive;
+pub mod bundle;
+pub mod callback;
 pub mod cloner;
+pub mod credentials;
+pub mod forge;
 pub mod git;
 pub mod info;
+pub mod preflight;
+pub mod rate_limit;
 pub mod repository;
+pub mod scheduler;
+pub mod smart_http;
+pub mod summary;
+pub mod upload;
 
+use cloc_api::problem::Failure;
+use hyper::StatusCode;
 use snafu::Snafu;
 use std::string::FromUtf8Error;
+use tokio::time::Instant;
 
 type Id = i64;
 
@@ -24,6 +39,18 @@ pub enum Error {
     #[snafu(display("Error at API request {url} message: {message}"))]
     RemoteError { url: String, message: String },
 
+    #[snafu(display("Repository {url} not found"))]
+    RepositoryNotFound { url: String },
+
+    #[snafu(display("Repository {url} is private or requires authentication"))]
+    AuthenticationRequired { url: String },
+
+    #[snafu(display("Can't reach {url}: {message}"))]
+    RemoteUnreachable { url: String, message: String },
+
+    #[snafu(display("Request to {url} timed out"))]
+    RemoteTimeout { url: String },
+
     #[snafu(display("Can't extract default branch for repository {repo}"))]
     ExtractDefaultBranchError { repo: String },
 
@@ -60,6 +87,22 @@ pub enum Error {
     #[snafu(display("Error at cloning (git clone) repository {repository}: {error}"))]
     CloneError { repository: String, error: String },
 
+    #[snafu(display("Cloning repository {repository} took longer than {seconds} seconds"))]
+    CloneTimeout { repository: String, seconds: u64 },
+
+    #[snafu(display("Repository {repository} is larger than {limit} bytes"))]
+    CloneTooLarge { repository: String, limit: u64 },
+
+    #[snafu(display("Repository {repository} is {size}, larger than the limit of {limit}"))]
+    RepositoryTooLarge {
+        repository: String,
+        size: String,
+        limit: String,
+    },
+
+    #[snafu(display("Cloning repository {repository} was cancelled"))]
+    CloneCancelled { repository: String },
+
     #[snafu(display("Error at pulling (git pull) repository {url}: {error}"))]
     PullError { url: String, error: String },
 
@@ -100,4 +143,108 @@ pub enum Error {
 
     #[snafu(display("Rejected insertion to disk cache for {path}"))]
     Rejected { path: String },
+
+    #[snafu(display("Error at HTTP request {url}: {source}"))]
+    Http { url: String, source: reqwest::Error },
+
+    #[snafu(display("Forge API of host {host} is not supported"))]
+    UnsupportedForge { host: String },
+
+    #[snafu(display("'{name}' is not a valid owner or repository name"))]
+    InvalidName { name: String },
+
+    #[snafu(display("Can't extract archive: {error}"))]
+    Archive { error: String },
+
+    #[snafu(display("Archive entry '{path}' escapes the destination or is a link"))]
+    UnsafeArchiveEntry { path: String },
+
+    #[snafu(display("'{path}' conflicts with a file or directory extracted before"))]
+    ConflictingUploadPath { path: String },
+
+    #[snafu(display("Extracted archive exceeds {limit} bytes"))]
+    ArchiveTooLarge { limit: u64 },
+
+    #[snafu(display("Archive has more than {limit} entries"))]
+    TooManyArchiveEntries { limit: usize },
+
+    #[snafu(display("{desc}"))]
+    QuotaExceeded { desc: String },
+
+    #[snafu(display("Too many new analyses from this address, retry in {retry_after} seconds"))]
+    RateLimited { retry_after: u64 },
+}
+
+impl Error {
+    /// Stable machine-readable identifier of the failure for API clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::NotFound { .. } | Error::RepositoryNotFound { .. } => "repository_not_found",
+            Error::AuthenticationRequired { .. } => "authentication_required",
+            Error::RemoteError { .. } | Error::RemoteUnreachable { .. } | Error::Http { .. } => {
+                "remote_unreachable"
+            }
+            Error::RemoteTimeout { .. } => "remote_timeout",
+            Error::BranchNotFound { .. } | Error::WrongBranch { .. } => "branch_not_found",
+            Error::ExtractDefaultBranchError { .. } => "default_branch_not_found",
+            Error::RepositoryTooLarge { .. } | Error::CloneTooLarge { .. } => {
+                "repository_too_large"
+            }
+            Error::CloneTimeout { .. } => "clone_timeout",
+            Error::QuotaExceeded { .. } => "quota_exceeded",
+            Error::RateLimited { .. } => "rate_limited",
+            Error::InProgress { .. } => "in_progress",
+            Error::UnsupportedForge { .. } => "unsupported_forge",
+            Error::InvalidName { .. } => "invalid_name",
+            _ => "internal_error",
+        }
+    }
+
+    /// HTTP status of the failure, shared by all handlers.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NotFound { .. }
+            | Error::RepositoryNotFound { .. }
+            | Error::BranchNotFound { .. }
+            | Error::WrongBranch { .. }
+            | Error::ExtractDefaultBranchError { .. }
+            | Error::UnsupportedForge { .. } => StatusCode::NOT_FOUND,
+            Error::InvalidName { .. } => StatusCode::BAD_REQUEST,
+            Error::AuthenticationRequired { .. } => StatusCode::UNAUTHORIZED,
+            Error::RemoteError { .. } | Error::RemoteUnreachable { .. } | Error::Http { .. } => {
+                StatusCode::BAD_GATEWAY
+            }
+            Error::RemoteTimeout { .. } | Error::CloneTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Error::RepositoryTooLarge { .. } | Error::CloneTooLarge { .. } => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            Error::InProgress { .. } => StatusCode::CONFLICT,
+            Error::QuotaExceeded { .. } | Error::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// `CloneTimeout` counted from `started`, when the whole work began, rather than from
+    /// the git command that was stopped.
+    pub fn timed_out_since(self, started: Instant) -> Self {
+        match self {
+            Error::CloneTimeout { repository, .. } => Error::CloneTimeout {
+                repository,
+                seconds: started.elapsed().as_secs(),
+            },
+            error => error,
+        }
+    }
+
+    /// The failure as kept in the status of a task, so it is reported as it would have been
+    /// by the request that started it.
+    pub fn failure(&self) -> Failure {
+        Failure {
+            code: self.code().to_string(),
+            status: self.status().as_u16(),
+            detail: self.to_string(),
+        }
+    }
 }
@@ -0,0 +1,117 @@
+use super::Error;
+
+/// Limits applied to the size the forge reports before a repository is cloned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizePolicy {
+    /// Larger repositories are not cloned at all.
+    pub reject_above: Option<u64>,
+    /// Larger repositories wait for one of `large_concurrency` slots.
+    pub large_above: Option<u64>,
+    pub large_concurrency: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeClass {
+    /// The forge is not supported or didn't report a size.
+    Unknown,
+    Regular(u64),
+    Large(u64),
+}
+
+impl SizeClass {
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            SizeClass::Unknown => None,
+            SizeClass::Regular(size) | SizeClass::Large(size) => Some(*size),
+        }
+    }
+}
+
+impl SizePolicy {
+    /// Reads `PREFLIGHT_MAX_BYTES` (default 2 GiB), `PREFLIGHT_LARGE_BYTES` (default 256 MiB),
+    /// 0 disables either, and `LARGE_CLONE_CONCURRENCY` (default 1).
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: u64) -> u64 {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let reject_above = var("PREFLIGHT_MAX_BYTES", 2 * 1024 * 1024 * 1024);
+        let large_above = var("PREFLIGHT_LARGE_BYTES", 256 * 1024 * 1024);
+        Self {
+            reject_above: (reject_above > 0).then_some(reject_above),
+            large_above: (large_above > 0).then_some(large_above),
+            large_concurrency: var("LARGE_CLONE_CONCURRENCY", 1).max(1) as usize,
+        }
+    }
+
+    pub fn classify(&self, repository: &str, size: Option<u64>) -> Result<SizeClass, Error> {
+        let Some(size) = size else {
+            return Ok(SizeClass::Unknown);
+        };
+        if let Some(limit) = self.reject_above.filter(|limit| size > *limit) {
+            return Err(Error::RepositoryTooLarge {
+                repository: repository.to_string(),
+                size: format_bytes(size),
+                limit: format_bytes(limit),
+            });
+        }
+        if self.large_above.is_some_and(|limit| size > limit) {
+            Ok(SizeClass::Large(size))
+        } else {
+            Ok(SizeClass::Regular(size))
+        }
+    }
+}
+
+/// `1.5 MiB` style size for statuses and messages.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_bytes, SizeClass, SizePolicy};
+    use crate::logic::Error;
+
+    #[test]
+    fn large_repositories_are_rejected_or_deprioritised() {
+        let policy = SizePolicy {
+            reject_above: Some(10 * 1024 * 1024),
+            large_above: Some(1024 * 1024),
+            large_concurrency: 1,
+        };
+
+        assert_eq!(policy.classify("repo", None).unwrap(), SizeClass::Unknown);
+        assert_eq!(
+            policy.classify("repo", Some(1024)).unwrap(),
+            SizeClass::Regular(1024)
+        );
+        assert_eq!(
+            policy.classify("repo", Some(2 * 1024 * 1024)).unwrap(),
+            SizeClass::Large(2 * 1024 * 1024)
+        );
+        let error = policy.classify("repo", Some(11 * 1024 * 1024)).unwrap_err();
+        assert!(matches!(error, Error::RepositoryTooLarge { .. }));
+        assert_eq!(
+            error.to_string(),
+            "Repository repo is 11.0 MiB, larger than the limit of 10.0 MiB"
+        );
+
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+    }
+}
@@ -0,0 +1,345 @@
+use super::Error;
+use dashmap::DashMap;
+use hyper::HeaderMap;
+use std::{
+    net::{IpAddr, Ipv6Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Number of tracked buckets above which refilled ones are dropped.
+const PRUNE_THRESHOLD: usize = 10_000;
+/// Buckets are walked at most this often, however many there are.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Budget {
+    /// Every request, mostly served from the database or the cache.
+    Read,
+    /// Requests that queue a new clone.
+    Clone,
+}
+
+/// Address of an anonymous client, added to the request extensions by the middleware.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Token bucket refilled at `rate` tokens per second up to `burst`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl BucketLimit {
+    pub fn per_minute(count: u32, burst: u32) -> Self {
+        Self {
+            rate: f64::from(count) / 60.0,
+            burst: f64::from(burst.max(1)),
+        }
+    }
+
+    pub fn per_hour(count: u32, burst: u32) -> Self {
+        Self {
+            rate: f64::from(count) / 3600.0,
+            burst: f64::from(burst.max(1)),
+        }
+    }
+}
+
+/// Header with the client address set by a trusted reverse proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpHeader {
+    /// `CF-Connecting-IP` or another header holding a single address.
+    Single(String),
+    /// `X-Forwarded-For`, the client is `proxies` entries from the right.
+    ForwardedFor { proxies: usize },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    /// `None` disables the budget.
+    pub read: Option<BucketLimit>,
+    pub clone: Option<BucketLimit>,
+    /// Without a trusted header the peer address is used.
+    pub ip_header: Option<IpHeader>,
+}
+
+impl RateLimits {
+    /// Reads `RATE_LIMIT_READS_PER_MINUTE` (default 120) with `RATE_LIMIT_READ_BURST` (60),
+    /// `RATE_LIMIT_CLONES_PER_HOUR` (20) with `RATE_LIMIT_CLONE_BURST` (5), 0 disables a budget.
+    /// `RATE_LIMIT_IP_HEADER` names the header of the trusted proxy, e.g. `CF-Connecting-IP`;
+    /// for `X-Forwarded-For` `RATE_LIMIT_TRUSTED_PROXIES` (default 1) proxies append to it.
+    pub fn from_env() -> Self {
+        fn var(name: &str, default: u32) -> u32 {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let reads = var("RATE_LIMIT_READS_PER_MINUTE", 120);
+        let clones = var("RATE_LIMIT_CLONES_PER_HOUR", 20);
+        let ip_header = std::env::var("RATE_LIMIT_IP_HEADER")
+            .ok()
+            .filter(|name| !name.trim().is_empty())
+            .map(|name| {
+                if name.trim().eq_ignore_ascii_case("x-forwarded-for") {
+                    IpHeader::ForwardedFor {
+                        proxies: var("RATE_LIMIT_TRUSTED_PROXIES", 1).max(1) as usize,
+                    }
+                } else {
+                    IpHeader::Single(name.trim().to_string())
+                }
+            });
+
+        Self {
+            read: (reads > 0)
+                .then(|| BucketLimit::per_minute(reads, var("RATE_LIMIT_READ_BURST", 60))),
+            clone: (clones > 0)
+                .then(|| BucketLimit::per_hour(clones, var("RATE_LIMIT_CLONE_BURST", 5))),
+            ip_header,
+        }
+    }
+
+    fn limit(&self, budget: Budget) -> Option<BucketLimit> {
+        match budget {
+            Budget::Read => self.read,
+            Budget::Clone => self.clone,
+        }
+    }
+
+    /// Client address, taken from the trusted header if one is configured.
+    pub fn client_ip(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Option<IpAddr> {
+        let Some(ip_header) = &self.ip_header else {
+            return peer;
+        };
+        let value = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let ip = match ip_header {
+            IpHeader::Single(name) => value(name).and_then(|value| value.trim().parse().ok()),
+            IpHeader::ForwardedFor { proxies } => value("x-forwarded-for").and_then(|value| {
+                // Entries to the left of the trusted proxies are set by the client
+                value
+                    .rsplit(',')
+                    .nth(proxies - 1)
+                    .and_then(|ip| ip.trim().parse().ok())
+            }),
+        };
+        ip.or(peer)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: BucketLimit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(limit.burst);
+        self.updated = now;
+    }
+}
+
+/// Token buckets per client address and budget.
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: Arc<RateLimits>,
+    buckets: Arc<DashMap<(IpAddr, Budget), Bucket>>,
+    last_prune: Arc<Mutex<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: Arc::new(limits),
+            buckets: Arc::new(DashMap::new()),
+            last_prune: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    pub fn limits(&self) -> &RateLimits {
+        &self.limits
+    }
+
+    /// Takes a token of `budget`, returns how long to wait for the next one if there is none.
+    /// IPv6 clients share the bucket of their /64, a single host usually gets a whole one.
+    pub fn take(&self, ip: IpAddr, budget: Budget) -> Result<(), Duration> {
+        self.take_at(ip, budget, Instant::now())
+    }
+
+    /// `take` for the clone budget as a service error.
+    pub fn take_clone(&self, ip: IpAddr) -> Result<(), Error> {
+        self.take(ip, Budget::Clone)
+            .map_err(|retry_after| Error::RateLimited {
+                retry_after: retry_after.as_secs().max(1),
+            })
+    }
+
+    fn take_at(&self, ip: IpAddr, budget: Budget, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limits.limit(budget) else {
+            return Ok(());
+        };
+        if self.buckets.len() > PRUNE_THRESHOLD && self.prune_due(now) {
+            self.prune(now);
+        }
+
+        let mut bucket = self
+            .buckets
+            .entry((bucket_ip(ip), budget))
+            .or_insert(Bucket {
+                tokens: limit.burst,
+                updated: now,
+            });
+        bucket.refill(limit, now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if limit.rate > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    /// Whether `PRUNE_INTERVAL` passed since the last prune, only one caller gets `true`.
+    fn prune_due(&self, now: Instant) -> bool {
+        let Ok(mut last_prune) = self.last_prune.try_lock() else {
+            return false;
+        };
+        if now.saturating_duration_since(*last_prune) < PRUNE_INTERVAL {
+            return false;
+        }
+        *last_prune = now;
+        true
+    }
+
+    /// Drops buckets that are full again, they behave the same as new ones.
+    fn prune(&self, now: Instant) {
+        let limits = self.limits.clone();
+        self.buckets.retain(|(_, budget), bucket| {
+            limits.limit(*budget).is_some_and(|limit| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst
+            })
+        });
+    }
+}
+
+/// Address the bucket of `ip` is kept under: IPv4 as is, IPv6 as its /64 prefix.
+fn bucket_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !u128::from(u64::MAX))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BucketLimit, Budget, IpHeader, RateLimiter, RateLimits, PRUNE_INTERVAL, PRUNE_THRESHOLD,
+    };
+    use hyper::HeaderMap;
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn budgets_are_separate_and_refill() {
+        let limiter = RateLimiter::new(RateLimits {
+            read: Some(BucketLimit::per_minute(60, 2)),
+            clone: Some(BucketLimit::per_hour(60, 1)),
+            ip_header: None,
+        });
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(limiter.take_at(ip, Budget::Clone, now).is_ok());
+        let retry_after = limiter.take_at(ip, Budget::Clone, now).unwrap_err();
+        assert!((59..=60).contains(&retry_after.as_secs()));
+        assert!(limiter.take_at(other, Budget::Clone, now).is_ok());
+
+        assert!(limiter.take_at(ip, Budget::Read, now).is_ok());
+        assert!(limiter.take_at(ip, Budget::Read, now).is_ok());
+        assert!(limiter.take_at(ip, Budget::Read, now).is_err());
+        let later = now + Duration::from_secs(1);
+        assert!(limiter.take_at(ip, Budget::Read, later).is_ok());
+        assert!(limiter.take_at(ip, Budget::Read, later).is_err());
+
+        let ipv6: IpAddr = "2001:db8:1:2::1".parse().unwrap();
+        let same_prefix: IpAddr = "2001:db8:1:2:ffff::2".parse().unwrap();
+        let other_prefix: IpAddr = "2001:db8:1:3::1".parse().unwrap();
+        assert!(limiter.take_at(ipv6, Budget::Clone, now).is_ok());
+        assert!(limiter.take_at(same_prefix, Budget::Clone, now).is_err());
+        assert!(limiter.take_at(other_prefix, Budget::Clone, now).is_ok());
+
+        limiter.prune(now + Duration::from_secs(3600));
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn many_buckets_are_pruned_once_per_interval() {
+        let limiter = RateLimiter::new(RateLimits {
+            read: Some(BucketLimit::per_minute(60, 2)),
+            clone: None,
+            ip_header: None,
+        });
+        let fill = |start: u32, now| {
+            for i in start..start + PRUNE_THRESHOLD as u32 + 1 {
+                let ip = IpAddr::V4(Ipv4Addr::from(i));
+                assert!(limiter.take_at(ip, Budget::Read, now).is_ok());
+            }
+        };
+        let now = Instant::now() + PRUNE_INTERVAL;
+        fill(0, now);
+        assert_eq!(limiter.buckets.len(), PRUNE_THRESHOLD + 1);
+
+        // All buckets are full again, but only the first request walks them
+        let later = now + Duration::from_secs(60);
+        fill(1 << 24, later);
+        assert_eq!(limiter.buckets.len(), PRUNE_THRESHOLD + 1);
+
+        let next_interval = later + PRUNE_INTERVAL;
+        let ip = IpAddr::V4(Ipv4Addr::from(2 << 24));
+        assert!(limiter.take_at(ip, Budget::Read, next_interval).is_ok());
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn client_ip_comes_from_trusted_header_only() {
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("CF-Connecting-IP", "198.51.100.7".parse().unwrap());
+        headers.insert(
+            "X-Forwarded-For",
+            "203.0.113.9, 198.51.100.7, 10.0.0.2".parse().unwrap(),
+        );
+        let limits = |ip_header| RateLimits {
+            read: None,
+            clone: None,
+            ip_header,
+        };
+
+        assert_eq!(limits(None).client_ip(&headers, Some(peer)), Some(peer));
+        assert_eq!(
+            limits(Some(IpHeader::Single("CF-Connecting-IP".to_string())))
+                .client_ip(&headers, Some(peer)),
+            "198.51.100.7".parse().ok()
+        );
+        assert_eq!(
+            limits(Some(IpHeader::ForwardedFor { proxies: 2 })).client_ip(&headers, Some(peer)),
+            "198.51.100.7".parse().ok()
+        );
+        assert_eq!(
+            limits(Some(IpHeader::ForwardedFor { proxies: 4 })).client_ip(&headers, Some(peer)),
+            Some(peer)
+        );
+    }
+}
@@ -1,29 +1,64 @@
 use super::{
-    cloner::Cloner,
+    api_key::ApiKeys,
+    callback::{CallbackPayload, Notifier},
+    cloner::{CloneLimits, Cloner},
+    credentials::{Credential, Credentials},
+    forge::Forge,
     git::Git,
-    info::{to_unique_name, to_url, Branches, Status, Task},
+    info::{
+        to_scoped_name, to_unique_name, to_url, Branches, CloneOptions, OwnerReport, Requester,
+        Status, Task,
+    },
+    preflight::{format_bytes, SizeClass, SizePolicy},
+    rate_limit::RateLimiter,
     Error, Id, QuerySnafu,
 };
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
 use chrono::{DateTime, Utc};
-use dashmap::DashMap;
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures_util::{stream, StreamExt};
 use rand::{thread_rng, Rng};
 use scopeguard::defer;
 use snafu::ResultExt;
-use std::{path::Path, process::Stdio, str::from_utf8, sync::Arc};
-use tokio::sync::Mutex;
+use std::{collections::HashMap, path::Path, process::Stdio, str::from_utf8, sync::Arc};
+use tokio::{
+    sync::{Mutex, OwnedSemaphorePermit, Semaphore},
+    time::{timeout_at, Instant},
+};
 use tokio_postgres::{IsolationLevel::Serializable, NoTls, Row};
 use tracing::{error, info, warn};
 
+const BATCH_CONCURRENCY: usize = 8;
+
+/// Name of a forge repository as used in clone urls.
+fn repository_file_name(host: &str, name: &str) -> String {
+    if host != "git.sr.ht" && !name.ends_with(".git") {
+        format!("{name}.git")
+    } else {
+        name.to_string()
+    }
+}
+
+/// User agent of tasks queued by the service itself; they are not counted as visits.
+pub const SERVICE_USER_AGENT: &str = "cloc.info";
+
 type VecTasks = Arc<Mutex<Vec<(Option<Row>, Task)>>>;
 #[derive(Clone)]
 pub struct RepositoryProvider {
     pub connection_pool: Pool<PostgresConnectionManager<NoTls>>,
     pub git_provider: Git,
+    pub forge: Forge,
     pub cloner: Cloner,
+    notifier: Notifier,
+    credentials: Credentials,
+    api_keys: ApiKeys,
+    rate_limiter: RateLimiter,
+    size_policy: SizePolicy,
+    large_permits: Arc<Semaphore>,
     tasks: VecTasks,
     statuses: Arc<DashMap<String, Status>>,
+    callbacks: Arc<DashMap<String, Vec<String>>>,
     cancel: Arc<tokio_util::sync::CancellationToken>,
 }
 
@@ -31,18 +66,34 @@ impl RepositoryProvider {
     pub fn new(
         connection_pool: Pool<PostgresConnectionManager<NoTls>>,
         git_provider: Git,
+        forge: Forge,
+        notifier: Notifier,
+        credentials: Credentials,
+        rate_limiter: RateLimiter,
         cancel: Arc<tokio_util::sync::CancellationToken>,
     ) -> Self {
         let statuses = Arc::new(DashMap::with_capacity_and_shard_amount(512, 32));
+        let callbacks = Arc::new(DashMap::new());
         let tasks = Arc::new(Mutex::new(Vec::with_capacity(1024)));
-        let cloner = Cloner::new(statuses.clone());
+        let cloner = Cloner::new(statuses.clone(), CloneLimits::from_env(), cancel.clone());
+        let api_keys = ApiKeys::new(connection_pool.clone());
+        let size_policy = SizePolicy::from_env();
+        let large_permits = Arc::new(Semaphore::new(size_policy.large_concurrency));
 
         Self {
             connection_pool,
             git_provider,
+            forge,
             cloner,
+            notifier,
+            credentials,
+            api_keys,
+            rate_limiter,
+            size_policy,
+            large_permits,
             tasks,
             statuses,
+            callbacks,
             cancel,
         }
     }
@@ -74,8 +125,10 @@ impl RepositoryProvider {
                 .await
             {
                 tracing::error!("Error at processing {unique_name}: {}", e);
-                s.statuses.insert(unique_name, Status::Error(e.to_string()));
+                s.statuses
+                    .insert(unique_name.clone(), Status::Error(e.failure()));
             }
+            s.notify_callbacks(&unique_name);
         };
         tokio::spawn(future);
     }
@@ -117,15 +170,46 @@ impl RepositoryProvider {
                 warn!("Can't remove dir {cleanup_path}: {error}");
             }
         }
-        self.cloner
-            .clone_repository(task, unique_name, &tmp_path)
-            .await?;
+        // The clone may use at most what is left of the key's byte quota
+        let cloner = match task.usage.and_then(|charge| charge.max_bytes) {
+            Some(max_bytes) => self.cloner.with_max_bytes(max_bytes),
+            None => self.cloner.clone(),
+        };
+        let started = Instant::now();
+        let mut deadline = started + cloner.limits().timeout;
+        let size = self
+            .preflight(task, unique_name, deadline)
+            .await
+            .map_err(|error| error.timed_out_since(started))?;
+        let _permit = match size {
+            SizeClass::Large(size) => {
+                let waiting = Instant::now();
+                let permit = self.wait_for_large_slot(unique_name, size).await?;
+                // Waiting for other clones doesn't count against this one
+                deadline += waiting.elapsed();
+                Some(permit)
+            }
+            SizeClass::Regular(_) | SizeClass::Unknown => None,
+        };
+        cloner
+            .clone_repository(task, unique_name, &tmp_path, size.size(), deadline)
+            .await
+            .map_err(|error| error.timed_out_since(started))?;
         let scc_output = count_line_of_code(&tmp_path, "").await?;
         self.statuses
             .insert(unique_name.to_string(), Status::Done(scc_output.clone()));
 
         let last_commit_local = last_commit_local(&tmp_path).await?;
         let repository_size = dir_size(&tmp_path).await? as i64;
+        if let Some(charge) = task.usage {
+            if let Err(error) = self
+                .api_keys
+                .record_bytes(charge.usage_id, repository_size as u64)
+                .await
+            {
+                error!("Can't record cloned size of {unique_name}: {error}");
+            }
+        }
 
         let branch_id = if let Some(row) = row {
             self.update_database(
@@ -154,6 +238,62 @@ impl RepositoryProvider {
         Ok(())
     }
 
+    /// Asks the forge how large the repository is, rejects it if it is above the limit.
+    /// A failed request only skips the check, the clone itself is limited anyway. The time
+    /// taken counts against the clone `deadline`.
+    async fn preflight(
+        &self,
+        task: &Task,
+        unique_name: &str,
+        deadline: Instant,
+    ) -> Result<SizeClass, Error> {
+        let size = self
+            .forge
+            .repository_size(&task.host, &task.owner, &task.repository_name);
+        let size = match timeout_at(deadline, size).await {
+            Ok(Ok(size)) => size,
+            Ok(Err(error)) => {
+                warn!("Can't get size of {unique_name} from the forge: {error}");
+                None
+            }
+            Err(_) => {
+                return Err(Error::CloneTimeout {
+                    repository: unique_name.to_string(),
+                    seconds: self.cloner.limits().timeout.as_secs(),
+                })
+            }
+        };
+        self.size_policy.classify(unique_name, size)
+    }
+
+    /// Large repositories are cloned a few at a time so they don't hold up the others.
+    async fn wait_for_large_slot(
+        &self,
+        unique_name: &str,
+        size: u64,
+    ) -> Result<OwnedSemaphorePermit, Error> {
+        if let Ok(permit) = self.large_permits.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+        self.statuses.insert(
+            unique_name.to_string(),
+            Status::InProgress(format!(
+                "Expected size: {}\nWaiting for other large repositories to finish\n",
+                format_bytes(size)
+            )),
+        );
+        tokio::select! {
+            permit = self.large_permits.clone().acquire_owned() => {
+                permit.map_err(|error| Error::CloneCancelled {
+                    repository: format!("{unique_name}: {error}"),
+                })
+            }
+            _ = self.cancel.cancelled() => Err(Error::CloneCancelled {
+                repository: unique_name.to_string(),
+            }),
+        }
+    }
+
     // Идёт запрос
     // Если коммит актуальный, возвращаем его, выходим из функции.
     // Если коммит не актуальный, возвращаем предыдущую информацию и ставим задачу на скачивание репозитория
@@ -164,17 +304,87 @@ impl RepositoryProvider {
         repository_name: String,
         branch: Option<String>,
         user_agent: String,
+    ) -> Result<(String, Status), Error> {
+        let requester = Requester::anonymous(user_agent);
+        self.request_info_for(
+            host,
+            owner,
+            repository_name,
+            branch,
+            CloneOptions::default(),
+            requester,
+        )
+        .await
+    }
+
+    /// `request_info` on behalf of `requester`. The configured host credential is used
+    /// if the request has none and the requester may use it, results of other credentials
+    /// are never returned.
+    /// New analyses count against the quota of the requester's API key,
+    /// or the rate limit of its address for anonymous clients.
+    pub async fn request_info_for(
+        &self,
+        host: String,
+        owner: String,
+        repository_name: String,
+        branch: Option<String>,
+        options: CloneOptions,
+        requester: Requester,
     ) -> Result<(String, Status), Error> {
         info!(
             "get_info scc_output {} {} {:?}",
             owner, repository_name, branch
         );
 
+        let credential = self.credential_for(&host, &requester);
         let url = to_url(&host, &owner, &repository_name);
-        let default_branch = self.git_provider.default_branch(&url).await?;
-        let branch = branch.unwrap_or(default_branch.clone());
-        let query = "select * from branches where name=$4 and repository_id=(select id from repositories where hostname=$1 and owner=$2 and repository_name=$3);";
+        // Listing branches with the credential is the access check for stored results
+        let default_branch = self
+            .git_provider
+            .default_branch(&url, credential.as_ref())
+            .await?;
+        let task = Task {
+            host,
+            owner,
+            repository_name,
+            branch: branch.unwrap_or(default_branch.clone()),
+            default_branch,
+            user_agent: requester.user_agent.clone(),
+            credential,
+            usage: None,
+            options,
+        };
+        self.request_task(task, &requester).await
+    }
+
+    /// Result of a branch without queueing anything: the status of a requested analysis,
+    /// otherwise the stored result. `None` if it was never analysed.
+    pub async fn known_status_for(
+        &self,
+        host: &str,
+        owner: &str,
+        repository_name: &str,
+        branch: Option<String>,
+        requester: &Requester,
+    ) -> Result<Option<Status>, Error> {
+        // Listing branches with the credential is the access check for stored results
+        let default_branch = self
+            .default_branch_remote(host, owner, repository_name, requester)
+            .await?;
+        let branch = branch.unwrap_or(default_branch);
+        let unique_name = self.unique_name_for(
+            host,
+            owner,
+            repository_name,
+            &branch,
+            &CloneOptions::default(),
+            requester,
+        );
+        if let Some(status) = self.current_status(&unique_name) {
+            return Ok(Some(status));
+        }
 
+        let query = "select scc_output from branches where name=$4 and clone_options='' and repository_id=(select id from repositories where hostname=$1 and owner=$2 and repository_name=$3);";
         let connection =
             self.connection_pool
                 .get()
@@ -186,16 +396,41 @@ impl RepositoryProvider {
             .query_opt(query, &[&host, &owner, &repository_name, &branch])
             .await
             .context(QuerySnafu { query })?;
+        Ok(row.map(|row| Status::Done(row.get("scc_output"))))
+    }
 
-        let unique_name = to_unique_name(&host, &owner, &repository_name, &branch);
-        let task = Task {
-            host,
-            owner,
-            repository_name,
-            branch,
-            default_branch,
-            user_agent,
-        };
+    /// Returns the stored result of `task` and queues it unless the result is current.
+    /// The caller has checked that `requester` may read the repository.
+    async fn request_task(
+        &self,
+        mut task: Task,
+        requester: &Requester,
+    ) -> Result<(String, Status), Error> {
+        let query = "select * from branches where name=$4 and clone_options=$5 and repository_id=(select id from repositories where hostname=$1 and owner=$2 and repository_name=$3);";
+        let clone_options = task.options.to_string();
+
+        let connection =
+            self.connection_pool
+                .get()
+                .await
+                .map_err(|error| Error::ConnectionPool {
+                    error: error.to_string(),
+                })?;
+        let row = connection
+            .query_opt(
+                query,
+                &[
+                    &task.host,
+                    &task.owner,
+                    &task.repository_name,
+                    &task.branch,
+                    &clone_options,
+                ],
+            )
+            .await
+            .context(QuerySnafu { query })?;
+
+        let unique_name = task.to_unique_name();
         // Если скачивания не было, статус Ready
         // Если скачивание идёт статус InProgress
         let result_status = if let Some(row) = &row {
@@ -233,10 +468,25 @@ impl RepositoryProvider {
             Status::Ready
         };
 
-        let current_status = self.current_status(&unique_name);
-        let need_task = should_queue_task(current_status.as_ref(), &result_status);
-
-        if need_task {
+        if let Some(previous) = self.reserve_task(&unique_name, &result_status) {
+            // Charged only once the task is known to be new, a duplicate request is free
+            let charged = if let Some(api_key) = &requester.api_key {
+                self.api_keys
+                    .start_analysis(api_key)
+                    .await
+                    .map(|charge| task.usage = Some(charge))
+            } else if let Some(client_ip) = requester.client_ip {
+                self.rate_limiter.take_clone(client_ip)
+            } else {
+                Ok(())
+            };
+            if let Err(error) = charged {
+                match previous {
+                    Some(status) => self.statuses.insert(unique_name.clone(), status),
+                    None => self.statuses.remove(&unique_name).map(|(_, status)| status),
+                };
+                return Err(error);
+            }
             info!("add_task {}", unique_name);
             match self.tasks.try_lock() {
                 Ok(mut tasks) => {
@@ -244,7 +494,6 @@ impl RepositoryProvider {
                         .iter()
                         .any(|(_row, task)| unique_name == task.to_unique_name())
                     {
-                        self.statuses.insert(unique_name.clone(), Status::Ready);
                         tasks.push((row, task));
                     }
                 }
@@ -255,6 +504,165 @@ impl RepositoryProvider {
         Ok((unique_name, result_status))
     }
 
+    /// Report over the public repositories of `owner` with their stored results. Nothing
+    /// is cloned, see [`RepositoryProvider::queue_owner`].
+    pub async fn owner_report(&self, host: &str, owner: &str) -> Result<OwnerReport, Error> {
+        let repositories = self.forge.owner_repositories(host, owner).await?;
+        let query = "select repositories.repository_name, branches.name, branches.scc_output from repositories join branches on branches.repository_id = repositories.id where repositories.hostname = $1 and repositories.owner = $2 and not repositories.private and branches.clone_options = ''";
+        let connection =
+            self.connection_pool
+                .get()
+                .await
+                .map_err(|error| Error::ConnectionPool {
+                    error: error.to_string(),
+                })?;
+        let stored: HashMap<(String, String), Vec<u8>> = connection
+            .query(query, &[&host, &owner])
+            .await
+            .context(QuerySnafu { query })?
+            .into_iter()
+            .map(|row| ((row.get(0), row.get(1)), row.get(2)))
+            .collect();
+
+        let results = repositories
+            .into_iter()
+            .map(|repository| {
+                let repository_name = repository_file_name(host, &repository.name);
+                let status = repository.default_branch.as_ref().and_then(|branch| {
+                    let unique_name = to_unique_name(host, owner, &repository_name, branch);
+                    self.current_status(&unique_name).or_else(|| {
+                        stored
+                            .get(&(repository_name.clone(), branch.clone()))
+                            .map(|data| Status::Done(data.clone()))
+                    })
+                });
+                (repository, status)
+            })
+            .collect();
+        Ok(OwnerReport::new(host, owner, results))
+    }
+
+    /// Queues the default branch of every public repository of `owner`, charging each new
+    /// analysis to `requester`. The forge listing already has the default branches.
+    pub async fn queue_owner(
+        &self,
+        host: &str,
+        owner: &str,
+        requester: &Requester,
+    ) -> Result<OwnerReport, Error> {
+        let repositories = self.forge.owner_repositories(host, owner).await?;
+        let credential = self.credential_for(host, requester);
+        let branches: Vec<_> = repositories
+            .iter()
+            .map(|repository| {
+                (
+                    repository_file_name(host, &repository.name),
+                    repository.default_branch.clone(),
+                )
+            })
+            .collect();
+        let statuses: Vec<_> = stream::iter(branches)
+            .map(|(repository_name, default_branch)| {
+                let credential = credential.clone();
+                async move {
+                    let Some(default_branch) = default_branch else {
+                        // Empty repositories have no default branch and nothing to count
+                        return None;
+                    };
+                    let task = Task {
+                        host: host.to_string(),
+                        owner: owner.to_string(),
+                        repository_name,
+                        branch: default_branch.clone(),
+                        default_branch,
+                        user_agent: requester.user_agent.clone(),
+                        credential,
+                        usage: None,
+                        options: CloneOptions::default(),
+                    };
+                    Some(match self.request_task(task, requester).await {
+                        Ok((_unique_name, status)) => status,
+                        Err(error) => Status::Error(error.failure()),
+                    })
+                }
+            })
+            .buffered(BATCH_CONCURRENCY)
+            .collect()
+            .await;
+
+        Ok(OwnerReport::new(
+            host,
+            owner,
+            repositories.into_iter().zip(statuses).collect(),
+        ))
+    }
+
+    /// Remembers `url` to be called once `unique_name` is done or failed.
+    pub fn register_callback(&self, unique_name: &str, url: String) {
+        self.callbacks
+            .entry(unique_name.to_string())
+            .or_default()
+            .push(url);
+        // The task may have finished before the callback was registered
+        self.notify_callbacks(unique_name);
+    }
+
+    fn notify_callbacks(&self, unique_name: &str) {
+        let Some(payload) = self
+            .current_status(unique_name)
+            .and_then(|status| CallbackPayload::new(unique_name, &status))
+        else {
+            return;
+        };
+
+        if let Some((_, urls)) = self.callbacks.remove(unique_name) {
+            for url in urls {
+                self.notifier.spawn_deliver(url, payload.clone());
+            }
+        }
+    }
+
+    /// The request credential, otherwise the one configured for `host` if the requester may
+    /// use it. Git only sends it to `host`.
+    pub fn credential_for(&self, host: &str, requester: &Requester) -> Option<Credential> {
+        requester
+            .credential
+            .clone()
+            .or_else(|| {
+                self.credentials
+                    .for_host(host)
+                    .filter(|_| requester.may_use_shared_credential())
+            })
+            .map(|credential| credential.with_host(host))
+    }
+
+    /// Marks `unique_name` as queued unless it already is, returns the status it replaced.
+    fn reserve_task(&self, unique_name: &str, result_status: &Status) -> Option<Option<Status>> {
+        match self.statuses.entry(unique_name.to_string()) {
+            Entry::Occupied(mut entry) => should_queue_task(Some(entry.get()), result_status)
+                .then(|| Some(entry.insert(Status::Ready))),
+            Entry::Vacant(entry) => should_queue_task(None, result_status).then(|| {
+                entry.insert(Status::Ready);
+                None
+            }),
+        }
+    }
+
+    /// Name of the status of the analysis `requester` would start, see [`Task::to_unique_name`].
+    pub fn unique_name_for(
+        &self,
+        host: &str,
+        owner: &str,
+        repository_name: &str,
+        branch: &str,
+        options: &CloneOptions,
+        requester: &Requester,
+    ) -> String {
+        let unique_name =
+            options.to_unique_name(&to_unique_name(host, owner, repository_name, branch));
+        to_scoped_name(self.credential_for(host, requester).as_ref(), unique_name)
+    }
+
     pub fn current_status(&self, unique_name: &str) -> Option<Status> {
         self.statuses
             .get(unique_name)
@@ -266,9 +674,14 @@ impl RepositoryProvider {
         host: &str,
         owner: &str,
         repository_name: &str,
+        requester: &Requester,
     ) -> Result<String, Error> {
         let url = to_url(host, owner, repository_name);
-        let default_branch = self.git_provider.default_branch(&url).await?;
+        let credential = self.credential_for(host, requester);
+        let default_branch = self
+            .git_provider
+            .default_branch(&url, credential.as_ref())
+            .await?;
         Ok(default_branch)
     }
 
@@ -278,19 +691,51 @@ impl RepositoryProvider {
         owner: &str,
         repository_name: &str,
         branch: &str,
+        requester: &Requester,
     ) -> Result<String, Error> {
         let url = to_url(host, owner, repository_name);
         let branch = branch.trim_start_matches('/');
-        let last_commit = self.git_provider.last_commit(&url, branch).await?;
+        let credential = self.credential_for(host, requester);
+        let last_commit = self
+            .git_provider
+            .last_commit(&url, branch, credential.as_ref())
+            .await?;
         Ok(last_commit)
     }
 
-    pub async fn remote_branches(&self, url: &str) -> Result<Branches, Error> {
-        let branches = self.git_provider.all_branches(url).await?;
+    pub async fn remote_branches(
+        &self,
+        host: &str,
+        url: &str,
+        requester: &Requester,
+    ) -> Result<Branches, Error> {
+        let credential = self.credential_for(host, requester);
+        let branches = self
+            .git_provider
+            .all_branches(url, credential.as_ref())
+            .await?;
         Ok(branches)
     }
 
+    /// Whether anonymous clients can't read the repository, which keeps it out of the public
+    /// lists. Only probed when the analysis used a credential, a failed probe counts as private.
+    async fn is_private(&self, task: &Task) -> bool {
+        if task.credential.is_none() {
+            return false;
+        }
+        match self.git_provider.default_branch(&task.to_url(), None).await {
+            Ok(_) => false,
+            Err(error) => {
+                info!("{} is private: {error}", task.to_path());
+                true
+            }
+        }
+    }
+
     async fn update_statistic(&self, branch_id: Id, user_agent: &str) {
+        if user_agent == SERVICE_USER_AGENT {
+            return;
+        }
         let connection = match self.connection_pool.get().await {
             Ok(connection) => connection,
             Err(error) => {
@@ -318,21 +763,27 @@ impl RepositoryProvider {
         &self,
         connection: &mut bb8::PooledConnection<'_, PostgresConnectionManager<NoTls>>,
         row: &Row,
-        Task { branch, .. }: &Task,
+        task: &Task,
         scc_output: &[u8],
         last_commit_local: &str,
         repository_size: i64,
     ) -> Result<Id, Error> {
+        let Task {
+            branch, options, ..
+        } = task;
+        let private = self.is_private(task).await;
         let repository_id: Id = row.get("repository_id");
         tracing::debug!(
-                "INSERT INTO branches VALUES(DEFAULT, {}, '{}', '{}', 'scc', {}) ON CONFLICT (repository_id, name) DO UPDATE SET repository_id = EXCLUDED.repository_id, name = EXCLUDED.name, last_commit_sha = EXCLUDED.last_commit_sha, scc_output = EXCLUDED.scc_output, size = EXCLUDED.size RETURNING id;",
+                "INSERT INTO branches VALUES(DEFAULT, {}, '{}', '{}', 'scc', {}, '{}') ON CONFLICT (repository_id, name, clone_options) DO UPDATE SET ... RETURNING id;",
                 repository_id,
                 branch,
                 last_commit_local,
-                repository_size
+                repository_size,
+                options
             );
 
-        let upsert_branch = "INSERT INTO branches VALUES(DEFAULT, $1, $2, $3, $4, $5) ON CONFLICT (repository_id, name) DO UPDATE SET repository_id = EXCLUDED.repository_id, name = EXCLUDED.name, last_commit_sha = EXCLUDED.last_commit_sha, scc_output = EXCLUDED.scc_output, size = EXCLUDED.size RETURNING id";
+        let upsert_branch = "INSERT INTO branches (repository_id, name, last_commit_sha, scc_output, size, clone_options, blob_limit) VALUES($1, $2, $3, $4, $5, $6, $7) ON CONFLICT (repository_id, name, clone_options) DO UPDATE SET repository_id = EXCLUDED.repository_id, name = EXCLUDED.name, last_commit_sha = EXCLUDED.last_commit_sha, scc_output = EXCLUDED.scc_output, size = EXCLUDED.size, blob_limit = EXCLUDED.blob_limit RETURNING id";
+        let blob_limit = self.blob_limit();
         let transaction = connection
             .build_transaction()
             .isolation_level(Serializable)
@@ -350,12 +801,21 @@ impl RepositoryProvider {
                     &last_commit_local,
                     &scc_output,
                     &repository_size,
+                    &options.to_string(),
+                    &blob_limit,
                 ],
             )
             .await
             .with_context(|_e| QuerySnafu {
                 query: upsert_branch.to_string(),
             })?;
+        let update_private = "UPDATE repositories SET private = $2 WHERE id = $1";
+        transaction
+            .execute(update_private, &[&repository_id, &private])
+            .await
+            .with_context(|_e| QuerySnafu {
+                query: update_private.to_string(),
+            })?;
 
         transaction.commit().await.with_context(|_e| QuerySnafu {
             query: upsert_branch.to_string(),
@@ -368,19 +828,23 @@ impl RepositoryProvider {
     async fn insert_to_database(
         &self,
         connection: &mut bb8::PooledConnection<'_, PostgresConnectionManager<NoTls>>,
-        Task {
+        task: &Task,
+        scc_output: &[u8],
+        last_commit_local: &str,
+        repository_size: i64,
+    ) -> Result<Id, Error> {
+        let Task {
             host,
             owner,
             repository_name,
             branch,
             default_branch,
+            options,
             ..
-        }: &Task,
-        scc_output: &[u8],
-        last_commit_local: &str,
-        repository_size: i64,
-    ) -> Result<Id, Error> {
-        let upsert_repositories = "insert into repositories values (DEFAULT, $1, $2, $3, $4) ON CONFLICT (hostname, owner, repository_name) DO UPDATE SET hostname=EXCLUDED.hostname, owner=EXCLUDED.owner, repository_name=EXCLUDED.repository_name RETURNING ID;";
+        } = task;
+        // Private repositories stay out of the public lists
+        let private = self.is_private(task).await;
+        let upsert_repositories = "insert into repositories (hostname, owner, repository_name, default_branch, private) values ($1, $2, $3, $4, $5) ON CONFLICT (hostname, owner, repository_name) DO UPDATE SET hostname=EXCLUDED.hostname, owner=EXCLUDED.owner, repository_name=EXCLUDED.repository_name, private=EXCLUDED.private RETURNING ID;";
         let transaction = connection
             .build_transaction()
             .isolation_level(Serializable)
@@ -392,7 +856,7 @@ impl RepositoryProvider {
         let row = transaction
             .query_one(
                 upsert_repositories,
-                &[host, owner, repository_name, default_branch],
+                &[host, owner, repository_name, default_branch, &private],
             )
             .await
             .with_context(|_e| QuerySnafu {
@@ -406,14 +870,16 @@ impl RepositoryProvider {
         let repository_id: Id = row.get("id");
 
         tracing::debug!(
-            "INSERT INTO branches VALUES(DEFAULT, {}, '{}', '{}', 'scc', {}) RETURNING id;",
+            "INSERT INTO branches VALUES(DEFAULT, {}, '{}', '{}', 'scc', {}, '{}') RETURNING id;",
             repository_id,
             branch,
             &last_commit_local,
-            repository_size
+            repository_size,
+            options
         );
 
-        let insert_branch = "INSERT INTO branches VALUES(DEFAULT, $1, $2, $3, $4, $5) RETURNING id";
+        let insert_branch = "INSERT INTO branches (repository_id, name, last_commit_sha, scc_output, size, clone_options, blob_limit) VALUES($1, $2, $3, $4, $5, $6, $7) RETURNING id";
+        let blob_limit = self.blob_limit();
         let transaction = connection
             .build_transaction()
             .isolation_level(Serializable)
@@ -432,6 +898,8 @@ impl RepositoryProvider {
                     &last_commit_local,
                     &scc_output,
                     &repository_size,
+                    &options.to_string(),
+                    &blob_limit,
                 ],
             )
             .await
@@ -448,6 +916,11 @@ impl RepositoryProvider {
         Ok(branch_id)
     }
 
+    /// Files above the limit were not counted, stored with the result.
+    fn blob_limit(&self) -> Option<i64> {
+        self.cloner.limits().blob_limit.map(|limit| limit as i64)
+    }
+
     async fn is_commit_actual(
         &self,
         row: &Row,
@@ -456,14 +929,17 @@ impl RepositoryProvider {
             owner,
             repository_name,
             branch,
+            credential,
             ..
         }: &Task,
     ) -> Result<bool, Error> {
         let db_last_commit: String = row.get("last_commit_sha");
         let db_branch_name: String = row.get("name");
 
+        let url = to_url(host, owner, repository_name);
         let last_commit_remote = self
-            .last_commit_remote(host, owner, repository_name, branch)
+            .git_provider
+            .last_commit(&url, branch, credential.as_ref())
             .await?;
 
         let is_actual = last_commit_remote.eq(&db_last_commit) && db_branch_name.eq(branch);
@@ -509,10 +985,15 @@ pub(crate) async fn last_commit_local(path: &str) -> Result<CommitHash, Error> {
     }
 }
 
-pub async fn count_line_of_code(path: &str, _format: &str) -> Result<Vec<u8>, Error> {
+/// Runs scc on `path`; `format` is one of scc's `--format` values, its default if empty.
+pub async fn count_line_of_code(path: &str, format: &str) -> Result<Vec<u8>, Error> {
     let mut scc_command = tokio::process::Command::new("scc");
     tracing::debug!("Counting line of code in path: {path}");
-    scc_command.args(["--ci", path]);
+    scc_command.arg("--ci");
+    if !format.is_empty() {
+        scc_command.args(["--format", format]);
+    }
+    scc_command.arg(path);
     let out = match scc_command.output().await {
         Ok(output) if !output.status.success() => {
             let error = String::from_utf8(output.stderr)
@@ -592,10 +1073,59 @@ fn generate_path() -> String {
 }
 
 #[cfg(test)]
-mod tests {
-    use super::should_queue_task;
-    use crate::logic::info::Status;
+pub(crate) mod tests {
+    use super::{should_queue_task, RepositoryProvider};
+    use crate::logic::{
+        api_key::{ApiKey, Quota},
+        callback::Notifier,
+        credentials::Credentials,
+        forge::Forge,
+        git::Git,
+        info::{Branches, CloneOptions, Requester, Status},
+        rate_limit::{RateLimiter, RateLimits},
+        Error,
+    };
+    use bb8_postgres::PostgresConnectionManager;
     use chrono::Utc;
+    use retainer::Cache;
+    use std::{sync::Arc, time::Duration};
+    use tokio_postgres::NoTls;
+
+    /// Provider whose pool never connects, for tests that don't reach the database.
+    pub(crate) fn provider() -> RepositoryProvider {
+        provider_with_git(Git::new(Arc::new(Cache::new())))
+    }
+
+    /// `provider` that knows the branches of `url` without asking the remote.
+    pub(crate) async fn provider_with_branches(
+        url: &str,
+        branches: Branches,
+    ) -> RepositoryProvider {
+        let cache = Arc::new(Cache::new());
+        cache
+            .insert(url.to_string(), branches, Duration::from_secs(60))
+            .await;
+        provider_with_git(Git::new(cache))
+    }
+
+    fn provider_with_git(git: Git) -> RepositoryProvider {
+        let manager =
+            PostgresConnectionManager::new_from_stringlike("host=localhost", NoTls).unwrap();
+        RepositoryProvider::new(
+            bb8::Pool::builder().build_unchecked(manager),
+            git,
+            Forge::new(),
+            Notifier::from_env(),
+            Default::default(),
+            RateLimiter::new(RateLimits::from_env()),
+            Default::default(),
+        )
+    }
+
+    /// Status of `unique_name` as if it had been requested.
+    pub(crate) fn set_status(provider: &RepositoryProvider, unique_name: &str, status: Status) {
+        provider.statuses.insert(unique_name.to_string(), status);
+    }
 
     #[test]
     fn queues_when_previous_result_needs_refresh() {
@@ -608,7 +1138,13 @@ mod tests {
         assert!(should_queue_task(None, &previous));
         assert!(should_queue_task(Some(&Status::Done(vec![])), &previous));
         assert!(should_queue_task(
-            Some(&Status::Error("failed".to_string())),
+            Some(&Status::Error(
+                Error::CloneTimeout {
+                    repository: "repo".to_string(),
+                    seconds: 1,
+                }
+                .failure()
+            )),
             &previous
         ));
     }
@@ -622,4 +1158,37 @@ mod tests {
         ));
         assert!(!should_queue_task(Some(&Status::Cloned), &Status::Ready,));
     }
+
+    #[tokio::test]
+    async fn shared_results_are_not_served_to_anonymous_clients() {
+        let mut provider = provider();
+        provider.credentials = Credentials::parse("github.com=operator-token");
+        let name = |requester: &Requester| {
+            provider.unique_name_for(
+                "github.com",
+                "org",
+                "repo.git",
+                "main",
+                &CloneOptions::default(),
+                requester,
+            )
+        };
+        let with_key = Requester {
+            api_key: Some(ApiKey {
+                id: 1,
+                name: "ci".to_string(),
+                quota: Quota::default(),
+            }),
+            ..Default::default()
+        };
+
+        let shared = name(&with_key);
+        assert_eq!(shared, "private/shared/github.com/org/repo.git/main");
+        assert_eq!(name(&Requester::service()), shared);
+        set_status(&provider, &shared, Status::Done(b"Total 1".to_vec()));
+
+        let anonymous = name(&Requester::anonymous("curl".to_string()));
+        assert_eq!(anonymous, "github.com/org/repo.git/main");
+        assert!(provider.current_status(&anonymous).is_none());
+    }
 }
@@ -0,0 +1,234 @@
+use super::{
+    info::{to_url, Branches, CloneOptions, Requester},
+    repository::RepositoryProvider,
+    Error, QuerySnafu,
+};
+use chrono::{Timelike, Utc};
+use snafu::ResultExt;
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+const WATCHED_QUERY: &str = "select repositories.hostname, repositories.owner, repositories.repository_name, branches.name, branches.last_commit_sha from repositories join branches on repositories.id = branches.repository_id where repositories.watched and branches.clone_options = '';";
+
+/// Hours of the day (UTC) when refreshing is allowed, `start > end` wraps around midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffPeak {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl OffPeak {
+    fn parse(value: &str) -> Option<Self> {
+        let (start, end) = value.split_once('-')?;
+        let start = start.trim().parse().ok()?;
+        let end = end.trim().parse().ok()?;
+        (start < 24 && end <= 24).then_some(Self { start, end })
+    }
+
+    fn contains(&self, hour: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= hour && hour < self.end
+        } else {
+            hour >= self.start || hour < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WatchedBranch {
+    name: String,
+    last_commit_sha: String,
+}
+
+/// Periodically re-queues watched repositories whose branches moved since the last analysis.
+pub struct Scheduler {
+    provider: RepositoryProvider,
+    interval: Duration,
+    off_peak: OffPeak,
+    cancel: Arc<CancellationToken>,
+}
+
+impl Scheduler {
+    pub fn new(
+        provider: RepositoryProvider,
+        interval: Duration,
+        off_peak: OffPeak,
+        cancel: Arc<CancellationToken>,
+    ) -> Self {
+        Self {
+            provider,
+            interval,
+            off_peak,
+            cancel,
+        }
+    }
+
+    /// Reads `SCHEDULER_INTERVAL_SECS` (default 3600) and `SCHEDULER_OFF_PEAK_HOURS` (default `1-6`).
+    pub fn from_env(provider: RepositoryProvider, cancel: Arc<CancellationToken>) -> Self {
+        let interval = std::env::var("SCHEDULER_INTERVAL_SECS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(3600);
+        let off_peak = std::env::var("SCHEDULER_OFF_PEAK_HOURS")
+            .ok()
+            .and_then(|value| OffPeak::parse(&value))
+            .unwrap_or(OffPeak { start: 1, end: 6 });
+
+        Self::new(provider, Duration::from_secs(interval), off_peak, cancel)
+    }
+
+    pub async fn run(&self) {
+        info!(
+            "Scheduler started: interval {:?}, off-peak hours {:?} UTC",
+            self.interval, self.off_peak
+        );
+        loop {
+            tokio::select! {
+                _ = self.cancel.cancelled() => break,
+                _ = tokio::time::sleep(self.interval) => {}
+            }
+
+            if !self.off_peak.contains(Utc::now().hour()) {
+                continue;
+            }
+
+            match self.refresh_watched().await {
+                Ok(queued) => info!("Scheduler queued {queued} watched branches"),
+                Err(error) => error!("Scheduler error: {error}"),
+            }
+        }
+    }
+
+    async fn refresh_watched(&self) -> Result<usize, Error> {
+        let rows = {
+            let connection = self.provider.connection_pool.get().await.map_err(|error| {
+                Error::ConnectionPool {
+                    error: error.to_string(),
+                }
+            })?;
+            connection
+                .query(WATCHED_QUERY, &[])
+                .await
+                .context(QuerySnafu {
+                    query: WATCHED_QUERY,
+                })?
+        };
+
+        let mut repositories: BTreeMap<(String, String, String), Vec<WatchedBranch>> =
+            BTreeMap::new();
+        for row in rows {
+            repositories
+                .entry((
+                    row.get("hostname"),
+                    row.get("owner"),
+                    row.get("repository_name"),
+                ))
+                .or_default()
+                .push(WatchedBranch {
+                    name: row.get("name"),
+                    last_commit_sha: row.get("last_commit_sha"),
+                });
+        }
+
+        let mut queued = 0;
+        for ((host, owner, repository_name), watched) in repositories {
+            let url = to_url(&host, &owner, &repository_name);
+            self.provider.git_provider.invalidate(&url).await;
+            let branches = match self
+                .provider
+                .remote_branches(&host, &url, &Requester::service())
+                .await
+            {
+                Ok(branches) => branches,
+                Err(error) => {
+                    error!("Scheduler can't list branches of {url}: {error}");
+                    continue;
+                }
+            };
+
+            for branch in changed_branches(&watched, &branches) {
+                let result = self
+                    .provider
+                    .request_info_for(
+                        host.clone(),
+                        owner.clone(),
+                        repository_name.clone(),
+                        Some(branch),
+                        CloneOptions::default(),
+                        Requester::service(),
+                    )
+                    .await;
+                match result {
+                    Ok((unique_name, status)) => {
+                        info!("Scheduler refresh {unique_name}: {status}");
+                        queued += 1;
+                    }
+                    Err(error) => error!("Scheduler can't queue {url}: {error}"),
+                }
+            }
+        }
+
+        Ok(queued)
+    }
+}
+
+fn changed_branches(watched: &[WatchedBranch], remote: &Branches) -> Vec<String> {
+    watched
+        .iter()
+        .filter(|branch| {
+            remote
+                .branches
+                .iter()
+                .any(|remote| remote.name == branch.name && remote.commit != branch.last_commit_sha)
+        })
+        .map(|branch| branch.name.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{changed_branches, OffPeak, WatchedBranch};
+    use crate::logic::info::{BranchValue, Branches};
+
+    #[test]
+    fn off_peak_window_wraps_midnight() {
+        let night = OffPeak { start: 1, end: 6 };
+        let wrapped = OffPeak::parse("22-4").unwrap();
+
+        assert!(night.contains(2));
+        assert!(!night.contains(6));
+        assert!(wrapped.contains(23));
+        assert!(wrapped.contains(3));
+        assert!(!wrapped.contains(12));
+        assert_eq!(OffPeak::parse("25-4"), None);
+    }
+
+    #[test]
+    fn only_moved_branches_are_refreshed() {
+        let watched = |name: &str, commit: &str| WatchedBranch {
+            name: name.to_string(),
+            last_commit_sha: commit.to_string(),
+        };
+        let remote = |name: &str, commit: &str| BranchValue {
+            name: name.to_string(),
+            commit: commit.to_string(),
+        };
+        let branches = Branches {
+            default_branch: "main".to_string(),
+            branches: vec![remote("main", "new"), remote("dev", "same")],
+            tags: Vec::new(),
+        };
+
+        let changed = changed_branches(
+            &[
+                watched("main", "old"),
+                watched("dev", "same"),
+                watched("gone", "old"),
+            ],
+            &branches,
+        );
+
+        assert_eq!(changed, ["main"]);
+    }
+}
@@ -0,0 +1,375 @@
+use super::{
+    credentials::Credential,
+    info::{BranchValue, Branches, TagValue},
+    Error,
+};
+use reqwest::StatusCode;
+
+const ADVERTISEMENT_TYPE: &str = "application/x-git-upload-pack-advertisement";
+/// Larger advertisements are refused, the host is chosen by the client.
+const MAX_ADVERTISEMENT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Refs of a repository as advertised by `git-upload-pack`, over smart HTTP or `ls-remote`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Advertisement {
+    /// `(name, commit)` pairs in the advertised order, `HEAD` included.
+    pub refs: Vec<(String, String)>,
+    /// Target of `HEAD` from the `symref=HEAD:` capability.
+    pub head_target: Option<String>,
+}
+
+impl Advertisement {
+    /// Branches with the default one taken from the `symref` capability. Without it, as for
+    /// bundles, the branch at the `HEAD` commit is used, `main` or `master` if several are.
+    pub fn branches(&self) -> Branches {
+        let branches: Vec<BranchValue> = self
+            .refs
+            .iter()
+            .filter_map(|(name, commit)| {
+                Some(BranchValue {
+                    name: name.strip_prefix("refs/heads/")?.to_string(),
+                    commit: commit.clone(),
+                })
+            })
+            .collect();
+
+        let default_branch = match &self.head_target {
+            Some(target) => target.trim_start_matches("refs/heads/").to_string(),
+            None => {
+                let head = self.refs.iter().find(|(name, _)| name == "HEAD");
+                let at_head: Vec<&str> = branches
+                    .iter()
+                    .filter(|branch| head.is_some_and(|(_, commit)| &branch.commit == commit))
+                    .map(|branch| branch.name.as_str())
+                    .collect();
+                ["main", "master"]
+                    .into_iter()
+                    .find(|name| at_head.contains(name))
+                    .or(at_head.first().copied())
+                    .unwrap_or_default()
+                    .to_string()
+            }
+        };
+
+        Branches {
+            default_branch,
+            branches,
+            tags: self.tags(),
+        }
+    }
+
+    /// Tags peeled with the `^{}` entries that follow annotated ones.
+    fn tags(&self) -> Vec<TagValue> {
+        let mut tags: Vec<TagValue> = Vec::new();
+        for (name, object) in &self.refs {
+            let Some(name) = name.strip_prefix("refs/tags/") else {
+                continue;
+            };
+            match name.strip_suffix("^{}") {
+                Some(peeled) => {
+                    if let Some(tag) = tags.iter_mut().rev().find(|tag| tag.name == peeled) {
+                        tag.commit = object.clone();
+                    }
+                }
+                None => tags.push(TagValue {
+                    name: name.to_string(),
+                    object: object.clone(),
+                    commit: object.clone(),
+                }),
+            }
+        }
+        tags
+    }
+}
+
+/// Fetches `info/refs` of `url`, the same request `git ls-remote` starts with.
+pub async fn advertised_refs(
+    client: &reqwest::Client,
+    url: &str,
+    credential: Option<&Credential>,
+) -> Result<Advertisement, Error> {
+    let refs_url = format!(
+        "{}/info/refs?service=git-upload-pack",
+        url.trim_end_matches('/')
+    );
+    let mut request = client.get(&refs_url);
+    if let Some(credential) = credential {
+        let (username, token) = credential.basic_auth();
+        request = request.basic_auth(username, Some(token));
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|error| request_error(url, error))?;
+
+    let status = response.status();
+    match status {
+        StatusCode::NOT_FOUND => return Err(Error::RepositoryNotFound { url: url.into() }),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(Error::AuthenticationRequired { url: url.into() })
+        }
+        status if status.is_server_error() => {
+            return Err(Error::RemoteUnreachable {
+                url: url.into(),
+                message: status.to_string(),
+            })
+        }
+        status if !status.is_success() => {
+            return Err(Error::RemoteError {
+                url: url.into(),
+                message: status.to_string(),
+            })
+        }
+        _ => {}
+    }
+    let is_smart = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with(ADVERTISEMENT_TYPE));
+    if !is_smart {
+        return Err(Error::RemoteError {
+            url: url.into(),
+            message: "Not a git smart HTTP server".to_string(),
+        });
+    }
+
+    let body = read_limited(response, url, MAX_ADVERTISEMENT_BYTES).await?;
+    parse_advertisement(url, &body)
+}
+
+/// Body of `response`, read as it arrives so that no more than `limit` bytes are kept.
+async fn read_limited(
+    mut response: reqwest::Response,
+    url: &str,
+    limit: usize,
+) -> Result<Vec<u8>, Error> {
+    let too_large = || Error::RemoteError {
+        url: url.into(),
+        message: format!("Ref advertisement is larger than {limit} bytes"),
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|error| request_error(url, error))?
+    {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+fn request_error(url: &str, error: reqwest::Error) -> Error {
+    if error.is_timeout() {
+        Error::RemoteTimeout { url: url.into() }
+    } else if error.is_connect() {
+        Error::RemoteUnreachable {
+            url: url.into(),
+            message: error.to_string(),
+        }
+    } else {
+        Error::Http {
+            url: url.into(),
+            source: error,
+        }
+    }
+}
+
+/// Parses the pkt-lines of a protocol v0 advertisement.
+pub fn parse_advertisement(url: &str, body: &[u8]) -> Result<Advertisement, Error> {
+    let line_error = |desc: &str| Error::Line {
+        url: url.to_string(),
+        desc: desc.to_string(),
+    };
+
+    let mut advertisement = Advertisement::default();
+    let mut rest = body;
+    let mut first_ref = true;
+    while !rest.is_empty() {
+        let length = rest
+            .get(..4)
+            .and_then(|length| std::str::from_utf8(length).ok())
+            .and_then(|length| usize::from_str_radix(length, 16).ok())
+            .ok_or_else(|| line_error("Invalid pkt-line length"))?;
+        // Flush packets separate the service announcement from the refs
+        if length == 0 {
+            rest = &rest[4..];
+            continue;
+        }
+        let line = rest
+            .get(4..length)
+            .ok_or_else(|| line_error("Truncated pkt-line"))?;
+        rest = &rest[length..];
+
+        let line = std::str::from_utf8(line).map_err(|_| line_error("Ref is not UTF-8"))?;
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        if line.starts_with("# service=") {
+            continue;
+        }
+
+        let (reference, capabilities) = match line.split_once('\0') {
+            Some((reference, capabilities)) if first_ref => (reference, Some(capabilities)),
+            _ => (line, None),
+        };
+        first_ref = false;
+        if let Some(capabilities) = capabilities {
+            advertisement.head_target = capabilities
+                .split(' ')
+                .find_map(|capability| capability.strip_prefix("symref=HEAD:"))
+                .map(str::to_string);
+        }
+
+        let (commit, name) = reference
+            .split_once(' ')
+            .ok_or_else(|| line_error("Can't split ref into commit and name"))?;
+        // Empty repositories advertise only their capabilities
+        if name != "capabilities^{}" {
+            advertisement
+                .refs
+                .push((name.to_string(), commit.to_string()));
+        }
+    }
+
+    Ok(advertisement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{advertised_refs, parse_advertisement, read_limited, ADVERTISEMENT_TYPE};
+    use crate::logic::{credentials::Credential, forge::tests::spawn_api, info::TagValue, Error};
+    use axum::{http::HeaderMap, response::IntoResponse, routing::get, Router};
+    use hyper::{header::AUTHORIZATION, StatusCode};
+
+    const SYMREF: &str = "symref=HEAD:refs/heads/trunk";
+
+    fn pkt_line(line: &str) -> String {
+        format!("{:04x}{line}", line.len() + 4)
+    }
+
+    fn advertisement_body(capabilities: &str) -> String {
+        let commit = "1111111111111111111111111111111111111111";
+        let tag = "2222222222222222222222222222222222222222";
+        [
+            pkt_line("# service=git-upload-pack\n"),
+            "0000".to_string(),
+            pkt_line(&format!(
+                "{commit} HEAD\0multi_ack {capabilities} agent=git/2.39\n"
+            )),
+            pkt_line(&format!("{commit} refs/heads/main\n")),
+            pkt_line(&format!("{commit} refs/heads/trunk\n")),
+            pkt_line(&format!("{tag} refs/tags/v1\n")),
+            pkt_line(&format!("{commit} refs/tags/v1^{{}}\n")),
+            "0000".to_string(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn default_branch_comes_from_symref() {
+        let advertisement = advertisement_body(SYMREF);
+        let advertisement = parse_advertisement("url", advertisement.as_bytes()).unwrap();
+        let branches = advertisement.branches();
+
+        // `main` points at the same commit, only the capability tells them apart
+        assert_eq!(branches.default_branch, "trunk");
+        assert_eq!(branches.branches.len(), 2);
+        assert_eq!(advertisement.refs.len(), 5);
+        assert_eq!(
+            branches.tags,
+            vec![TagValue {
+                name: "v1".to_string(),
+                object: "2".repeat(40),
+                commit: "1".repeat(40),
+            }]
+        );
+
+        // Without the capability `main` is preferred among the branches at the HEAD commit
+        let branches = parse_advertisement("url", advertisement_body("ofs-delta").as_bytes())
+            .unwrap()
+            .branches();
+        assert_eq!(branches.default_branch, "main");
+
+        assert!(parse_advertisement("url", b"00zz").is_err());
+        assert!(parse_advertisement("url", b"0030short").is_err());
+    }
+
+    #[tokio::test]
+    async fn refs_are_fetched_from_smart_http_server() {
+        let router = Router::new()
+            .route(
+                "/org/repo.git/info/refs",
+                get(|| async {
+                    (
+                        [("content-type", ADVERTISEMENT_TYPE)],
+                        advertisement_body(SYMREF),
+                    )
+                }),
+            )
+            .route(
+                "/org/private.git/info/refs",
+                get(|headers: HeaderMap| async move {
+                    // "user:secret" in base64
+                    if headers
+                        .get(AUTHORIZATION)
+                        .is_some_and(|value| value == "Basic dXNlcjpzZWNyZXQ=")
+                    {
+                        (
+                            [("content-type", ADVERTISEMENT_TYPE)],
+                            advertisement_body(SYMREF),
+                        )
+                            .into_response()
+                    } else {
+                        StatusCode::UNAUTHORIZED.into_response()
+                    }
+                }),
+            );
+        let base_url = spawn_api(router).await;
+        let client = reqwest::Client::new();
+
+        let refs = advertised_refs(&client, &format!("{base_url}/org/repo.git"), None)
+            .await
+            .unwrap();
+        assert_eq!(refs.branches().default_branch, "trunk");
+
+        let private = format!("{base_url}/org/private.git");
+        assert!(matches!(
+            advertised_refs(&client, &private, None).await,
+            Err(Error::AuthenticationRequired { .. })
+        ));
+        let credential = Credential::new(Some("user".to_string()), "secret".to_string());
+        assert!(advertised_refs(&client, &private, Some(&credential))
+            .await
+            .is_ok());
+
+        let missing = format!("{base_url}/org/missing.git");
+        assert!(matches!(
+            advertised_refs(&client, &missing, None).await,
+            Err(Error::RepositoryNotFound { .. })
+        ));
+
+        let refs_url = format!("{base_url}/org/repo.git/info/refs");
+        let length = advertisement_body(SYMREF).len();
+        let response = client.get(&refs_url).send().await.unwrap();
+        assert_eq!(
+            read_limited(response, &refs_url, length)
+                .await
+                .unwrap()
+                .len(),
+            length
+        );
+        let response = client.get(&refs_url).send().await.unwrap();
+        assert!(matches!(
+            read_limited(response, &refs_url, length - 1).await,
+            Err(Error::RemoteError { .. })
+        ));
+    }
+}
@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use utoipa::ToSchema;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct LanguageStat {
+    pub language: String,
+    pub files: u64,
+    pub lines: u64,
+    pub blanks: u64,
+    pub comments: u64,
+    pub code: u64,
+    pub complexity: u64,
+}
+
+impl LanguageStat {
+    fn add(&mut self, other: &LanguageStat) {
+        self.files += other.files;
+        self.lines += other.lines;
+        self.blanks += other.blanks;
+        self.comments += other.comments;
+        self.code += other.code;
+        self.complexity += other.complexity;
+    }
+
+    fn set(&mut self, column: &str, value: u64) {
+        match column {
+            "Files" => self.files = value,
+            "Lines" => self.lines = value,
+            "Blanks" => self.blanks = value,
+            "Comments" => self.comments = value,
+            "Code" => self.code = value,
+            "Complexity" => self.complexity = value,
+            _ => {}
+        }
+    }
+}
+
+/// Per-language numbers extracted from the `scc --ci` table.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, ToSchema)]
+pub struct Summary {
+    pub languages: Vec<LanguageStat>,
+    pub total: LanguageStat,
+}
+
+impl Default for Summary {
+    fn default() -> Self {
+        Self {
+            languages: Vec::new(),
+            total: LanguageStat {
+                language: "Total".to_string(),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Summary {
+    pub fn parse(scc_output: &[u8]) -> Self {
+        let text = String::from_utf8_lossy(scc_output);
+        let mut columns: Vec<&str> = Vec::new();
+        let mut summary = Summary::default();
+
+        for line in text.lines() {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+
+            if tokens.first() == Some(&"Language") {
+                columns = tokens[1..].to_vec();
+                continue;
+            }
+
+            if columns.is_empty() || tokens.len() <= columns.len() {
+                continue;
+            }
+
+            let (name, values) = tokens.split_at(tokens.len() - columns.len());
+            let Ok(values) = values
+                .iter()
+                .map(|value| value.parse::<u64>())
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                continue;
+            };
+
+            let mut stat = LanguageStat {
+                language: name.join(" "),
+                ..Default::default()
+            };
+            for (column, value) in columns.iter().zip(values) {
+                stat.set(column, value);
+            }
+
+            if stat.language == "Total" {
+                summary.total = stat;
+            } else {
+                summary.languages.push(stat);
+            }
+        }
+
+        summary
+    }
+
+    pub fn merge(&mut self, other: &Summary) {
+        for stat in &other.languages {
+            match self
+                .languages
+                .iter_mut()
+                .find(|current| current.language == stat.language)
+            {
+                Some(current) => current.add(stat),
+                None => self.languages.push(stat.clone()),
+            }
+        }
+        self.total.add(&other.total);
+        self.languages.sort_by_key(|stat| Reverse(stat.code));
+    }
+
+    pub fn language(&self, name: &str) -> Option<&LanguageStat> {
+        self.languages
+            .iter()
+            .find(|stat| stat.language.eq_ignore_ascii_case(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    const SCC_OUTPUT: &str = "\
+-------------------------------------------------------------------------------
+Language                 Files     Lines   Blanks  Comments     Code Complexity
+-------------------------------------------------------------------------------
+Rust                        10      3120      310        40     2770        180
+Plain Text                   2        20        0         0       20          0
+-------------------------------------------------------------------------------
+Total                       12      3140      310        40     2790        180
+-------------------------------------------------------------------------------
+Estimated Cost to Develop (organic) $79,349
+Estimated Schedule Effort (organic) 5.38 months
+Estimated People Required (organic) 1.31
+-------------------------------------------------------------------------------
+Processed 105370 bytes, 0.105 megabytes (SI)
+-------------------------------------------------------------------------------
+";
+
+    #[test]
+    fn parses_languages_and_total() {
+        let summary = Summary::parse(SCC_OUTPUT.as_bytes());
+
+        assert_eq!(summary.languages.len(), 2);
+        assert_eq!(summary.languages[1].language, "Plain Text");
+        assert_eq!(summary.languages[0].code, 2770);
+        assert_eq!(summary.total.files, 12);
+        assert_eq!(summary.total.code, 2790);
+    }
+
+    #[test]
+    fn merge_sums_languages() {
+        let mut summary = Summary::parse(SCC_OUTPUT.as_bytes());
+        summary.merge(&Summary::parse(SCC_OUTPUT.as_bytes()));
+
+        assert_eq!(summary.language("rust").map(|stat| stat.code), Some(5540));
+        assert_eq!(summary.total.lines, 6280);
+    }
+}
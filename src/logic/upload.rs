@@ -0,0 +1,222 @@
+use super::{info::BranchValue, Error, QuerySnafu};
+use bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
+use chrono::{DateTime, Duration, Utc};
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use snafu::ResultExt;
+use tokio_postgres::NoTls;
+
+/// Length of the hex encoded upload id, 128 bits of the content hash.
+const ID_LENGTH: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredUpload {
+    pub id: String,
+    pub scc_output: Vec<u8>,
+    /// Analysed branch of an uploaded git bundle or bare repository.
+    pub branch: Option<BranchValue>,
+    pub created: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+}
+
+impl StoredUpload {
+    pub fn is_expired(&self) -> bool {
+        self.expires <= Utc::now()
+    }
+}
+
+/// Hash of the uploaded files, identical uploads get the same id.
+#[derive(Default)]
+pub struct ContentHash {
+    files: Sha256,
+    current: Option<Sha256>,
+}
+
+impl ContentHash {
+    /// Starts a new file, names and contents are hashed separately so boundaries can't shift.
+    pub fn file(&mut self, name: &str) {
+        self.finish_file();
+        self.files.update((name.len() as u64).to_le_bytes());
+        self.files.update(name.as_bytes());
+        self.current = Some(Sha256::new());
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.current.get_or_insert_with(Sha256::new).update(data);
+    }
+
+    pub fn id(mut self) -> String {
+        self.finish_file();
+        let mut id = hex::encode(self.files.finalize());
+        id.truncate(ID_LENGTH);
+        id
+    }
+
+    fn finish_file(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.files.update(current.finalize());
+        }
+    }
+}
+
+/// Random id of a private upload, as long as a content hash id so it can't be told apart.
+pub fn private_id() -> String {
+    hex::encode(thread_rng().gen::<[u8; ID_LENGTH / 2]>())
+}
+
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LENGTH && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+/// Upload results stored in the `uploads` table. Public results are stored under their
+/// content hash, private ones under a random id with the hash in `content_hash`.
+#[derive(Clone)]
+pub struct UploadStore {
+    connection_pool: Pool<PostgresConnectionManager<NoTls>>,
+    ttl: Duration,
+}
+
+impl UploadStore {
+    pub fn new(connection_pool: Pool<PostgresConnectionManager<NoTls>>, ttl: Duration) -> Self {
+        Self {
+            connection_pool,
+            ttl,
+        }
+    }
+
+    /// Keeps results for `UPLOAD_TTL_DAYS` (default 30) days.
+    pub fn from_env(connection_pool: Pool<PostgresConnectionManager<NoTls>>) -> Self {
+        let days = std::env::var("UPLOAD_TTL_DAYS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(30);
+        Self::new(connection_pool, Duration::days(days))
+    }
+
+    pub async fn get(&self, id: &str) -> Result<Option<StoredUpload>, Error> {
+        let connection = self.connection().await?;
+        let query =
+            "select id, scc_output, branch, commit, created, expires from uploads where id=$1";
+        let row = connection
+            .query_opt(query, &[&id])
+            .await
+            .context(QuerySnafu { query })?;
+
+        Ok(row.as_ref().map(stored_upload))
+    }
+
+    /// Unexpired private result of an identical upload.
+    pub async fn find_private(&self, content_hash: &str) -> Result<Option<StoredUpload>, Error> {
+        let connection = self.connection().await?;
+        let query = "select id, scc_output, branch, commit, created, expires from uploads \
+                     where content_hash=$1 and expires > now() limit 1";
+        let row = connection
+            .query_opt(query, &[&content_hash])
+            .await
+            .context(QuerySnafu { query })?;
+
+        Ok(row.as_ref().map(stored_upload))
+    }
+
+    /// Stores the result or extends the expiry of an identical one, removes expired results.
+    /// `content_hash` is set for private results, whose `id` is random.
+    pub async fn insert(
+        &self,
+        id: &str,
+        content_hash: Option<&str>,
+        scc_output: &[u8],
+        branch: Option<&BranchValue>,
+    ) -> Result<StoredUpload, Error> {
+        let connection = self.connection().await?;
+        let query = "delete from uploads where expires < now()";
+        connection
+            .execute(query, &[])
+            .await
+            .context(QuerySnafu { query })?;
+
+        let created = Utc::now();
+        let expires = created + self.ttl;
+        let name = branch.map(|branch| branch.name.as_str());
+        let commit = branch.map(|branch| branch.commit.as_str());
+        let query = "insert into uploads(id, content_hash, scc_output, branch, commit, created, \
+                     expires) values($1, $2, $3, $4, $5, $6, $7) \
+                     on conflict (id) do update set expires=excluded.expires \
+                     returning created";
+        let row = connection
+            .query_one(
+                query,
+                &[
+                    &id,
+                    &content_hash,
+                    &scc_output,
+                    &name,
+                    &commit,
+                    &created,
+                    &expires,
+                ],
+            )
+            .await
+            .context(QuerySnafu { query })?;
+
+        Ok(StoredUpload {
+            id: id.to_string(),
+            scc_output: scc_output.to_vec(),
+            branch: branch.cloned(),
+            created: row.get("created"),
+            expires,
+        })
+    }
+
+    async fn connection(
+        &self,
+    ) -> Result<bb8::PooledConnection<'_, PostgresConnectionManager<NoTls>>, Error> {
+        self.connection_pool
+            .get()
+            .await
+            .map_err(|error| Error::ConnectionPool {
+                error: error.to_string(),
+            })
+    }
+}
+
+fn stored_upload(row: &tokio_postgres::Row) -> StoredUpload {
+    let name: Option<String> = row.get("branch");
+    let commit: Option<String> = row.get("commit");
+    StoredUpload {
+        id: row.get("id"),
+        scc_output: row.get("scc_output"),
+        branch: name
+            .zip(commit)
+            .map(|(name, commit)| BranchValue { name, commit }),
+        created: row.get("created"),
+        expires: row.get("expires"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_id, private_id, ContentHash};
+
+    #[test]
+    fn identical_uploads_share_an_id() {
+        let hash = |files: &[(&str, &[u8])]| {
+            let mut hash = ContentHash::default();
+            for (name, data) in files {
+                hash.file(name);
+                hash.update(data);
+            }
+            hash.id()
+        };
+
+        let id = hash(&[("a.rs", b"fn main() {}")]);
+        assert!(is_valid_id(&id));
+        assert_eq!(id, hash(&[("a.rs", b"fn main() {}")]));
+        assert_ne!(id, hash(&[("b.rs", b"fn main() {}")]));
+        assert_ne!(hash(&[("a", b"bc")]), hash(&[("ab", b"c")]));
+        assert_ne!(hash(&[("a", b"x"), ("b", b"y")]), hash(&[("a", b"xy")]));
+        assert!(!is_valid_id("../etc/passwd"));
+        assert!(is_valid_id(&private_id()));
+        assert_ne!(private_id(), private_id());
+    }
+}
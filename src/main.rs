@@ -2,14 +2,23 @@
 
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
-use clap::Parser;
-use cloc::application::start_application;
+use clap::{Parser, Subcommand};
+use cloc::{
+    application::start_application,
+    cli::{self, RepositoryUrl},
+    logic::api_key::{ApiKeys, Quota},
+};
 use const_format::formatcp;
-use std::net::{IpAddr, SocketAddr};
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::process::ExitCode;
 use time::{macros::format_description, UtcOffset};
 use tokio_postgres::NoTls;
-use tracing_subscriber::{fmt::time::OffsetTime, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::{
+    fmt::{time::OffsetTime, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+};
 
 fn main() -> ExitCode {
     match run() {
@@ -22,62 +31,71 @@ fn main() -> ExitCode {
 }
 
 fn run() -> Result<(), String> {
+    let opt = Opt::parse();
+
+    // Client modes print their result to stdout, so logs go to stderr and only warnings
+    let serving = matches!(opt.command, None | Some(Command::Serve { .. }));
+    let (default_filter, writer) = if serving {
+        (
+            "cloc=trace,tower_http=trace",
+            BoxMakeWriter::new(std::io::stdout),
+        )
+    } else {
+        ("cloc=warn", BoxMakeWriter::new(std::io::stderr))
+    };
+
     let timer = format_description!(
         "[year]-[month padding:zero]-[day padding:zero] [hour]:[minute]:[second]:[subsecond digits:3]"
     );
     let time_offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
     let timer = OffsetTime::new(time_offset, timer);
 
-    let layer = tracing_subscriber::fmt::layer().compact().with_timer(timer);
+    let layer = tracing_subscriber::fmt::layer()
+        .compact()
+        .with_timer(timer)
+        .with_writer(writer);
 
     //Set the RUST_LOG, if it hasn't been explicitly defined
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or("cloc=trace,tower_http=trace".into()),
+            std::env::var("RUST_LOG").unwrap_or(default_filter.into()),
         ))
         .with(layer)
         .init();
 
-    const VERSION: &str = formatcp!(
-        "\n\n\
-        Version:             {}\n\
-         Description:         {}\n\
-         Build Timestamp:     {}\n\
-         Commit SHA:          {}\n\
-         Commit Message:      \"{}\"\n\
-         rustc Version:       {}\n\
-         cargo Target Triple: {}\n",
-        env!("CARGO_PKG_VERSION"),
-        env!("CARGO_PKG_DESCRIPTION"),
-        env!("VERGEN_BUILD_TIMESTAMP"),
-        env!("VERGEN_GIT_DESCRIBE"),
-        env!("VERGEN_GIT_COMMIT_MESSAGE"),
-        env!("VERGEN_RUSTC_SEMVER"),
-        env!("VERGEN_CARGO_TARGET_TRIPLE")
-    );
-
-    #[derive(Debug, Parser)]
-    #[command(version = VERSION)]
-    #[command(about)]
-    struct Opt {
-        /// IP address of service
-        ip_address: std::net::Ipv4Addr,
-        /// Port of service
-        port: u16,
-    }
-
-    let opt = Opt::parse();
-    let ip = IpAddr::V4(opt.ip_address);
-    let port = opt.port;
-
-    let socket = SocketAddr::new(ip, port);
-
     let r = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .thread_name("main_thread")
         .build()
         .map_err(|error| format!("Failed to build Tokio runtime: {error}"))?;
 
+    match opt.command {
+        Some(Command::ApiKey { command }) => r.block_on(manage_api_keys(command)),
+        Some(Command::Analyze {
+            url,
+            branch,
+            format,
+        }) => r.block_on(analyze(url, branch, format)),
+        Some(Command::Remote {
+            server,
+            url,
+            branch,
+            api_key,
+        }) => r.block_on(remote(server, url, branch, api_key)),
+        Some(Command::Serve { ip_address, port }) => serve(r, ip_address, port),
+        None => {
+            let (Some(ip_address), Some(port)) = (opt.ip_address, opt.port) else {
+                return Err("IP address and port are required".to_string());
+            };
+            serve(r, ip_address, port)
+        }
+    }
+}
+
+fn serve(r: tokio::runtime::Runtime, ip_address: Ipv4Addr, port: u16) -> Result<(), String> {
+    let ip = IpAddr::V4(ip_address);
+    let socket = SocketAddr::new(ip, port);
+
     let path = std::env::current_exe()
         .map_err(|error| format!("Failed to resolve current executable path: {error}"))?;
     let threads_count = r.metrics().num_workers();
@@ -88,7 +106,180 @@ fn run() -> Result<(), String> {
     r.block_on(start_all(socket))
 }
 
+const VERSION: &str = formatcp!(
+    "\n\n\
+    Version:             {}\n\
+     Description:         {}\n\
+     Build Timestamp:     {}\n\
+     Commit SHA:          {}\n\
+     Commit Message:      \"{}\"\n\
+     rustc Version:       {}\n\
+     cargo Target Triple: {}\n",
+    env!("CARGO_PKG_VERSION"),
+    env!("CARGO_PKG_DESCRIPTION"),
+    env!("VERGEN_BUILD_TIMESTAMP"),
+    env!("VERGEN_GIT_DESCRIBE"),
+    env!("VERGEN_GIT_COMMIT_MESSAGE"),
+    env!("VERGEN_RUSTC_SEMVER"),
+    env!("VERGEN_CARGO_TARGET_TRIPLE")
+);
+
+#[derive(Debug, Parser)]
+#[command(version = VERSION)]
+#[command(about)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Opt {
+    /// IP address of service
+    #[arg(required = true)]
+    ip_address: Option<std::net::Ipv4Addr>,
+    /// Port of service
+    #[arg(required = true)]
+    port: Option<u16>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Start the service, the same as passing the address and port without a subcommand
+    Serve {
+        /// IP address of service
+        ip_address: Ipv4Addr,
+        /// Port of service
+        port: u16,
+    },
+    /// Clone a repository and count its lines of code locally, without the database
+    Analyze {
+        /// Repository such as https://github.com/boyter/scc
+        url: String,
+        /// Branch to count, the one in the url or the default branch if omitted
+        #[arg(long)]
+        branch: Option<String>,
+        /// Output format of scc: tabular, wide, json, csv, html, ...
+        #[arg(long, default_value = "tabular")]
+        format: String,
+    },
+    /// Ask a running service for the lines of code of a repository
+    Remote {
+        /// Url of the service such as https://cloc.info
+        server: String,
+        /// Repository such as https://github.com/boyter/scc
+        url: String,
+        /// Branch to count, the one in the url or the default branch if omitted
+        #[arg(long)]
+        branch: Option<String>,
+        /// Key sent as `X-Api-Key`
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+    /// Manage API keys
+    ApiKey {
+        #[command(subcommand)]
+        command: ApiKeyCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ApiKeyCommand {
+    /// Create a key and print it, the key can't be shown again
+    Create {
+        /// Name of the client the key is issued to
+        name: String,
+        /// Maximum number of new analyses per hour, unlimited if omitted
+        #[arg(long)]
+        analyses_per_hour: Option<u32>,
+        /// Maximum total size of cloned repositories in bytes, unlimited if omitted
+        #[arg(long)]
+        max_bytes: Option<u64>,
+    },
+    /// Revoke a key by its id
+    Revoke { id: i64 },
+}
+
+async fn manage_api_keys(command: ApiKeyCommand) -> Result<(), String> {
+    let api_keys = ApiKeys::new(connect_database().await?);
+    match command {
+        ApiKeyCommand::Create {
+            name,
+            analyses_per_hour,
+            max_bytes,
+        } => {
+            let quota = Quota {
+                analyses_per_hour,
+                max_bytes,
+            };
+            let (api_key, key) = api_keys
+                .create(&name, quota)
+                .await
+                .map_err(|error| format!("Failed to create API key: {error}"))?;
+            println!("id:  {}\nkey: {key}", api_key.id);
+            Ok(())
+        }
+        ApiKeyCommand::Revoke { id } => {
+            let revoked = api_keys
+                .revoke(id)
+                .await
+                .map_err(|error| format!("Failed to revoke API key: {error}"))?;
+            if revoked {
+                println!("API key {id} revoked");
+                Ok(())
+            } else {
+                Err(format!("No active API key with id {id}"))
+            }
+        }
+    }
+}
+
+fn repository_url(url: &str) -> Result<RepositoryUrl, String> {
+    RepositoryUrl::parse(url).ok_or_else(|| format!("Not a repository url: {url}"))
+}
+
+fn print_output(output: &[u8]) -> Result<(), String> {
+    std::io::stdout()
+        .write_all(output)
+        .map_err(|error| format!("Failed to print the result: {error}"))
+}
+
+async fn analyze(url: String, branch: Option<String>, format: String) -> Result<(), String> {
+    let url = repository_url(&url)?;
+    let output = cli::analyze(&url, branch, &format).await.map_err(|error| {
+        format!(
+            "Failed to analyze {}/{}: {error}",
+            url.owner, url.repository
+        )
+    })?;
+    print_output(&output)
+}
+
+async fn remote(
+    server: String,
+    url: String,
+    branch: Option<String>,
+    api_key: Option<String>,
+) -> Result<(), String> {
+    let url = repository_url(&url)?;
+    let mut client = cloc_api::Client::new(server);
+    if let Some(api_key) = api_key {
+        client = client.with_api_key(api_key);
+    }
+    let output = client
+        .analyze(&url.analysis_request(branch))
+        .await
+        .map_err(|error| {
+            format!(
+                "Failed to analyze {}/{}: {error}",
+                url.owner, url.repository
+            )
+        })?;
+    print_output(output.as_bytes())
+}
+
 async fn start_all(socket: SocketAddr) -> Result<(), String> {
+    let pool = connect_database().await?;
+    start_application(socket, pool).await
+}
+
+async fn connect_database() -> Result<Pool<PostgresConnectionManager<NoTls>>, String> {
     // Read database connection parameters from environment variables
     let db_host = std::env::var("DATABASE_HOST").unwrap_or_else(|_| "localhost".to_string());
     let db_user = std::env::var("DATABASE_USER").unwrap_or_else(|_| "postgres".to_string());
@@ -113,10 +304,8 @@ async fn start_all(socket: SocketAddr) -> Result<(), String> {
         .user(&db_user)
         .to_owned();
     // let manager = PostgresConnectionManager::new(config, NoTls);
-    let pool = Pool::builder()
+    Pool::builder()
         .build(manager)
         .await
-        .map_err(|error| format!("Failed to build Postgres pool: {error}"))?;
-
-    start_application(socket, pool).await
+        .map_err(|error| format!("Failed to build Postgres pool: {error}"))
 }
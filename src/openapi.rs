@@ -0,0 +1,372 @@
+use crate::{
+    analysis::{self, Analysis, AnalysisRequest, AnalysisStatus, PreviousResult},
+    badge, handlers, logic, preview,
+    problem::{Failure, ProblemDetails},
+    statistic, upload, webhook, websocket,
+};
+use axum::{
+    routing::{get, MethodRouter},
+    Json, Router,
+};
+use utoipa::{
+    openapi::{
+        path::{HttpMethod, OperationBuilder, PathItem},
+        Content, ResponseBuilder,
+    },
+    IntoParams, IntoResponses, OpenApi,
+};
+
+/// Prefix of the JSON lookups of a forge.
+pub const REPOSITORY_API_PREFIX: &str = "/api/:host";
+/// Prefix of the statistics, the analysis API and this document.
+pub const API_PREFIX: &str = "/api";
+/// Prefix of the pages, which answer the cloc protocol when sent `If-Match: cloc`.
+pub const PAGE_PREFIX: &str = "/:host";
+/// Prefix of the progress streams.
+pub const WEBSOCKET_PREFIX: &str = "/ws/:host";
+
+/// Static pages served as files, with the file and the summary of their path item.
+pub const PAGES: [(&str, &str, &str); 2] = [
+    (
+        "/",
+        "dist/index.html",
+        "Home page with the repository form.",
+    ),
+    (
+        "/upload",
+        "dist/upload.html",
+        "Page to upload files, archives and bundles.",
+    ),
+];
+
+/// Router that remembers the paths of its routes, so they can be checked against the document.
+pub struct Routes<S = ()> {
+    router: Router<S>,
+    paths: Vec<String>,
+}
+
+impl<S: Clone + Send + Sync + 'static> Default for Routes<S> {
+    fn default() -> Self {
+        Self {
+            router: Router::new(),
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl<S: Clone + Send + Sync + 'static> Routes<S> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, path: &str, method_router: MethodRouter<S>) -> Self {
+        self.router = self.router.route(path, method_router);
+        self.paths.push(path.to_string());
+        self
+    }
+
+    pub fn merge(mut self, other: Routes<S>) -> Self {
+        self.router = self.router.merge(other.router);
+        self.paths.extend(other.paths);
+        self
+    }
+
+    /// Routes of `other` below `prefix`, which may have parameters such as `/:host`.
+    pub fn nest(mut self, prefix: &str, other: Routes<S>) -> Self {
+        self.router = self.router.nest(prefix, other.router);
+        self.paths.extend(
+            other
+                .paths
+                .into_iter()
+                .map(|path| format!("{prefix}{path}")),
+        );
+        self
+    }
+
+    pub fn paths(&self) -> &[String] {
+        &self.paths
+    }
+
+    pub fn with_state<S2: Clone + Send + Sync + 'static>(self, state: S) -> Routes<S2> {
+        Routes {
+            router: self.router.with_state(state),
+            paths: self.paths,
+        }
+    }
+
+    pub fn into_router(self) -> Router<S> {
+        self.router
+    }
+}
+
+impl Routes {
+    /// Frontend files below `prefix`, served as they are and not part of the document.
+    pub fn static_files(mut self, prefix: &str, service: MethodRouter) -> Self {
+        self.router = self.router.nest_service(prefix, service);
+        self
+    }
+}
+
+/// Forge, owner and repository as in the forge's own urls.
+#[derive(IntoParams)]
+#[into_params(parameter_in = Path)]
+#[allow(dead_code)]
+pub struct RepositoryPath {
+    /// `github.com`, `gitlab.com`, `codeberg.org` or another forge.
+    host: String,
+    owner: String,
+    /// With or without `.git`.
+    repo: String,
+}
+
+/// Repository path followed by a branch, which may contain slashes.
+#[derive(IntoParams)]
+#[into_params(parameter_in = Path)]
+#[allow(dead_code)]
+pub struct BranchPath {
+    /// `github.com`, `gitlab.com`, `codeberg.org` or another forge.
+    host: String,
+    owner: String,
+    /// With or without `.git`.
+    repo: String,
+    branch: String,
+}
+
+#[derive(IntoParams)]
+#[into_params(parameter_in = Path)]
+#[allow(dead_code)]
+pub struct OwnerPath {
+    /// `github.com`, `gitlab.com`, `codeberg.org` or another forge.
+    host: String,
+    /// User or organisation.
+    owner: String,
+}
+
+#[derive(IntoParams)]
+#[into_params(parameter_in = Path)]
+#[allow(dead_code)]
+pub struct LimitPath {
+    /// Maximum number of repositories.
+    limit: u64,
+}
+
+/// Query of the cloc protocol and the progress streams.
+#[derive(IntoParams)]
+#[into_params(parameter_in = Query)]
+#[allow(dead_code)]
+pub struct CloneQuery {
+    /// Comma separated directories or files to analyse, everything if missing.
+    paths: Option<String>,
+    /// Check out submodules recursively.
+    submodules: Option<bool>,
+    /// Url notified with a POST when the analysis finishes, ignored by the streams.
+    callback: Option<String>,
+}
+
+/// Failures of lookups on the forge, as `application/problem+json`.
+#[derive(IntoResponses)]
+#[allow(dead_code)]
+pub enum LookupProblems {
+    /// Repository, branch or owner not found, or the forge API is not supported.
+    #[response(status = 404, content_type = "application/problem+json")]
+    NotFound(ProblemDetails),
+    /// The repository is private and no valid token was sent.
+    #[response(status = 401, content_type = "application/problem+json")]
+    AuthenticationRequired(ProblemDetails),
+    /// Too many requests from this client or api key, see `Retry-After`.
+    #[response(status = 429, content_type = "application/problem+json")]
+    RateLimited(ProblemDetails),
+    /// The forge failed or can't be reached.
+    #[response(status = 502, content_type = "application/problem+json")]
+    RemoteUnreachable(ProblemDetails),
+    /// The forge didn't answer in time.
+    #[response(status = 504, content_type = "application/problem+json")]
+    RemoteTimeout(ProblemDetails),
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "cloc",
+        description = "Counts lines of code of public and private git repositories."
+    ),
+    paths(
+        handlers::owner_report,
+        handlers::queue_owner,
+        handlers::default_branch_info,
+        handlers::branch_commit_info,
+        handlers::all_branches_lookup,
+        handlers::owner_handler,
+        handlers::default_handler,
+        handlers::handler_with_branch,
+        websocket::handler_ws,
+        websocket::handler_ws_with_branch,
+        statistic::largest,
+        statistic::recent,
+        statistic::popular,
+        analysis::create_analysis,
+        analysis::get_analysis,
+        openapi_json,
+        badge::badge,
+        badge::badge_with_branch,
+        preview::preview,
+        preview::preview_with_branch,
+        upload::upload,
+        upload::upload_bundle,
+        upload::upload_result,
+        webhook::webhook,
+    ),
+    components(schemas(
+        logic::info::Status,
+        logic::info::Branches,
+        logic::info::BranchValue,
+        logic::info::TagValue,
+        logic::info::OwnerReport,
+        logic::info::OwnerRepository,
+        logic::summary::LanguageStat,
+        logic::summary::Summary,
+        logic::info::LargestRepository,
+        logic::info::LargestBranch,
+        logic::info::RecentRepository,
+        logic::info::RecentBranch,
+        logic::info::PopularRepository,
+        logic::info::PopularBranch,
+        AnalysisRequest,
+        Analysis,
+        AnalysisStatus,
+        PreviousResult,
+        ProblemDetails,
+        Failure,
+        upload::UploadResult,
+    )),
+    tags(
+        (name = "repositories", description = "Lookups on the forge, answered without cloning"),
+        (name = "analyses", description = "Line counts of a repository"),
+        (name = "statistics", description = "Repositories counted so far"),
+        (name = "images", description = "Badges and link previews"),
+        (name = "uploads", description = "Line counts of uploaded files and repositories"),
+        (name = "webhooks", description = "Push events of the forges"),
+        (name = "pages", description = "Frontend pages"),
+    )
+)]
+struct ApiDoc;
+
+/// Route patterns sharing a handler, documented under the first one of their group.
+const ALIASES: [&[&str]; 2] = [&handlers::BRANCH_PATHS, &handlers::REPOSITORY_PATHS];
+
+/// axum's `:param` and `*param` as OpenAPI's `{param}`.
+pub fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix([':', '*']) {
+            Some(name) => format!("{{{name}}}"),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// OpenAPI 3 document of the service with every route pattern listed.
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut document = ApiDoc::openapi();
+    for (path, file, summary) in PAGES {
+        let response = ResponseBuilder::new()
+            .description(format!("`{file}`"))
+            .content("text/html", Content::default())
+            .build();
+        let operation = OperationBuilder::new()
+            .tag("pages")
+            .summary(Some(summary))
+            .response("200", response)
+            .build();
+        document
+            .paths
+            .paths
+            .insert(path.to_string(), PathItem::new(HttpMethod::Get, operation));
+    }
+    let mut aliases = Vec::new();
+    for (path, item) in &document.paths.paths {
+        let Some((canonical, group)) = ALIASES.iter().find_map(|group| {
+            let canonical = openapi_path(group[0]);
+            path.contains(&canonical).then_some((canonical, group))
+        }) else {
+            continue;
+        };
+        for alias in &group[1..] {
+            let mut item = item.clone();
+            // Operation ids have to be unique
+            for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+                operation.operation_id = None;
+            }
+            aliases.push((path.replacen(&canonical, &openapi_path(alias), 1), item));
+        }
+    }
+    document.paths.paths.extend(aliases);
+    document
+}
+
+/// `GET /api/openapi.json`: this document.
+#[utoipa::path(
+    get,
+    path = "/api/openapi.json",
+    responses((status = 200, description = "OpenAPI 3 document of the service", content_type = "application/json"))
+)]
+async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(document())
+}
+
+pub fn routes() -> Routes {
+    Routes::new().route("/openapi.json", get(openapi_json))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{document, openapi_path};
+    use crate::{
+        analysis::Analyses,
+        application,
+        logic::{archive::ArchiveLimits, repository::tests::provider, upload::UploadStore},
+        upload::{UploadLimits, UploadState},
+        webhook::WebhookState,
+    };
+
+    #[tokio::test]
+    async fn every_route_is_documented() {
+        assert_eq!(
+            openapi_path("/:owner/:repo/tree/*branch"),
+            "/{owner}/{repo}/tree/{branch}"
+        );
+
+        let provider = provider();
+        let pool = provider.connection_pool.clone();
+        let upload_state = UploadState::new(
+            "cloc_repo",
+            UploadLimits::from_env(),
+            ArchiveLimits::from_env(),
+            UploadStore::from_env(pool.clone()),
+            Default::default(),
+        );
+        let webhook_state = WebhookState {
+            provider: provider.clone(),
+            secret: None,
+        };
+        let routes = application::routes(
+            provider.clone(),
+            Analyses::new(provider),
+            pool,
+            upload_state,
+            webhook_state,
+        );
+
+        let document = document();
+        for path in routes.paths() {
+            let path = openapi_path(path);
+            assert!(
+                document.paths.paths.contains_key(&path),
+                "{path} is not documented"
+            );
+        }
+        // Nothing documented that isn't served
+        assert_eq!(document.paths.paths.len(), routes.paths().len());
+        assert!(document.components.unwrap().schemas.contains_key("Status"));
+    }
+}
@@ -0,0 +1,297 @@
+use crate::{
+    badge::{escape, humanize},
+    handlers::{extract_user_agent, requester, BRANCH_PATHS, REPOSITORY_PATHS},
+    logic::{
+        info::{CloneOptions, Requester, Status},
+        repository::RepositoryProvider,
+        summary::Summary,
+    },
+    openapi::{BranchPath, RepositoryPath, Routes},
+    problem::Problem,
+};
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use hyper::{
+    header::{HeaderValue, CACHE_CONTROL, CONTENT_TYPE},
+    Request,
+};
+use resvg::{tiny_skia, usvg};
+use std::sync::{Arc, LazyLock};
+
+const PNG_CONTENT_TYPE: &str = "image/png";
+const BAR_COLORS: [&str; 5] = ["#f97316", "#3b82f6", "#22c55e", "#eab308", "#a855f7"];
+const CARD_WIDTH: usize = 1200;
+const CARD_HEIGHT: usize = 630;
+
+/// Fonts of the system, loaded once for all cards.
+static FONTS: LazyLock<Arc<usvg::fontdb::Database>> = LazyLock::new(|| {
+    let mut fonts = usvg::fontdb::Database::new();
+    fonts.load_system_fonts();
+    Arc::new(fonts)
+});
+
+/// Origin used for absolute urls in meta tags, `PUBLIC_URL` or `https://cloc.info`.
+pub(crate) fn public_url() -> String {
+    std::env::var("PUBLIC_URL")
+        .unwrap_or_else(|_| "https://cloc.info".to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Open Graph and Twitter card tags for the result page served at `path`.
+pub fn open_graph_tags(path: &str, owner: &str, repository_name: &str) -> String {
+    let public_url = public_url();
+    let name = repository_name.trim_end_matches(".git");
+    let title = escape(&format!("{owner}/{name} · lines of code"));
+    let description = escape(&format!(
+        "Lines of code, languages and complexity of {}",
+        path.trim_start_matches('/')
+    ));
+    let url = escape(&format!("{public_url}{path}"));
+    let image = escape(&format!("{public_url}/preview{path}"));
+
+    format!(
+        "<meta property=\"og:type\" content=\"website\">\n\
+         <meta property=\"og:title\" content=\"{title}\">\n\
+         <meta property=\"og:description\" content=\"{description}\">\n\
+         <meta property=\"og:url\" content=\"{url}\">\n\
+         <meta property=\"og:image\" content=\"{image}\">\n\
+         <meta property=\"og:image:type\" content=\"image/png\">\n\
+         <meta property=\"og:image:width\" content=\"{CARD_WIDTH}\">\n\
+         <meta property=\"og:image:height\" content=\"{CARD_HEIGHT}\">\n\
+         <meta name=\"twitter:card\" content=\"summary_large_image\">\n\
+         <meta name=\"twitter:title\" content=\"{title}\">\n\
+         <meta name=\"twitter:image\" content=\"{image}\">\n"
+    )
+}
+
+/// Inserts `tags` right before `</head>`, the page is returned untouched without it.
+pub fn inject_head(page: Vec<u8>, tags: &str) -> Vec<u8> {
+    let marker = b"</head>";
+    match page
+        .windows(marker.len())
+        .position(|window| window.eq_ignore_ascii_case(marker))
+    {
+        Some(position) => [&page[..position], tags.as_bytes(), &page[position..]].concat(),
+        None => page,
+    }
+}
+
+pub fn routes() -> Routes<RepositoryProvider> {
+    let routes = REPOSITORY_PATHS.iter().fold(Routes::new(), |routes, path| {
+        routes.route(path, get(preview))
+    });
+    BRANCH_PATHS.iter().fold(routes, |routes, path| {
+        routes.route(path, get(preview_with_branch))
+    })
+}
+
+/// Open Graph card of a repository, linked from the `og:image` of its page.
+#[utoipa::path(
+    get,
+    path = "/preview/{host}/{owner}/{repo}",
+    tag = "images",
+    params(RepositoryPath),
+    responses((status = 200, description = "Card with the languages, without them while there is no result", content_type = "image/png"))
+)]
+async fn preview(
+    Path((host, owner, repository_name)): Path<(String, String, String)>,
+    State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let requester = requester(extract_user_agent(&request), &request);
+    render_preview(host, owner, repository_name, None, provider, requester).await
+}
+
+/// Open Graph card of a branch.
+#[utoipa::path(
+    get,
+    path = "/preview/{host}/{owner}/{repo}/tree/{branch}",
+    tag = "images",
+    params(BranchPath),
+    responses((status = 200, description = "Card with the languages, without them while there is no result", content_type = "image/png"))
+)]
+async fn preview_with_branch(
+    Path((host, owner, repository_name, branch)): Path<(String, String, String, String)>,
+    State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let branch = branch.trim_matches('/').to_string();
+    let requester = requester(extract_user_agent(&request), &request);
+    render_preview(
+        host,
+        owner,
+        repository_name,
+        Some(branch),
+        provider,
+        requester,
+    )
+    .await
+}
+
+async fn render_preview(
+    host: String,
+    owner: String,
+    mut repository_name: String,
+    branch: Option<String>,
+    provider: RepositoryProvider,
+    requester: Requester,
+) -> Response<Body> {
+    if host != "git.sr.ht" && !repository_name.ends_with(".git") {
+        repository_name = format!("{repository_name}.git");
+    }
+    let title = format!("{owner}/{}", repository_name.trim_end_matches(".git"));
+    let subtitle = match &branch {
+        Some(branch) => format!("{host} · {branch}"),
+        None => host.clone(),
+    };
+
+    let status = provider
+        .request_info_for(
+            host,
+            owner,
+            repository_name,
+            branch,
+            CloneOptions::default(),
+            requester,
+        )
+        .await;
+
+    let (card, cache_control) = match status {
+        Ok((_, Status::Done(data))) | Ok((_, Status::Previous { data, .. })) => {
+            (card(&title, &subtitle, Some(&Summary::parse(&data))), None)
+        }
+        result => {
+            if let Err(error) = result {
+                tracing::warn!("Preview error: {error}");
+            }
+            (
+                card(&title, &subtitle, None),
+                Some("no-cache,private,max-age=0"),
+            )
+        }
+    };
+
+    // Crawlers don't render SVG images, the card is sent as PNG
+    match tokio::task::spawn_blocking(move || rasterize(&card)).await {
+        Ok(Some(png)) => {
+            let mut response = ([(CONTENT_TYPE, PNG_CONTENT_TYPE)], png).into_response();
+            if let Some(cache_control) = cache_control {
+                response
+                    .headers_mut()
+                    .insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+            }
+            response
+        }
+        _ => Problem::internal("Can't render the preview").into_response(),
+    }
+}
+
+/// Renders the SVG `card` to PNG, `None` if it can't be parsed.
+fn rasterize(card: &str) -> Option<Vec<u8>> {
+    let options = usvg::Options {
+        fontdb: FONTS.clone(),
+        ..Default::default()
+    };
+    let tree = match usvg::Tree::from_str(card, &options) {
+        Ok(tree) => tree,
+        Err(error) => {
+            tracing::error!("Can't parse preview card: {error}");
+            return None;
+        }
+    };
+    let mut pixmap = tiny_skia::Pixmap::new(CARD_WIDTH as u32, CARD_HEIGHT as u32)?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().ok()
+}
+
+fn card(title: &str, subtitle: &str, summary: Option<&Summary>) -> String {
+    let title = escape(title);
+    let subtitle = escape(subtitle);
+    let mut body = String::new();
+
+    match summary {
+        Some(summary) => {
+            let total = summary.total.code.max(1);
+            body.push_str(&format!(
+                r##"<text x="80" y="300" font-size="72" font-weight="700" fill="#fff">{} lines of code</text>"##,
+                humanize(summary.total.code)
+            ));
+            body.push_str(&format!(
+                r##"<text x="80" y="350" font-size="28" fill="#94a3b8">{} files · {} languages</text>"##,
+                summary.total.files,
+                summary.languages.len()
+            ));
+
+            let mut x = 80;
+            let bar_width = CARD_WIDTH - 160;
+            for (index, stat) in summary.languages.iter().take(BAR_COLORS.len()).enumerate() {
+                let width = (bar_width as u64 * stat.code / total) as usize;
+                let color = BAR_COLORS[index];
+                let y = 470 + index / 3 * 50;
+                let label_x = 80 + (index % 3) * 360;
+                body.push_str(&format!(
+                    r##"<rect x="{x}" y="390" width="{width}" height="24" fill="{color}"/><circle cx="{}" cy="{}" r="9" fill="{color}"/><text x="{}" y="{}" font-size="26" fill="#e2e8f0">{} {}%</text>"##,
+                    label_x + 9,
+                    y - 9,
+                    label_x + 28,
+                    y,
+                    escape(&stat.language),
+                    stat.code * 100 / total
+                ));
+                x += width;
+            }
+        }
+        None => body.push_str(
+            r##"<text x="80" y="320" font-size="56" fill="#fff">Analysis in progress…</text>"##,
+        ),
+    }
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{CARD_WIDTH}" height="{CARD_HEIGHT}" viewBox="0 0 {CARD_WIDTH} {CARD_HEIGHT}"><rect width="100%" height="100%" fill="#0f172a"/><g font-family="Verdana,DejaVu Sans,sans-serif"><text x="80" y="130" font-size="56" font-weight="700" fill="#fff">{title}</text><text x="80" y="185" font-size="30" fill="#94a3b8">{subtitle}</text>{body}<text x="{}" y="590" font-size="24" fill="#64748b" text-anchor="end">cloc.info</text></g></svg>"##,
+        CARD_WIDTH - 80
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{card, inject_head, open_graph_tags, rasterize, CARD_HEIGHT, CARD_WIDTH};
+    use crate::logic::summary::Summary;
+
+    #[test]
+    fn tags_are_injected_into_head() {
+        let page = b"<html><head><title>x</title></head><body></body></html>".to_vec();
+        let tags = open_graph_tags("/github.com/acme/tool", "acme", "tool.git");
+        let page = String::from_utf8(inject_head(page, &tags)).unwrap();
+
+        assert!(page.contains("og:title\" content=\"acme/tool · lines of code\""));
+        assert!(page.contains("/preview/github.com/acme/tool\""));
+        assert!(page.find("og:image").unwrap() < page.find("</head>").unwrap());
+    }
+
+    #[test]
+    fn card_lists_top_languages() {
+        let summary = Summary::parse(
+            b"Language Files Lines Blanks Comments Code Complexity\nRust 3 120 10 10 100 0\nTotal 3 120 10 10 100 0\n",
+        );
+
+        let svg = card("acme/tool", "github.com", Some(&summary));
+
+        assert!(svg.contains("100 lines of code"));
+        assert!(svg.contains("Rust 100%"));
+    }
+
+    #[test]
+    fn card_is_rendered_as_png() {
+        let png = rasterize(&card("acme/tool", "github.com", None)).unwrap();
+
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+        assert_eq!((width, height), (CARD_WIDTH as u32, CARD_HEIGHT as u32));
+    }
+}
@@ -0,0 +1,211 @@
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::borrow::Cow;
+use tracing::Instrument;
+
+pub use cloc_api::problem::{Failure, ProblemDetails, PROBLEM_JSON};
+
+/// Header echoing the id of the request, accepted from the client or a proxy.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+const REQUEST_ID_LENGTH: usize = 16;
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled, set by `request_id_middleware`.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'))
+}
+
+/// Keeps the client's `X-Request-Id` or generates one, logs the request within a span
+/// carrying it and returns it in the response and in problem bodies.
+pub async fn request_id_middleware(mut request: Request<Body>, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| {
+            thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(REQUEST_ID_LENGTH)
+                .map(char::from)
+                .collect()
+        });
+    let header_value = HeaderValue::from_str(&request_id).ok();
+    if let Some(value) = &header_value {
+        request
+            .headers_mut()
+            .insert(REQUEST_ID_HEADER, value.clone());
+    }
+
+    let span = tracing::info_span!("request", request_id = %request_id);
+    let mut response = REQUEST_ID
+        .scope(request_id, next.run(request))
+        .instrument(span)
+        .await;
+    if let Some(value) = header_value {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// RFC 7807 error response with a stable `code` and the request id as extension members.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub status: StatusCode,
+    pub code: Cow<'static, str>,
+    pub detail: String,
+    pub retry_after: Option<u64>,
+}
+
+impl Problem {
+    pub fn new(status: StatusCode, code: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: Cow::Borrowed(code),
+            detail: detail.into(),
+            retry_after: None,
+        }
+    }
+
+    pub fn internal(detail: impl ToString) -> Self {
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "internal_error",
+            detail.to_string(),
+        )
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn body(&self) -> ProblemDetails {
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: self
+                .status
+                .canonical_reason()
+                .unwrap_or("Error")
+                .to_string(),
+            status: self.status.as_u16(),
+            detail: self.detail.clone(),
+            code: self.code.to_string(),
+            request_id: current_request_id(),
+        }
+    }
+}
+
+impl From<&crate::logic::Error> for Problem {
+    fn from(error: &crate::logic::Error) -> Self {
+        let problem = Self::new(error.status(), error.code(), error.to_string());
+        match error {
+            crate::logic::Error::RateLimited { retry_after } => {
+                problem.with_retry_after(*retry_after)
+            }
+            _ => problem,
+        }
+    }
+}
+
+/// A task that failed in the background is reported the way its error would have been.
+impl From<&Failure> for Problem {
+    fn from(failure: &Failure) -> Self {
+        Self {
+            status: StatusCode::from_u16(failure.status)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+            code: Cow::Owned(failure.code.clone()),
+            detail: failure.detail.clone(),
+            retry_after: None,
+        }
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            tracing::error!("{}", self.detail);
+        } else {
+            tracing::info!("{}", self.detail);
+        }
+
+        let mut response = (
+            self.status,
+            [(header::CONTENT_TYPE, PROBLEM_JSON)],
+            Json(self.body()),
+        )
+            .into_response();
+        if let Some(seconds) = self.retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{request_id_middleware, Problem, PROBLEM_JSON, REQUEST_ID_HEADER};
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header::CONTENT_TYPE, Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn problem_carries_the_request_id() {
+        let app = Router::new()
+            .route(
+                "/missing",
+                get(|| async { Problem::new(StatusCode::NOT_FOUND, "not_found", "Nothing here") }),
+            )
+            .layer(axum::middleware::from_fn(request_id_middleware));
+
+        let request = Request::get("/missing")
+            .header(REQUEST_ID_HEADER, "abc-123")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc-123");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["title"], "Not Found");
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["request_id"], "abc-123");
+
+        // Ids that could forge log lines are replaced
+        let request = Request::get("/missing")
+            .header(REQUEST_ID_HEADER, "a b")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert_eq!(request_id.len(), 16);
+    }
+}
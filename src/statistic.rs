@@ -1,19 +1,34 @@
-use crate::logic::info::{LargestRepositories, PopularRepositories, RecentRepositories};
+use crate::{
+    logic::info::{
+        LargestRepositories, LargestRepository, PopularRepositories, PopularRepository,
+        RecentRepositories, RecentRepository,
+    },
+    openapi::{LimitPath, Routes},
+    problem::{Problem, ProblemDetails},
+};
 use axum::{
     body::Body,
     extract::{Path, State},
     response::{IntoResponse, Response},
+    routing::get,
 };
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
-use hyper::{header::CONTENT_TYPE, StatusCode};
+use hyper::header::CONTENT_TYPE;
 use mime_guess::mime::APPLICATION_JSON;
 use serde::Serialize;
 use std::fmt::Display;
 use tokio_postgres::NoTls;
 
+pub fn routes() -> Routes<Pool<PostgresConnectionManager<NoTls>>> {
+    Routes::new()
+        .route("/largest/:limit", get(largest))
+        .route("/recent/:limit", get(recent))
+        .route("/popular/:limit", get(popular))
+}
+
 fn internal_server_error_response(error: impl Display) -> Response<Body> {
-    (StatusCode::INTERNAL_SERVER_ERROR, error.to_string()).into_response()
+    Problem::internal(error).into_response()
 }
 
 fn json_response<T: Serialize>(payload: &T) -> Response<Body> {
@@ -23,6 +38,16 @@ fn json_response<T: Serialize>(payload: &T) -> Response<Body> {
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/largest/{limit}",
+    tag = "statistics",
+    params(LimitPath),
+    responses(
+        (status = 200, description = "Largest repositories first", body = Vec<LargestRepository>),
+        (status = 500, description = "The database failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn largest(
     Path(limit): Path<i64>,
     State(connection_pool): State<Pool<PostgresConnectionManager<NoTls>>>,
@@ -42,6 +67,16 @@ pub async fn largest(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/recent/{limit}",
+    tag = "statistics",
+    params(LimitPath),
+    responses(
+        (status = 200, description = "Most recently analysed repositories first", body = Vec<RecentRepository>),
+        (status = 500, description = "The database failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn recent(
     Path(limit): Path<u64>,
     State(connection_pool): State<Pool<PostgresConnectionManager<NoTls>>>,
@@ -62,6 +97,16 @@ pub async fn recent(
     }
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/popular/{limit}",
+    tag = "statistics",
+    params(LimitPath),
+    responses(
+        (status = 200, description = "Most often requested repositories first", body = Vec<PopularRepository>),
+        (status = 500, description = "The database failed", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
 pub async fn popular(
     Path(limit): Path<u64>,
     State(connection_pool): State<Pool<PostgresConnectionManager<NoTls>>>,
@@ -0,0 +1,843 @@
+use crate::{
+    logic::{
+        self,
+        api_key::ApiKey,
+        archive::{conflicting_path, extract_archive, ArchiveKind, ArchiveLimits, Extracted},
+        bundle::{
+            clone_local, find_bare_repository, resolve_branch, sanitize_bare_repository,
+            source_branches,
+        },
+        cloner::{CloneLimits, Cloner},
+        info::BranchValue,
+        repository::{count_line_of_code, dir_size},
+        summary::Summary,
+        upload::{is_valid_id, private_id, ContentHash, StoredUpload, UploadStore},
+    },
+    openapi::Routes,
+    preview::public_url,
+    problem::{Problem, ProblemDetails},
+};
+use axum::{
+    body::Body,
+    extract::{
+        multipart::Field, DefaultBodyLimit, Extension, Multipart, Path as UrlPath, Query, State,
+    },
+    response::Response,
+    routing::{get, post, MethodRouter},
+};
+use chrono::{DateTime, Utc};
+use hyper::{
+    header::{ACCEPT, CONTENT_TYPE, EXPIRES, LOCATION},
+    HeaderMap, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+use tempfile::{Builder, TempDir};
+use tokio::{
+    fs,
+    io::AsyncWriteExt,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use utoipa::{IntoParams, ToSchema};
+
+/// Prefix of every temporary upload entry, leftovers are removed on startup.
+const UPLOAD_PREFIX: &str = "upload-";
+/// Room for multipart boundaries and headers on top of the file contents.
+const MULTIPART_OVERHEAD: usize = 1024 * 1024;
+
+type UploadError = Problem;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadLimits {
+    /// Maximum total size of the uploaded files.
+    pub max_bytes: u64,
+    /// Maximum number of uploaded files, an archive counts as one.
+    pub max_files: usize,
+    /// Maximum number of uploads analysed at the same time.
+    pub concurrency: usize,
+}
+
+impl UploadLimits {
+    /// Reads `UPLOAD_MAX_BYTES` (default 100 MiB), `UPLOAD_MAX_FILES` (default 10 000)
+    /// and `UPLOAD_CONCURRENCY` (default 4).
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            max_bytes: var("UPLOAD_MAX_BYTES", 100 * 1024 * 1024),
+            max_files: var("UPLOAD_MAX_FILES", 10_000),
+            concurrency: var("UPLOAD_CONCURRENCY", 4).max(1),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UploadState {
+    root: Arc<PathBuf>,
+    limits: UploadLimits,
+    archive_limits: ArchiveLimits,
+    permits: Arc<Semaphore>,
+    store: UploadStore,
+    /// Runs git on uploaded repositories with the clone timeout and the archive size limit.
+    cloner: Cloner,
+}
+
+impl UploadState {
+    pub fn new(
+        root: impl Into<PathBuf>,
+        limits: UploadLimits,
+        archive_limits: ArchiveLimits,
+        store: UploadStore,
+        cancel: Arc<CancellationToken>,
+    ) -> Self {
+        let clone_limits = CloneLimits {
+            max_bytes: archive_limits.max_bytes,
+            ..CloneLimits::from_env()
+        };
+        Self {
+            root: Arc::new(root.into()),
+            limits,
+            archive_limits,
+            permits: Arc::new(Semaphore::new(limits.concurrency)),
+            store,
+            cloner: Cloner::new(Default::default(), clone_limits, cancel),
+        }
+    }
+
+    /// Removes upload directories and archives left behind by a crashed process.
+    pub async fn remove_stale(&self) {
+        let Ok(mut entries) = fs::read_dir(self.root.as_path()).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if !entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with(UPLOAD_PREFIX)
+            {
+                continue;
+            }
+            let path = entry.path();
+            let result = match entry.file_type().await {
+                Ok(file_type) if file_type.is_dir() => fs::remove_dir_all(&path).await,
+                _ => fs::remove_file(&path).await,
+            };
+            match result {
+                Ok(()) => tracing::info!("Removed stale upload {}", path.display()),
+                Err(error) => tracing::warn!("Can't remove {}: {error}", path.display()),
+            }
+        }
+    }
+}
+
+fn body_limit(limits: &UploadLimits) -> DefaultBodyLimit {
+    let body_limit = usize::try_from(limits.max_bytes)
+        .unwrap_or(usize::MAX)
+        .saturating_add(MULTIPART_OVERHEAD);
+    DefaultBodyLimit::max(body_limit)
+}
+
+/// `/post`, `/upload/bundle` and `/upload/:id`, limited by `state`.
+pub fn routes(state: UploadState) -> Routes {
+    Routes::new()
+        .route("/post", upload_route(state.clone()))
+        .route("/upload/bundle", upload_bundle_route(state.clone()))
+        .route("/upload/:id", upload_result_route(state))
+}
+
+pub fn upload_route(state: UploadState) -> MethodRouter {
+    let body_limit = body_limit(&state.limits);
+    post(upload).with_state(state).layer(body_limit)
+}
+
+/// Analyses an uploaded git bundle or an archive of a bare repository, `/upload/bundle`.
+/// Results are private: they are stored under a random id, which only the uploader gets.
+pub fn upload_bundle_route(state: UploadState) -> MethodRouter {
+    let body_limit = body_limit(&state.limits);
+    post(upload_bundle).with_state(state).layer(body_limit)
+}
+
+/// Permalink of a stored upload result, `/upload/:id`.
+pub fn upload_result_route(state: UploadState) -> MethodRouter {
+    get(upload_result).with_state(state)
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UploadQuery {
+    /// `json` for the per-language summary, scc text output otherwise.
+    format: Option<String>,
+    /// Branch of an uploaded bundle or bare repository, the default one if omitted.
+    branch: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(crate) struct UploadResult {
+    id: String,
+    url: String,
+    expires: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<BranchValue>,
+    summary: Summary,
+}
+
+/// Counts bytes and files written for a single upload.
+struct Budget {
+    limits: UploadLimits,
+    bytes: u64,
+    files: usize,
+}
+
+impl Budget {
+    fn new(limits: UploadLimits) -> Self {
+        Self {
+            limits,
+            bytes: 0,
+            files: 0,
+        }
+    }
+
+    fn add_file(&mut self) -> Result<(), UploadError> {
+        self.files += 1;
+        if self.files > self.limits.max_files {
+            return Err(payload_too_large(format!(
+                "Upload contains more than {} files",
+                self.limits.max_files
+            )));
+        }
+        Ok(())
+    }
+
+    fn add_bytes(&mut self, bytes: usize) -> Result<(), UploadError> {
+        self.bytes += bytes as u64;
+        if self.bytes > self.limits.max_bytes {
+            return Err(payload_too_large(format!(
+                "Upload is larger than {} bytes",
+                self.limits.max_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Counts the lines of uploaded files and archives, results are public under a content hash.
+#[utoipa::path(
+    post,
+    path = "/post",
+    tag = "uploads",
+    params(UploadQuery),
+    request_body(content_type = "multipart/form-data", description = "Files, zip and tar archives"),
+    responses(
+        (status = 200, description = "Result of an identical earlier upload", content((String = "text/plain"), (UploadResult = "application/json")), headers(("Location" = String), ("Expires" = String))),
+        (status = 201, description = "Result of the upload", content((String = "text/plain"), (UploadResult = "application/json")), headers(("Location" = String), ("Expires" = String))),
+        (status = 400, description = "Invalid multipart body or archive", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 413, description = "Too many or too large files", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many uploads in progress", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn upload(
+    Query(query): Query<UploadQuery>,
+    State(state): State<UploadState>,
+    mut multipart: Multipart,
+) -> Result<Response<Body>, UploadError> {
+    let _permit = acquire_permit(&state)?;
+    // Dropping `TempDir` removes it on every early return and when the client goes away
+    let tempdir = create_tempdir(&state).await?;
+    let path = tempdir.path().to_path_buf();
+
+    let mut budget = Budget::new(state.limits);
+    let mut extracted = Extracted::default();
+    let mut hash = ContentHash::default();
+    let mut index = 0usize;
+    while let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? {
+        let source_name = field
+            .file_name()
+            .or(field.name())
+            .unwrap_or("upload")
+            .to_string();
+        budget.add_file()?;
+        hash.file(&source_name);
+
+        if let Some(kind) = ArchiveKind::detect(&source_name) {
+            let archive = Builder::new()
+                .prefix(UPLOAD_PREFIX)
+                .tempfile_in(state.root.as_path())
+                .map_err(internal_server_error)?;
+            write_field(&mut field, archive.path(), &mut budget, &mut hash).await?;
+            let total = extract_archive(
+                kind,
+                archive.path().to_path_buf(),
+                path.clone(),
+                state.archive_limits,
+                extracted,
+            )
+            .await
+            .map_err(archive_error_response)?;
+            tracing::debug!(
+                "extract archive '{source_name}' ({} bytes)",
+                total.bytes - extracted.bytes
+            );
+            extracted = total;
+            index += 1;
+            continue;
+        }
+
+        let relative_path = sanitize_upload_path(&source_name);
+        if let Some(conflict) = conflicting_path(&path, &relative_path) {
+            return Err(archive_error_response(
+                logic::Error::ConflictingUploadPath { path: conflict },
+            ));
+        }
+        let file_path = unique_upload_path(&path, &relative_path, index);
+
+        if let Some(parent) = file_path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(internal_server_error)?;
+        }
+        extracted.bytes += write_field(&mut field, &file_path, &mut budget, &mut hash).await?;
+        extracted.entries += 1;
+        state
+            .archive_limits
+            .check(extracted)
+            .map_err(archive_error_response)?;
+
+        tracing::debug!("write file '{}'", file_path.display());
+        index += 1;
+    }
+
+    let id = hash.id();
+    if let Some(stored) = find_stored(&state, &id).await {
+        close(tempdir).await;
+        return upload_response(StatusCode::OK, &stored, query.format.as_deref());
+    }
+
+    let scc_output = count(&path).await?;
+    close(tempdir).await;
+
+    let stored = state
+        .store
+        .insert(&id, None, &scc_output, None)
+        .await
+        .map_err(internal_server_error)?;
+    upload_response(StatusCode::CREATED, &stored, query.format.as_deref())
+}
+
+/// Counts the lines of a branch of an uploaded git bundle or bare repository archive.
+#[utoipa::path(
+    post,
+    path = "/upload/bundle",
+    tag = "uploads",
+    params(UploadQuery),
+    request_body(content_type = "multipart/form-data", description = "One `.bundle` file or an archive of a bare repository"),
+    responses(
+        (status = 200, description = "Result of an identical earlier upload", content((String = "text/plain"), (UploadResult = "application/json")), headers(("Location" = String), ("Expires" = String))),
+        (status = 201, description = "Result of the upload under a private id", content((String = "text/plain"), (UploadResult = "application/json")), headers(("Location" = String), ("Expires" = String))),
+        (status = 400, description = "Not exactly one bundle or bare repository, or git can't read it", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Branch not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 413, description = "Upload or checkout too large", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 429, description = "Too many uploads in progress", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 503, description = "The service is shutting down", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 504, description = "git didn't finish in time", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn upload_bundle(
+    Query(query): Query<UploadQuery>,
+    State(state): State<UploadState>,
+    api_key: Option<Extension<ApiKey>>,
+    mut multipart: Multipart,
+) -> Result<Response<Body>, UploadError> {
+    let _permit = acquire_permit(&state)?;
+    let started = Instant::now();
+    let deadline = started + state.cloner.limits().timeout;
+    let tempdir = create_tempdir(&state).await?;
+    let path = tempdir.path().to_path_buf();
+
+    let mut budget = Budget::new(state.limits);
+    let mut hash = ContentHash::default();
+    let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? else {
+        return Err(bad_request(
+            "Upload a git bundle or an archive of a bare repository",
+        ));
+    };
+    let source_name = field
+        .file_name()
+        .or(field.name())
+        .unwrap_or("upload")
+        .to_string();
+    budget.add_file()?;
+    // The file name doesn't change the analysed code, only the content and branch do
+    hash.file("repository");
+
+    let source = if source_name.to_ascii_lowercase().ends_with(".bundle") {
+        let bundle = path.join("source.bundle");
+        write_field(&mut field, &bundle, &mut budget, &mut hash).await?;
+        bundle
+    } else if let Some(kind) = ArchiveKind::detect(&source_name) {
+        let archive = path.join("source.archive");
+        let extracted = path.join("source");
+        write_field(&mut field, &archive, &mut budget, &mut hash).await?;
+        extract_archive(
+            kind,
+            archive,
+            extracted.clone(),
+            state.archive_limits,
+            Extracted::default(),
+        )
+        .await
+        .map_err(archive_error_response)?;
+        let repository = find_bare_repository(&extracted)
+            .ok_or_else(|| bad_request("Archive does not contain a bare git repository"))?;
+        sanitize_bare_repository(&repository).map_err(archive_error_response)?;
+        repository
+    } else {
+        return Err(bad_request(format!(
+            "'{source_name}' is neither a git bundle nor an archive"
+        )));
+    };
+    drop(field);
+    if multipart
+        .next_field()
+        .await
+        .map_err(multipart_error)?
+        .is_some()
+    {
+        return Err(bad_request("Upload exactly one git bundle or archive"));
+    }
+
+    let branches = source_branches(&state.cloner, &source, deadline)
+        .await
+        .map_err(|error| clone_error_response(error.timed_out_since(started)))?;
+    let branch = resolve_branch(&branches, query.branch.as_deref()).map_err(|error| {
+        Problem::new(StatusCode::NOT_FOUND, "branch_not_found", error.to_string())
+    })?;
+    hash.file("branch");
+    hash.update(branch.name.as_bytes());
+    // Identical uploads of another key don't reveal the id
+    hash.file("owner");
+    if let Some(Extension(api_key)) = &api_key {
+        hash.update(api_key.id.to_string().as_bytes());
+    }
+    let content_hash = hash.id();
+    match state.store.find_private(&content_hash).await {
+        Ok(Some(stored)) => {
+            close(tempdir).await;
+            return upload_response(StatusCode::OK, &stored, query.format.as_deref());
+        }
+        Ok(None) => {}
+        Err(error) => tracing::warn!("Can't look up private upload: {error}"),
+    }
+
+    let checkout = path.join("checkout");
+    clone_local(&state.cloner, &source, &branch.name, &checkout, deadline)
+        .await
+        .map_err(|error| clone_error_response(error.timed_out_since(started)))?;
+    let size = dir_size(&checkout).await.map_err(internal_server_error)?;
+    if size > state.archive_limits.max_bytes {
+        return Err(payload_too_large(format!(
+            "Checkout of '{}' is larger than {} bytes",
+            branch.name, state.archive_limits.max_bytes
+        )));
+    }
+    let scc_output = count(&checkout).await?;
+    close(tempdir).await;
+
+    let stored = state
+        .store
+        .insert(
+            &private_id(),
+            Some(&content_hash),
+            &scc_output,
+            Some(&branch),
+        )
+        .await
+        .map_err(internal_server_error)?;
+    upload_response(StatusCode::CREATED, &stored, query.format.as_deref())
+}
+
+fn acquire_permit(state: &UploadState) -> Result<OwnedSemaphorePermit, UploadError> {
+    state.permits.clone().try_acquire_owned().map_err(|_| {
+        Problem::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "too_many_uploads",
+            "Too many uploads in progress, try again later",
+        )
+    })
+}
+
+async fn create_tempdir(state: &UploadState) -> Result<TempDir, UploadError> {
+    fs::create_dir_all(state.root.as_path())
+        .await
+        .map_err(internal_server_error)?;
+    Builder::new()
+        .prefix(UPLOAD_PREFIX)
+        .tempdir_in(state.root.as_path())
+        .map_err(internal_server_error)
+}
+
+/// Previous result of an identical upload, analysing again if the lookup fails.
+async fn find_stored(state: &UploadState, id: &str) -> Option<StoredUpload> {
+    match state.store.get(id).await {
+        Ok(Some(stored)) if !stored.is_expired() => {
+            tracing::debug!("upload {id} is already analysed");
+            Some(stored)
+        }
+        Ok(_) => None,
+        Err(error) => {
+            tracing::warn!("Can't look up upload {id}: {error}");
+            None
+        }
+    }
+}
+
+async fn count(path: &Path) -> Result<Vec<u8>, UploadError> {
+    let path = path
+        .to_str()
+        .ok_or_else(|| internal_server_error("temporary path is not valid UTF-8"))?;
+    count_line_of_code(path, "")
+        .await
+        .map_err(|e| internal_server_error(e.to_string()))
+}
+
+/// Stored result of an upload, JSON with `format=json` or `Accept: application/json`.
+#[utoipa::path(
+    get,
+    path = "/upload/{id}",
+    tag = "uploads",
+    params(("id" = String, Path, description = "Id from the `Location` of the upload"), UploadQuery),
+    responses(
+        (status = 200, description = "The result", content((String = "text/plain"), (UploadResult = "application/json")), headers(("Expires" = String))),
+        (status = 404, description = "Unknown id", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 410, description = "The upload has expired", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+async fn upload_result(
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<UploadQuery>,
+    State(state): State<UploadState>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, UploadError> {
+    if !is_valid_id(&id) {
+        return Err(upload_not_found(&id));
+    }
+    let stored = match state.store.get(&id).await.map_err(internal_server_error)? {
+        Some(stored) if stored.is_expired() => {
+            return Err(Problem::new(
+                StatusCode::GONE,
+                "upload_expired",
+                format!("Upload {id} has expired"),
+            ))
+        }
+        Some(stored) => stored,
+        None => return Err(upload_not_found(&id)),
+    };
+
+    let wants_json = headers
+        .get(ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("application/json"));
+    let format = query.format.as_deref().or(wants_json.then_some("json"));
+    upload_response(StatusCode::OK, &stored, format)
+}
+
+fn upload_response(
+    status: StatusCode,
+    stored: &StoredUpload,
+    format: Option<&str>,
+) -> Result<Response<Body>, UploadError> {
+    let location = format!("/upload/{}", stored.id);
+    let builder = Response::builder()
+        .status(status)
+        .header(LOCATION, &location)
+        .header(
+            EXPIRES,
+            stored
+                .expires
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        );
+
+    match format {
+        Some("json") => {
+            let result = UploadResult {
+                id: stored.id.clone(),
+                url: format!("{}{location}", public_url()),
+                expires: stored.expires,
+                branch: stored.branch.clone(),
+                summary: Summary::parse(&stored.scc_output),
+            };
+            let json = serde_json::to_vec(&result).map_err(internal_server_error)?;
+            builder
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(json))
+                .map_err(internal_server_error)
+        }
+        _ => builder
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from(stored.scc_output.clone()))
+            .map_err(internal_server_error),
+    }
+}
+
+/// Streams the field to `path` chunk by chunk, enforcing the byte budget. Returns the
+/// number of written bytes.
+async fn write_field(
+    field: &mut Field<'_>,
+    path: &Path,
+    budget: &mut Budget,
+    hash: &mut ContentHash,
+) -> Result<u64, UploadError> {
+    let mut file = fs::File::create(path)
+        .await
+        .map_err(internal_server_error)?;
+    let mut written = 0;
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        budget.add_bytes(chunk.len())?;
+        hash.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .map_err(internal_server_error)?;
+        written += chunk.len() as u64;
+    }
+    file.flush().await.map_err(internal_server_error)?;
+    Ok(written)
+}
+
+/// Removes the directory off the async runtime and reports failures.
+async fn close(tempdir: TempDir) {
+    let path = tempdir.path().display().to_string();
+    match tokio::task::spawn_blocking(move || tempdir.close()).await {
+        Ok(Ok(())) => tracing::debug!("Remove {path}"),
+        Ok(Err(error)) => tracing::warn!("Can't remove dir {path}: {error}"),
+        Err(error) => tracing::warn!("Can't remove dir {path}: {error}"),
+    }
+}
+
+fn archive_error_response(error: logic::Error) -> UploadError {
+    match error {
+        logic::Error::ArchiveTooLarge { .. } | logic::Error::TooManyArchiveEntries { .. } => {
+            payload_too_large(error)
+        }
+        logic::Error::UnsafeArchiveEntry { .. }
+        | logic::Error::ConflictingUploadPath { .. }
+        | logic::Error::Archive { .. } => bad_request(error),
+        error => internal_server_error(error),
+    }
+}
+
+fn clone_error_response(error: logic::Error) -> UploadError {
+    match error {
+        logic::Error::CloneTooLarge { .. } => payload_too_large(error),
+        logic::Error::CloneTimeout { .. } => Problem::from(&error),
+        logic::Error::CloneCancelled { .. } => Problem::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "service_unavailable",
+            error.to_string(),
+        ),
+        error => bad_request(error),
+    }
+}
+
+fn sanitize_upload_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+
+    for component in Path::new(name).components() {
+        if let Component::Normal(value) = component {
+            let Some(value) = value.to_str() else {
+                continue;
+            };
+
+            let sanitized = value
+                .chars()
+                .map(|ch| if ch.is_control() { '_' } else { ch })
+                .collect::<String>();
+
+            if !sanitized.is_empty() {
+                path.push(sanitized);
+            }
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        path.push("upload");
+    }
+
+    path
+}
+
+fn unique_upload_path(root: &Path, relative_path: &Path, index: usize) -> PathBuf {
+    let candidate = root.join(relative_path);
+
+    if !candidate.exists() {
+        return candidate;
+    }
+
+    let parent = candidate
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| root.to_path_buf());
+    let stem = candidate
+        .file_stem()
+        .and_then(|value| value.to_str())
+        .filter(|value| !value.is_empty())
+        .unwrap_or("upload");
+    let extension = candidate.extension().and_then(|value| value.to_str());
+    let file_name = match extension {
+        Some(extension) if !extension.is_empty() => format!("{index:04}_{stem}.{extension}"),
+        _ => format!("{index:04}_{stem}"),
+    };
+
+    parent.join(file_name)
+}
+
+fn upload_not_found(id: &str) -> UploadError {
+    Problem::new(
+        StatusCode::NOT_FOUND,
+        "upload_not_found",
+        format!("Upload {id} not found"),
+    )
+}
+
+fn multipart_error(error: axum::extract::multipart::MultipartError) -> UploadError {
+    Problem::new(error.status(), "invalid_upload", error.body_text())
+}
+
+fn payload_too_large(error: impl std::fmt::Display) -> UploadError {
+    Problem::new(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "upload_too_large",
+        error.to_string(),
+    )
+}
+
+fn bad_request(error: impl std::fmt::Display) -> UploadError {
+    Problem::new(StatusCode::BAD_REQUEST, "invalid_upload", error.to_string())
+}
+
+fn internal_server_error(error: impl std::fmt::Display) -> UploadError {
+    Problem::internal(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upload_route, UploadLimits, UploadState};
+    use crate::logic::{archive::ArchiveLimits, upload::UploadStore};
+    use crate::problem::PROBLEM_JSON;
+    use axum::{body::Body, Router};
+    use bb8_postgres::PostgresConnectionManager;
+    use hyper::{header::CONTENT_TYPE, Request, StatusCode};
+    use std::path::Path;
+    use tower::ServiceExt;
+
+    const BOUNDARY: &str = "X-CLOC-BOUNDARY";
+
+    fn multipart(files: &[(&str, &[u8])]) -> Request<Body> {
+        let mut body = Vec::new();
+        for (name, data) in files {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{name}\"\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        Request::post("/post")
+            .header(
+                "Content-Type",
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn app(root: &Path, limits: UploadLimits) -> Router {
+        // Rejected uploads never reach the database
+        let manager =
+            PostgresConnectionManager::new_from_stringlike("host=localhost", tokio_postgres::NoTls)
+                .unwrap();
+        let store = UploadStore::from_env(bb8::Pool::builder().build_unchecked(manager));
+        let state = UploadState::new(
+            root,
+            limits,
+            ArchiveLimits::from_env(),
+            store,
+            Default::default(),
+        );
+        Router::new().route("/post", upload_route(state))
+    }
+
+    #[tokio::test]
+    async fn oversized_uploads_are_rejected_and_removed() {
+        let root = tempfile::tempdir().unwrap();
+        let limits = UploadLimits {
+            max_bytes: 16,
+            max_files: 2,
+            concurrency: 1,
+        };
+        let app = app(root.path(), limits);
+
+        let response = app
+            .clone()
+            .oneshot(multipart(&[("a.rs", &[b'a'; 32])]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+
+        let files: [(&str, &[u8]); 3] = [("a.rs", b"1"), ("b.rs", b"2"), ("c.rs", b"3")];
+        let response = app.oneshot(multipart(&files)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn conflicting_paths_are_rejected() {
+        let root = tempfile::tempdir().unwrap();
+        let limits = UploadLimits {
+            max_bytes: 1024 * 1024,
+            max_files: 4,
+            concurrency: 1,
+        };
+        let app = app(root.path(), limits);
+
+        // A file where a later one needs a directory
+        let files: [(&str, &[u8]); 2] = [("src", b"1"), ("src/main.rs", b"2")];
+        let response = app.clone().oneshot(multipart(&files)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[CONTENT_TYPE], PROBLEM_JSON);
+
+        // The same path twice in an archive
+        let mut builder = tar::Builder::new(Vec::new());
+        for data in [b"1", b"2"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(1);
+            header.set_mode(0o644);
+            builder
+                .append_data(&mut header, "main.rs", &data[..])
+                .unwrap();
+        }
+        let archive = builder.into_inner().unwrap();
+        let response = app
+            .oneshot(multipart(&[("code.tar", &archive)]))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        assert_eq!(std::fs::read_dir(root.path()).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,315 @@
+use crate::{
+    logic::{
+        info::{to_url, CloneOptions, Requester},
+        repository::RepositoryProvider,
+    },
+    openapi::Routes,
+    problem::{Problem, ProblemDetails},
+};
+use axum::{
+    body::Body,
+    extract::State,
+    response::{IntoResponse, Response},
+    routing::post,
+};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use hyper::{HeaderMap, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Sha256;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct WebhookState {
+    pub provider: RepositoryProvider,
+    /// Shared secret configured on the forge side, webhooks are rejected without it.
+    pub secret: Option<Arc<String>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sender {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushRepository {
+    clone_url: Option<String>,
+    git_http_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    reference: String,
+    after: Option<String>,
+    #[serde(default)]
+    deleted: bool,
+    repository: PushRepository,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Push {
+    host: String,
+    owner: String,
+    repository_name: String,
+    branch: String,
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+// Gitea and Forgejo also send X-GitHub-Event for compatibility, so they are checked first
+fn detect_sender(headers: &HeaderMap) -> Option<(Sender, &str)> {
+    if let Some(event) = header(headers, "X-Gitea-Event").or(header(headers, "X-Forgejo-Event")) {
+        Some((Sender::Gitea, event))
+    } else if let Some(event) = header(headers, "X-Gitlab-Event") {
+        Some((Sender::GitLab, event))
+    } else {
+        header(headers, "X-GitHub-Event").map(|event| (Sender::GitHub, event))
+    }
+}
+
+fn is_push_event(sender: Sender, event: &str) -> bool {
+    match sender {
+        Sender::GitHub | Sender::Gitea => event == "push",
+        Sender::GitLab => event == "Push Hook",
+    }
+}
+
+fn verify_hmac(secret: &str, body: &[u8], signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature.trim()) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right)
+            .fold(0u8, |acc, (left, right)| acc | (left ^ right))
+            == 0
+}
+
+fn verify_signature(sender: Sender, headers: &HeaderMap, secret: &str, body: &[u8]) -> bool {
+    match sender {
+        Sender::GitHub => header(headers, "X-Hub-Signature-256")
+            .and_then(|value| value.strip_prefix("sha256="))
+            .is_some_and(|signature| verify_hmac(secret, body, signature)),
+        Sender::Gitea => header(headers, "X-Gitea-Signature")
+            .or(header(headers, "X-Forgejo-Signature"))
+            .is_some_and(|signature| verify_hmac(secret, body, signature)),
+        // GitLab does not sign payloads, it sends the secret token as is
+        Sender::GitLab => header(headers, "X-Gitlab-Token")
+            .is_some_and(|token| constant_time_eq(token.as_bytes(), secret.as_bytes())),
+    }
+}
+
+fn parse_push(body: &[u8]) -> Result<Option<Push>, String> {
+    let payload: PushPayload = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+
+    let is_deleted = payload.deleted
+        || payload
+            .after
+            .as_deref()
+            .is_some_and(|after| !after.is_empty() && after.chars().all(|ch| ch == '0'));
+    let Some(branch) = payload.reference.strip_prefix("refs/heads/") else {
+        return Ok(None);
+    };
+    if is_deleted {
+        return Ok(None);
+    }
+
+    let url = payload
+        .repository
+        .clone_url
+        .or(payload.repository.git_http_url)
+        .ok_or("Repository clone url is missing")?;
+    let path = url
+        .strip_prefix("https://")
+        .or(url.strip_prefix("http://"))
+        .ok_or_else(|| format!("Unsupported clone url {url}"))?;
+    let (host, path) = path
+        .split_once('/')
+        .ok_or_else(|| format!("Can't extract host from {url}"))?;
+    let (owner, repository_name) = path
+        .trim_end_matches('/')
+        .rsplit_once('/')
+        .ok_or_else(|| format!("Can't extract owner and repository from {url}"))?;
+
+    let repository_name = if host != "git.sr.ht" && !repository_name.ends_with(".git") {
+        format!("{repository_name}.git")
+    } else {
+        repository_name.to_string()
+    };
+
+    Ok(Some(Push {
+        host: host.to_string(),
+        owner: owner.to_string(),
+        repository_name,
+        branch: branch.to_string(),
+    }))
+}
+
+/// Events that don't refresh anything are acknowledged, so the forge doesn't retry them.
+fn ignored(reason: impl Into<String>) -> Response<Body> {
+    (
+        StatusCode::OK,
+        axum::Json(json!({ "status": "ignored", "reason": reason.into() })),
+    )
+        .into_response()
+}
+
+pub fn routes() -> Routes<WebhookState> {
+    Routes::new().route("/webhook", post(webhook))
+}
+
+/// Push events of GitHub, GitLab and Gitea, signed with `WEBHOOK_SECRET`. Forgets the cached
+/// branches of the repository and analyses the pushed branch again.
+#[utoipa::path(
+    post,
+    path = "/webhook",
+    tag = "webhooks",
+    request_body(content_type = "application/json", description = "Push event of the forge"),
+    responses(
+        (status = 202, description = "Analysis of the pushed branch queued", content_type = "application/json"),
+        (status = 200, description = "Event acknowledged but ignored, with the reason", content_type = "application/json"),
+        (status = 400, description = "Unknown sender or invalid event", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 401, description = "Invalid signature", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 403, description = "`WEBHOOK_SECRET` is not set", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 404, description = "Repository or branch not found", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 502, description = "The forge failed or can't be reached", body = ProblemDetails, content_type = "application/problem+json"),
+        (status = 504, description = "The forge didn't answer in time", body = ProblemDetails, content_type = "application/problem+json"),
+    )
+)]
+pub async fn webhook(
+    State(state): State<WebhookState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let Some(secret) = state.secret.as_deref() else {
+        return Problem::new(
+            StatusCode::FORBIDDEN,
+            "webhooks_disabled",
+            "Webhooks are disabled",
+        )
+        .into_response();
+    };
+    let Some((sender, event)) = detect_sender(&headers) else {
+        return Problem::new(
+            StatusCode::BAD_REQUEST,
+            "unknown_sender",
+            "Unknown webhook sender",
+        )
+        .into_response();
+    };
+    if !verify_signature(sender, &headers, secret, &body) {
+        tracing::warn!("Rejected {sender:?} webhook with invalid signature");
+        return Problem::new(
+            StatusCode::UNAUTHORIZED,
+            "invalid_signature",
+            "Invalid webhook signature",
+        )
+        .into_response();
+    }
+    if !is_push_event(sender, event) {
+        return ignored(format!("Event '{event}' ignored"));
+    }
+
+    let push = match parse_push(&body) {
+        Ok(Some(push)) => push,
+        Ok(None) => return ignored("Not a branch update"),
+        Err(error) => {
+            return Problem::new(StatusCode::BAD_REQUEST, "invalid_request", error).into_response()
+        }
+    };
+    tracing::info!("webhook() {sender:?} push to {push:?}");
+
+    let url = to_url(&push.host, &push.owner, &push.repository_name);
+    state.provider.git_provider.invalidate(&url).await;
+
+    let result = state
+        .provider
+        .request_info_for(
+            push.host,
+            push.owner,
+            push.repository_name,
+            Some(push.branch),
+            CloneOptions::default(),
+            Requester::service(),
+        )
+        .await;
+
+    match result {
+        Ok((unique_name, status)) => (
+            StatusCode::ACCEPTED,
+            axum::Json(json!({ "unique_name": unique_name, "status": status.to_string() })),
+        )
+            .into_response(),
+        Err(error) => Problem::from(&error).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_push, verify_signature, Push, Sender};
+    use hmac::{Hmac, Mac};
+    use hyper::{header::HeaderValue, HeaderMap};
+    use sha2::Sha256;
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn verifies_github_and_gitlab_signatures() {
+        let body = br#"{"ref":"refs/heads/main"}"#;
+        let mut headers = HeaderMap::new();
+        let signature = format!("sha256={}", sign("secret", body));
+        headers.insert(
+            "X-Hub-Signature-256",
+            HeaderValue::from_str(&signature).unwrap(),
+        );
+        headers.insert("X-Gitlab-Token", HeaderValue::from_static("secret"));
+
+        assert!(verify_signature(Sender::GitHub, &headers, "secret", body));
+        assert!(!verify_signature(Sender::GitHub, &headers, "other", body));
+        assert!(verify_signature(Sender::GitLab, &headers, "secret", body));
+        assert!(!verify_signature(Sender::Gitea, &headers, "secret", body));
+    }
+
+    #[test]
+    fn parses_branch_pushes_only() {
+        let push = br#"{"ref":"refs/heads/feature/x","after":"abc","repository":{"clone_url":"https://github.com/acme/tool.git"}}"#;
+        let gitlab = br#"{"ref":"refs/heads/main","repository":{"git_http_url":"https://gitlab.com/acme/tool"}}"#;
+        let tag = br#"{"ref":"refs/tags/v1","repository":{"clone_url":"https://github.com/acme/tool.git"}}"#;
+        let deleted = br#"{"ref":"refs/heads/main","after":"0000000000","repository":{"clone_url":"https://github.com/acme/tool.git"}}"#;
+
+        assert_eq!(
+            parse_push(push).unwrap(),
+            Some(Push {
+                host: "github.com".to_string(),
+                owner: "acme".to_string(),
+                repository_name: "tool.git".to_string(),
+                branch: "feature/x".to_string(),
+            })
+        );
+        assert_eq!(
+            parse_push(gitlab).unwrap().unwrap().repository_name,
+            "tool.git"
+        );
+        assert_eq!(parse_push(tag).unwrap(), None);
+        assert_eq!(parse_push(deleted).unwrap(), None);
+    }
+}
@@ -1,49 +1,93 @@
-use crate::logic::{
-    info::{to_unique_name, to_url, Status},
-    repository::RepositoryProvider,
+use crate::{
+    handlers::{extract_user_agent, requester, BRANCH_PATHS, REPOSITORY_PATHS},
+    logic::{
+        info::{CloneOptions, Status},
+        repository::RepositoryProvider,
+    },
+    openapi::{BranchPath, CloneQuery, LookupProblems, RepositoryPath, Routes},
+    problem::Problem,
 };
 use axum::{
+    body::Body,
     extract::{
         ws::{Message, WebSocket},
-        Path, State, WebSocketUpgrade,
+        Path, Query, State, WebSocketUpgrade,
     },
-    http::StatusCode,
     response::{IntoResponse, Response},
+    routing::get,
 };
+use hyper::Request;
+
+pub fn routes() -> Routes<RepositoryProvider> {
+    let routes = REPOSITORY_PATHS.iter().fold(Routes::new(), |routes, path| {
+        routes.route(path, get(handler_ws))
+    });
+    BRANCH_PATHS.iter().fold(routes, |routes, path| {
+        routes.route(path, get(handler_ws_with_branch))
+    })
+}
 
+/// Progress of the analysis of the default branch.
+#[utoipa::path(
+    get,
+    path = "/ws/{host}/{owner}/{repo}",
+    tag = "analyses",
+    params(RepositoryPath, CloneQuery),
+    responses(
+        (status = 101, description = "Switches to a WebSocket answering every message with the `Status` as JSON, `Ready` while nothing was requested with the credentials of the handshake. It's closed after `Done`"),
+        LookupProblems,
+    )
+)]
 pub async fn handler_ws(
     ws: WebSocketUpgrade,
     Path((host, owner, mut repository_name)): Path<(String, String, String)>,
+    Query(options): Query<CloneOptions>,
     State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
 ) -> Response {
     if host != "git.sr.ht" && !repository_name.ends_with(".git") {
         repository_name = format!("{repository_name}.git");
     }
+    let requester = requester(extract_user_agent(&request), &request);
 
-    let url = to_url(&host, &owner, &repository_name);
-    let branch = provider.git_provider.default_branch(&url).await;
+    let branch = provider
+        .default_branch_remote(&host, &owner, &repository_name, &requester)
+        .await;
 
     match branch {
-        Ok(branch) => ws
-            .on_upgrade(move |socket| {
-                handle_socket(
-                    host,
-                    owner,
-                    repository_name,
-                    branch,
-                    socket,
-                    State(provider),
-                )
-            })
-            .into_response(),
-        Err(e) => (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+        Ok(branch) => {
+            let unique_name = provider.unique_name_for(
+                &host,
+                &owner,
+                &repository_name,
+                &branch,
+                &options,
+                &requester,
+            );
+            ws.on_upgrade(move |socket| handle_socket(unique_name, socket, State(provider)))
+                .into_response()
+        }
+        Err(e) => Problem::from(&e).into_response(),
     }
 }
 
+/// Progress of the analysis of a branch.
+#[utoipa::path(
+    get,
+    path = "/ws/{host}/{owner}/{repo}/tree/{branch}",
+    tag = "analyses",
+    params(BranchPath, CloneQuery),
+    responses(
+        (status = 101, description = "Switches to a WebSocket answering every message with the `Status` as JSON, `Ready` while nothing was requested with the credentials of the handshake. It's closed after `Done`"),
+        LookupProblems,
+    )
+)]
 pub async fn handler_ws_with_branch(
     ws: WebSocketUpgrade,
     Path((host, owner, mut repository_name, branch)): Path<(String, String, String, String)>,
+    Query(options): Query<CloneOptions>,
     State(provider): State<RepositoryProvider>,
+    request: Request<Body>,
 ) -> Response {
     if host != "git.sr.ht" && !repository_name.ends_with(".git") {
         repository_name = format!("{repository_name}.git");
@@ -53,32 +97,25 @@ pub async fn handler_ws_with_branch(
     // } else {
     //     &branch
     // };
-    let branch = branch
-        .trim_start_matches('/')
-        .trim_end_matches('/')
-        .to_string();
+    let branch = branch.trim_start_matches('/').trim_end_matches('/');
+    let requester = requester(extract_user_agent(&request), &request);
+    let unique_name = provider.unique_name_for(
+        &host,
+        &owner,
+        &repository_name,
+        branch,
+        &options,
+        &requester,
+    );
 
-    ws.on_upgrade(move |socket| {
-        handle_socket(
-            host,
-            owner,
-            repository_name,
-            branch.to_string(),
-            socket,
-            State(provider),
-        )
-    })
+    ws.on_upgrade(move |socket| handle_socket(unique_name, socket, State(provider)))
 }
 
 async fn handle_socket(
-    host: String,
-    owner: String,
-    repository_name: String,
-    branch: String,
+    unique_name: String,
     mut socket: WebSocket,
     provider: State<RepositoryProvider>,
 ) {
-    let unique_name = to_unique_name(&host, &owner, &repository_name, &branch);
     tracing::info!("Connect websocket {}", unique_name);
 
     while let Some(msg) = socket.recv().await {
@@ -98,12 +135,114 @@ async fn handle_socket(
             // client disconnected
             match socket.close().await {
                 Ok(()) => tracing::debug!(
-                    "Can't receive message {}. Connection '{repository_name}' closed",
-                    repository_name
+                    "Can't receive message {}. Connection '{unique_name}' closed",
+                    unique_name
                 ),
-                Err(e) => tracing::warn!("{} {repository_name}", e.to_string()),
+                Err(e) => tracing::warn!("{} {unique_name}", e.to_string()),
             }
             return;
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::routes;
+    use crate::{
+        analysis::{self, Analyses},
+        logic::{
+            credentials::Credential,
+            forge::tests::spawn_api,
+            info::{to_private_name, Status},
+            repository::tests::{provider, set_status},
+        },
+        openapi::{Routes, API_PREFIX, WEBSOCKET_PREFIX},
+    };
+    use cloc_api::{analysis::AnalysisRequest, Client, Error};
+    use futures_util::StreamExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn client_follows_progress_with_its_credentials() {
+        let provider = provider();
+        let credential = Credential::new(None, "secret".to_string());
+        set_status(
+            &provider,
+            &to_private_name(&credential, "github.com/org/repo.git/main"),
+            Status::Done(b"Total 1".to_vec()),
+        );
+        set_status(
+            &provider,
+            "github.com/org/slow.git/main",
+            Status::InProgress("Cloning".to_string()),
+        );
+        let router = Routes::new()
+            .nest(
+                API_PREFIX,
+                analysis::routes().with_state(Analyses::new(provider.clone())),
+            )
+            .nest(WEBSOCKET_PREFIX, routes().with_state(provider))
+            .into_router();
+        let client = Client::new(spawn_api(router).await).with_interval(Duration::from_millis(10));
+        let request = AnalysisRequest {
+            host: "github.com".to_string(),
+            owner: "org".to_string(),
+            repository: "repo".to_string(),
+            branch: Some("main".to_string()),
+            paths: Vec::new(),
+            submodules: false,
+            callback: None,
+        };
+
+        // Only the token the analysis was requested with sees its progress
+        let statuses: Vec<_> = client
+            .clone()
+            .with_git_token(None, "secret")
+            .stream_progress(&request)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(statuses[..], [Ok(Status::Done(_))]));
+        let statuses: Vec<_> = client
+            .clone()
+            .with_progress_timeout(Duration::from_millis(100))
+            .stream_progress(&request)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(statuses.first(), Some(Ok(Status::Ready))));
+        assert!(matches!(
+            statuses.last(),
+            Some(Err(Error::ProgressTimeout { .. }))
+        ));
+
+        let slow = AnalysisRequest {
+            repository: "slow".to_string(),
+            ..request.clone()
+        };
+        let statuses: Vec<_> = client
+            .clone()
+            .with_progress_timeout(Duration::from_millis(100))
+            .stream_progress(&slow)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(statuses.first(), Some(Ok(Status::InProgress(_)))));
+        assert!(matches!(
+            statuses.last(),
+            Some(Err(Error::ProgressTimeout { .. }))
+        ));
+
+        let error = client.poll("missing").await.unwrap_err();
+        assert_eq!(error.code(), Some("analysis_not_found"));
+        let invalid = AnalysisRequest {
+            callback: Some("ftp://example.com".to_string()),
+            ..request
+        };
+        let error = client.request(&invalid).await.unwrap_err();
+        assert_eq!(error.code(), Some("invalid_callback"));
+    }
+}